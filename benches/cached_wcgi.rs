@@ -14,11 +14,12 @@ pub fn criterion_benchmark(c: &mut Criterion) {
         .build()
         .expect("Unable to start an async runtime");
     let options = Options {
-        wasm_cache: Some("examples/config.toml".into()),
+        wasm_cache: true,
         document_root: PathBuf::from("examples/"),
         cgi_bin: PathBuf::from(CurDir.as_os_str()),
         hostname: "localhost".to_string(),
         port: 0,
+        ..Default::default()
     };
     let server = runtime.block_on(Server::bind(options)).unwrap();
     let address = server.address().unwrap();
@@ -0,0 +1,58 @@
+use wasi::exports::http::incoming_handler::Guest;
+use wasi::http::types::{
+    Fields, IncomingBody, IncomingRequest, Method, OutgoingBody, OutgoingResponse, ResponseOutparam,
+};
+
+/// The largest chunk of the request body read at a time, which is also the most a single write may flush.
+const CHUNK_BYTES: u64 = 4096;
+
+struct Echo;
+
+impl Guest for Echo {
+    fn handle(request: IncomingRequest, response_out: ResponseOutparam) {
+        let headers = Fields::from_list(&[("content-type".to_string(), b"text/plain".to_vec())])
+            .expect("valid headers");
+        let response = OutgoingResponse::new(headers);
+        let body = response.body().expect("response body");
+
+        ResponseOutparam::set(response_out, Ok(response));
+
+        let output = body.write().expect("response body stream");
+        let method = match request.method() {
+            Method::Get => "GET".to_string(),
+            Method::Head => "HEAD".to_string(),
+            Method::Post => "POST".to_string(),
+            Method::Put => "PUT".to_string(),
+            Method::Delete => "DELETE".to_string(),
+            Method::Connect => "CONNECT".to_string(),
+            Method::Options => "OPTIONS".to_string(),
+            Method::Trace => "TRACE".to_string(),
+            Method::Patch => "PATCH".to_string(),
+            Method::Other(method) => method,
+        };
+        let line = format!(
+            "{method} {}\n",
+            request.path_with_query().unwrap_or_default()
+        );
+
+        output
+            .blocking_write_and_flush(line.as_bytes())
+            .expect("write request line");
+
+        let incoming = request.consume().expect("request body");
+        let input = incoming.stream().expect("request body stream");
+
+        while let Ok(chunk) = input.blocking_read(CHUNK_BYTES) {
+            output
+                .blocking_write_and_flush(&chunk)
+                .expect("write request body");
+        }
+
+        drop(input);
+        IncomingBody::finish(incoming);
+        drop(output);
+        OutgoingBody::finish(body, None).expect("finish response body");
+    }
+}
+
+wasi::http::proxy::export!(Echo);
@@ -7,9 +7,9 @@ fn main() -> io::Result<()> {
     let content_length =
         std::env::var("CONTENT_LENGTH").unwrap_or_else(|_| "text/html".to_string());
 
-    write!(output, "Content-Type: {}\n", content_type)?;
-    write!(output, "Content-Length: {}\n", content_length)?;
-    write!(output, "\n")?;
+    writeln!(output, "Content-Type: {}", content_type)?;
+    writeln!(output, "Content-Length: {}", content_length)?;
+    writeln!(output)?;
 
     io::copy(&mut stdin(), &mut output).map(|_| ())
 }
@@ -0,0 +1,4 @@
+#!/usr/bin/env sh
+
+echo "Something went wrong" >&2
+exit 1
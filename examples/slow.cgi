@@ -0,0 +1,7 @@
+#!/usr/bin/env sh
+
+sleep 1
+
+echo "Content-Type: text/plain"
+echo
+echo "Done"
@@ -0,0 +1,6 @@
+#!/usr/bin/env sh
+
+echo "Content-Type: text/plain"
+echo
+
+/usr/bin/env
@@ -3,6 +3,8 @@ use std::net::SocketAddr;
 pub struct ClientContext {
     remote_ip_address: String,
     remote_port: String,
+    tls_protocol: Option<String>,
+    tls_cipher: Option<String>,
 }
 
 impl ClientContext {
@@ -13,9 +15,18 @@ impl ClientContext {
         Self {
             remote_ip_address,
             remote_port,
+            tls_protocol: None,
+            tls_cipher: None,
         }
     }
 
+    /// Marks the client as connected over TLS with the negotiated protocol version and cipher suite.
+    pub fn with_tls(mut self, protocol: String, cipher: String) -> Self {
+        self.tls_protocol = Some(protocol);
+        self.tls_cipher = Some(cipher);
+        self
+    }
+
     pub fn remote_ip_address(&self) -> &str {
         self.remote_ip_address.as_str()
     }
@@ -23,4 +34,16 @@ impl ClientContext {
     pub fn remote_port(&self) -> &str {
         self.remote_port.as_str()
     }
+
+    pub fn is_secure(&self) -> bool {
+        self.tls_protocol.is_some()
+    }
+
+    pub fn tls_protocol(&self) -> Option<&str> {
+        self.tls_protocol.as_deref()
+    }
+
+    pub fn tls_cipher(&self) -> Option<&str> {
+        self.tls_cipher.as_deref()
+    }
 }
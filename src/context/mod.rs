@@ -3,5 +3,5 @@ mod request;
 mod server;
 
 pub use client::ClientContext;
-pub use request::RequestContext;
+pub use request::{protocol, IndexScript, RequestContext, RequestId};
 pub use server::{ScriptMapping, ServerContext};
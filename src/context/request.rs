@@ -1,11 +1,12 @@
 use crate::context::{ClientContext, ServerContext};
+use crate::script::Input;
 use crate::uri::decode_percent_encoded;
 use crate::variable::ToMetaVariable;
 use base64::Engine;
-use bytes::Bytes;
-use http::{HeaderValue, Request};
+use http::{HeaderValue, Request, Version};
 use std::collections::HashMap;
 use std::ffi::OsStr;
+use std::fmt;
 use std::io;
 use std::path::PathBuf;
 use std::sync::Arc;
@@ -15,26 +16,59 @@ pub struct RequestContext {
     variables: HashMap<String, String>,
     arguments: Vec<String>,
     script: Option<PathBuf>,
+    request_id: Option<RequestId>,
+    head: Request<()>,
+}
+
+/// A unique identifier for a request, used to correlate its log entries.
+/// Stored in the request's extensions, so local redirects keep the identifier of the original request.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RequestId(pub String);
+
+/// A script in the document root that is run as the index of a directory, instead of one in the CGI bin directory.
+/// Stored in the request's extensions by the router.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct IndexScript {
+    pub file: PathBuf,
+    pub script_name: String,
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
 }
 
 impl RequestContext {
     pub fn new(
         server: Arc<ServerContext>,
         client: Arc<ClientContext>,
-        request: &Request<Bytes>,
-    ) -> Self {
-        let (script, extra_path) = match server.script_filename(request.uri().path()) {
-            Some((script, extra_path)) => (Some(script), extra_path),
-            None => (None, request.uri().path()),
-        };
+        request: &Request<Input>,
+    ) -> io::Result<Self> {
+        let path = request.uri().path();
+        let mounted = server.script_mappings().by_prefix(path);
+        let (script, script_name, extra_path) =
+            match (request.extensions().get::<IndexScript>(), mounted) {
+                (Some(index), _) => (Some(index.file.clone()), index.script_name.clone(), ""),
+                // Scripts mounted at a path prefix are named after the prefix, like a directory.
+                (None, Some((prefix, _))) => {
+                    let script_name = prefix.trim_end_matches('/');
+                    (None, script_name.to_string(), &path[script_name.len()..])
+                }
+                (None, None) => match server.script_filename(path)? {
+                    Some((script, extra_path)) => {
+                        let script_name = script
+                            .strip_prefix(server.working_directory())
+                            .map(|s| format!("/cgi-bin/{}", s.display()))
+                            .unwrap_or_default();
+
+                        (Some(script), script_name, extra_path)
+                    }
+                    None => (None, String::new(), path),
+                },
+            };
 
         let mut variables = HashMap::with_capacity(32);
-
-        let script_name = script
-            .as_ref()
-            .and_then(|s| s.strip_prefix(server.working_directory()).ok())
-            .map(|s| format!("/cgi-bin/{}", s.display()))
-            .unwrap_or_else(String::new);
         let script_uri = format!(
             "{}://{}:{}{}{}?{}",
             server.scheme(),
@@ -50,7 +84,7 @@ impl RequestContext {
         variables.insert("GATEWAY_INTERFACE".to_string(), "CGI/1.1".to_string());
         variables.insert(
             "SERVER_PROTOCOL".to_string(),
-            format!("{:?}", request.version()),
+            protocol(request.version()).to_string(),
         );
         variables.insert("SCRIPT_URI".to_string(), script_uri);
         variables.insert("SCRIPT_NAME".to_string(), script_name);
@@ -64,38 +98,41 @@ impl RequestContext {
         variables.insert("REMOTE_PORT".to_string(), client.remote_port().to_string());
         variables.insert("REQUEST_METHOD".to_string(), request.method().to_string());
 
+        if client.is_secure() {
+            variables.insert("HTTPS".to_string(), "on".to_string());
+
+            if let Some(protocol) = client.tls_protocol() {
+                variables.insert("SSL_PROTOCOL".to_string(), protocol.to_string());
+            }
+
+            if let Some(cipher) = client.tls_cipher() {
+                variables.insert("SSL_CIPHER".to_string(), cipher.to_string());
+            }
+        }
+
         if let Some(query) = request.uri().query() {
             variables.insert("QUERY_STRING".to_string(), query.to_string());
         }
 
         if !extra_path.is_empty() {
-            match decode_percent_encoded(extra_path) {
-                Ok(path_info) => {
-                    variables.insert(
-                        "PATH_TRANSLATED".to_string(),
-                        server
-                            .translate_path(path_info.as_str())
-                            .display()
-                            .to_string(),
-                    );
-                    variables.insert("PATH_INFO".to_string(), path_info);
-                }
-                Err(path_info) => {
-                    variables.insert("PATH_INFO".to_string(), path_info.to_string());
-                    variables.insert(
-                        "PATH_TRANSLATED".to_string(),
-                        server.translate_path(path_info).display().to_string(),
-                    );
-                }
-            }
-        }
+            let path_info = decode_percent_encoded(extra_path)
+                .unwrap_or_else(|path_info| path_info.to_string());
 
-        if !request.body().is_empty() {
             variables.insert(
-                "CONTENT_LENGTH".to_string(),
-                request.body().len().to_string(),
+                "PATH_TRANSLATED".to_string(),
+                server.translate_path(&path_info)?.display().to_string(),
             );
+            variables.insert("PATH_INFO".to_string(), path_info);
+        }
+
+        let content_length = request.body().content_length();
 
+        // Bodies sent without a length (i.e., chunked) are only given a CONTENT_LENGTH once spooled.
+        if let Some(length) = content_length.filter(|length| *length > 0) {
+            variables.insert("CONTENT_LENGTH".to_string(), length.to_string());
+        }
+
+        if content_length != Some(0) {
             if let Some(value) = request
                 .headers()
                 .get(hyper::header::CONTENT_TYPE)
@@ -118,17 +155,38 @@ impl RequestContext {
             variables.insert(key, value);
         }
 
+        // HTTP/2 requests carry the host in the `:authority` pseudo-header instead of a `Host` header.
+        if let Some(authority) = request.uri().authority() {
+            variables
+                .entry("HTTP_HOST".to_string())
+                .or_insert_with(|| authority.to_string());
+        }
+
+        let mut head = Request::new(());
+
+        *head.method_mut() = request.method().clone();
+        *head.uri_mut() = request.uri().clone();
+        *head.version_mut() = request.version();
+        *head.headers_mut() = request.headers().clone();
+
         let arguments = Self::extract_arguments(request);
+        let request_id = request.extensions().get::<RequestId>().cloned();
 
-        Self {
+        if let Some(request_id) = &request_id {
+            variables.insert("UNIQUE_ID".to_string(), request_id.to_string());
+        }
+
+        Ok(Self {
             server,
             variables,
             arguments,
             script,
-        }
+            request_id,
+            head,
+        })
     }
 
-    fn extract_user(request: &Request<Bytes>) -> Option<(AuthType, String)> {
+    fn extract_user(request: &Request<Input>) -> Option<(AuthType, String)> {
         let header = request
             .headers()
             .get(hyper::header::AUTHORIZATION)?
@@ -155,7 +213,7 @@ impl RequestContext {
         }
     }
 
-    fn extract_arguments(request: &Request<Bytes>) -> Vec<String> {
+    fn extract_arguments(request: &Request<Input>) -> Vec<String> {
         let mut arguments = Vec::new();
         if request.method() == http::Method::GET || request.method() == http::Method::HEAD {
             if let Some(query) = request.uri().query() {
@@ -193,6 +251,29 @@ impl RequestContext {
         })
     }
 
+    pub fn set_content_length(&mut self, length: u64) {
+        if length > 0 {
+            self.variables
+                .insert("CONTENT_LENGTH".to_string(), length.to_string());
+        }
+    }
+
+    pub fn request_id(&self) -> Option<&RequestId> {
+        self.request_id.as_ref()
+    }
+
+    pub fn script_name(&self) -> &str {
+        self.variables
+            .get("SCRIPT_NAME")
+            .map(String::as_str)
+            .unwrap_or_default()
+    }
+
+    /// The method, URI, version and headers of the request, for scripts that handle HTTP requests directly.
+    pub fn request(&self) -> &Request<()> {
+        &self.head
+    }
+
     pub fn arguments(&self) -> impl Iterator<Item = &str> {
         self.arguments.iter().map(String::as_str)
     }
@@ -202,6 +283,17 @@ impl RequestContext {
     }
 }
 
+/// The name and version of the protocol the request was received with, as defined by RFC 3875 (e.g., `HTTP/1.1`).
+pub fn protocol(version: Version) -> &'static str {
+    match version {
+        Version::HTTP_09 => "HTTP/0.9",
+        Version::HTTP_10 => "HTTP/1.0",
+        Version::HTTP_2 => "HTTP/2.0",
+        Version::HTTP_3 => "HTTP/3.0",
+        _ => "HTTP/1.1",
+    }
+}
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 enum AuthType {
     Basic,
@@ -243,4 +335,11 @@ mod tests {
             Err(b"Other".as_slice())
         );
     }
+
+    #[test]
+    fn server_protocol() {
+        assert_eq!(protocol(Version::HTTP_10), "HTTP/1.0");
+        assert_eq!(protocol(Version::HTTP_11), "HTTP/1.1");
+        assert_eq!(protocol(Version::HTTP_2), "HTTP/2.0");
+    }
 }
@@ -1,8 +1,19 @@
-use crate::script::{Process, Wasm};
+use crate::context::RequestId;
+use crate::metrics::Metrics;
+use crate::script::{BoxedScript, Process, Wasm};
+use crate::server::confine::confine;
+use crate::server::{AccessLog, Encoding, ErrorLog, MimeTypes, SymlinkPolicy};
+use crate::shutdown::ShutdownHandle;
 use crate::{about, server};
+use http::HeaderValue;
+use std::collections::BTreeSet;
 use std::ffi::OsStr;
+use std::io;
 use std::net::SocketAddr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 pub struct ServerContext {
     document_root: PathBuf,
@@ -13,17 +24,54 @@ pub struct ServerContext {
     path: &'static str,
     scheme: String,
     software: String,
+    max_body_bytes: Option<u64>,
+    require_content_length: BTreeSet<String>,
     cgi_scripts: ScriptMapping,
+    shutdown: ShutdownHandle,
+    access_log: Option<Arc<AccessLog>>,
+    error_log: Arc<ErrorLog>,
+    development: bool,
+    mime_types: MimeTypes,
+    nosniff: bool,
+    symlinks: SymlinkPolicy,
+    index_files: Vec<String>,
+    autoindex: bool,
+    precompressed: bool,
+    compression_min_bytes: Option<u64>,
+    request_id_seed: u32,
+    request_count: AtomicU64,
+    metrics: Arc<Metrics>,
 }
 
 pub struct ScriptMapping {
     process: Process,
     wasm: Wasm,
+    extensions: Vec<(String, Box<dyn BoxedScript>)>,
+    prefixes: Vec<(String, Box<dyn BoxedScript>)>,
 }
 
 impl ScriptMapping {
     pub fn new(process: Process, wasm: Wasm) -> Self {
-        Self { process, wasm }
+        Self {
+            process,
+            wasm,
+            extensions: Vec::new(),
+            prefixes: Vec::new(),
+        }
+    }
+
+    /// Registers a script for files in the CGI bin directory with the given extension.
+    /// Custom scripts take precedence over the built-in `cgi` and `wcgi` extensions.
+    pub fn add_extension(&mut self, extension: String, script: Box<dyn BoxedScript>) {
+        self.extensions.push((extension, script));
+    }
+
+    /// Registers a script for every request path that starts with the given prefix.
+    /// The longest matching prefix wins.
+    pub fn add_prefix(&mut self, prefix: String, script: Box<dyn BoxedScript>) {
+        self.prefixes.push((prefix, script));
+        self.prefixes
+            .sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
     }
 
     pub fn process(&self) -> &Process {
@@ -33,17 +81,50 @@ impl ScriptMapping {
     pub fn wasm(&self) -> &Wasm {
         &self.wasm
     }
+
+    pub fn by_extension(&self, extension: &OsStr) -> Option<&dyn BoxedScript> {
+        self.extensions
+            .iter()
+            .find(|(e, _)| OsStr::new(e) == extension)
+            .map(|(_, script)| script.as_ref())
+    }
+
+    pub fn by_prefix(&self, path: &str) -> Option<(&str, &dyn BoxedScript)> {
+        self.prefixes
+            .iter()
+            .find(|(prefix, _)| path.starts_with(prefix.as_str()))
+            .map(|(prefix, script)| (prefix.as_str(), script.as_ref()))
+    }
 }
 
 impl ServerContext {
-    pub fn new(address: SocketAddr, options: server::Options, cgi_scripts: ScriptMapping) -> Self {
+    pub fn new(
+        address: SocketAddr,
+        options: server::Options,
+        cgi_scripts: ScriptMapping,
+        shutdown: ShutdownHandle,
+        access_log: Option<AccessLog>,
+        error_log: Arc<ErrorLog>,
+        metrics: Arc<Metrics>,
+    ) -> Self {
         let ip_address = address.ip().to_string();
         let port = address.port().to_string();
 
         let path: &'static str = env!("PATH");
-        let scheme = String::from("http");
+        let scheme = String::from(if options.tls_cert.is_some() {
+            "https"
+        } else {
+            "http"
+        });
         let software = format!("{}/{}", about::PROGRAM, about::VERSION);
 
+        // Distinguishes the request IDs of different server instances, such as after a restart.
+        let request_id_seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .subsec_nanos()
+            ^ std::process::id();
+
         Self {
             document_root: options.document_root,
             cgi_bin: options.cgi_bin,
@@ -53,7 +134,23 @@ impl ServerContext {
             path,
             scheme,
             software,
+            max_body_bytes: options.max_body_bytes,
+            require_content_length: options.require_content_length,
             cgi_scripts,
+            shutdown,
+            access_log: access_log.map(Arc::new),
+            error_log,
+            development: options.development,
+            mime_types: MimeTypes::new(&options.mime_types),
+            nosniff: options.nosniff,
+            symlinks: options.symlinks,
+            index_files: options.index_files,
+            autoindex: options.autoindex,
+            precompressed: options.precompressed,
+            compression_min_bytes: options.compression.then_some(options.compression_min_bytes),
+            request_id_seed,
+            request_count: AtomicU64::new(0),
+            metrics,
         }
     }
 
@@ -73,35 +170,108 @@ impl ServerContext {
         self.hostname.as_str()
     }
 
-    pub fn script_filename<'a>(&self, path: &'a str) -> Option<(PathBuf, &'a str)> {
-        let script_path = path.strip_prefix("/cgi-bin/")?;
+    /// The script file and extra path of a request path in the CGI bin directory, if it has one.
+    /// Fails if the script file would be outside of the CGI bin directory.
+    pub fn script_filename<'a>(&self, path: &'a str) -> io::Result<Option<(PathBuf, &'a str)>> {
+        let Some(script_path) = path.strip_prefix("/cgi-bin/") else {
+            return Ok(None);
+        };
 
         let index = script_path
             .chars()
             .position(|c| c == '/')
-            .unwrap_or_else(|| script_path.len());
+            .unwrap_or(script_path.len());
         let (filename, extra_path) = script_path.split_at(index);
+        let file_path = confine(&self.cgi_bin, filename, self.symlinks)?;
+
+        Ok(Some((file_path, extra_path)))
+    }
+
+    /// The file or directory in the document root for a static request path.
+    pub fn resolve_path(&self, path: &str) -> io::Result<PathBuf> {
+        confine(
+            &self.document_root,
+            path.strip_prefix('/').unwrap_or(path),
+            self.symlinks,
+        )
+    }
+
+    /// The first index file that exists in a directory, subject to the same confinement as request paths.
+    pub fn index_file(&self, directory: &Path) -> io::Result<Option<PathBuf>> {
+        for index_file in &self.index_files {
+            if let Some(path) = self.existing_file(directory.join(index_file))? {
+                return Ok(Some(path));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// The precompressed siblings of a static file that exist (e.g., `app.js.br`), if they are served at all.
+    pub fn precompressed_files(&self, path: &Path) -> io::Result<Vec<(Encoding, PathBuf)>> {
+        if !self.precompressed {
+            return Ok(Vec::new());
+        }
 
-        let mut file_path = self.cgi_bin.clone();
+        let mut files = Vec::new();
 
-        file_path.push(filename);
+        for encoding in Encoding::PRECOMPRESSED {
+            let mut sibling = path.as_os_str().to_owned();
 
-        Some((file_path, extra_path))
+            sibling.push(".");
+            sibling.push(encoding.extension());
+
+            if let Some(sibling) = self.existing_file(PathBuf::from(sibling))? {
+                files.push((encoding, sibling));
+            }
+        }
+
+        Ok(files)
     }
 
-    pub fn resolve_path(&self, path: &str) -> PathBuf {
-        let mut normalized_path = path.strip_prefix('/').unwrap_or(path);
+    /// The minimum size of script responses that are compressed, unless compression is disabled.
+    pub fn compression_min_bytes(&self) -> Option<u64> {
+        self.compression_min_bytes
+    }
 
-        if normalized_path.is_empty() {
-            normalized_path = "index.html";
+    /// A file in the document root, if it exists, subject to the same confinement as request paths.
+    fn existing_file(&self, path: PathBuf) -> io::Result<Option<PathBuf>> {
+        if !path.is_file() {
+            return Ok(None);
         }
 
-        self.document_root.join(normalized_path)
+        let relative = path
+            .strip_prefix(&self.document_root)
+            .map_err(|_| io::Error::from(io::ErrorKind::NotFound))?;
+
+        confine(
+            &self.document_root,
+            &relative.to_string_lossy(),
+            self.symlinks,
+        )
+        .map(Some)
+    }
+
+    /// Whether directories without an index file are listed.
+    pub fn is_autoindex(&self) -> bool {
+        self.autoindex
     }
 
-    pub fn translate_path(&self, path: &str) -> PathBuf {
-        self.document_root
-            .join(path.strip_prefix('/').unwrap_or(path))
+    /// Whether a file is run as a script rather than served, by its extension.
+    pub fn is_script_file(&self, path: &Path) -> bool {
+        path.extension().is_some_and(|extension| {
+            ["cgi", "wcgi", "whttp"].iter().any(|e| extension == *e)
+                || self.cgi_scripts.by_extension(extension).is_some()
+        })
+    }
+
+    /// The `PATH_TRANSLATED` of a script's extra path, which must stay within the document root.
+    pub fn translate_path(&self, path: &str) -> io::Result<PathBuf> {
+        confine(
+            &self.document_root,
+            path.strip_prefix('/').unwrap_or(path),
+            self.symlinks,
+        )
     }
 
     pub fn working_directory(&self) -> &OsStr {
@@ -116,7 +286,69 @@ impl ServerContext {
         self.port.as_str()
     }
 
+    pub fn max_body_bytes(&self) -> Option<u64> {
+        self.max_body_bytes
+    }
+
+    pub fn requires_content_length(&self, script_name: &str) -> bool {
+        self.require_content_length.contains(script_name)
+    }
+
     pub fn script_mappings(&self) -> &ScriptMapping {
         &self.cgi_scripts
     }
+
+    /// Whether the request path is handled by a script rather than served as a static file.
+    pub fn is_script_path(&self, path: &str) -> bool {
+        path.starts_with("/cgi-bin/") || self.cgi_scripts.by_prefix(path).is_some()
+    }
+
+    pub fn shutdown_handle(&self) -> &ShutdownHandle {
+        &self.shutdown
+    }
+
+    pub fn access_log(&self) -> Option<&Arc<AccessLog>> {
+        self.access_log.as_ref()
+    }
+
+    pub fn error_log(&self) -> &ErrorLog {
+        &self.error_log
+    }
+
+    /// Whether script diagnostics are included in error responses.
+    pub fn is_development(&self) -> bool {
+        self.development
+    }
+
+    /// The `Content-Type` of a static file, by its extension.
+    pub fn content_type(&self, path: &Path) -> HeaderValue {
+        self.mime_types.content_type(path)
+    }
+
+    /// Whether static files are sent with `X-Content-Type-Options: nosniff`.
+    pub fn is_nosniff(&self) -> bool {
+        self.nosniff
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    pub fn next_request_id(&self) -> RequestId {
+        let count = self.request_count.fetch_add(1, Ordering::Relaxed);
+
+        RequestId(format!("{:08x}{:08x}", self.request_id_seed, count as u32))
+    }
+
+    /// The script that handles the request path, as a file path in the CGI bin directory or a mounted path prefix.
+    pub fn script_path(&self, path: &str) -> Option<String> {
+        match self.cgi_scripts.by_prefix(path) {
+            Some((prefix, _)) => Some(prefix.to_string()),
+            None => self
+                .script_filename(path)
+                .ok()
+                .flatten()
+                .map(|(filename, _)| filename.display().to_string()),
+        }
+    }
 }
@@ -0,0 +1,134 @@
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+use tokio::select;
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+/// A script's timeout, which only counts the time the script spends making progress on its own.
+/// The clock stands still while the server waits on the client, so slow clients never time out a script.
+pub struct Deadline {
+    state: Mutex<State>,
+    changed: Notify,
+}
+
+struct State {
+    /// The time left once the clock was last started.
+    remaining: Duration,
+    started: Instant,
+    /// The number of outstanding pauses; the clock only runs when there are none.
+    pauses: usize,
+}
+
+impl Deadline {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            state: Mutex::new(State {
+                remaining: timeout,
+                started: Instant::now(),
+                pauses: 0,
+            }),
+            changed: Notify::new(),
+        }
+    }
+
+    /// Stops the clock until the returned guard is dropped. Overlapping pauses stop it only once.
+    pub fn pause(&self) -> Paused<'_> {
+        let mut state = self.lock();
+
+        if state.pauses == 0 {
+            state.remaining = state.remaining.saturating_sub(state.started.elapsed());
+        }
+
+        state.pauses += 1;
+        drop(state);
+        self.changed.notify_waiters();
+
+        Paused(self)
+    }
+
+    /// The time left before the deadline, which stays the same while the clock is paused.
+    pub fn remaining(&self) -> Duration {
+        let state = self.lock();
+
+        match state.pauses {
+            0 => state.remaining.saturating_sub(state.started.elapsed()),
+            _ => state.remaining,
+        }
+    }
+
+    /// Completes once the clock has run for the whole timeout.
+    pub async fn expired(&self) {
+        loop {
+            let changed = self.changed.notified();
+            tokio::pin!(changed);
+            // Registers for changes before reading the state, so none are missed in between.
+            changed.as_mut().enable();
+
+            let expiry = {
+                let state = self.lock();
+
+                (state.pauses == 0).then(|| state.started + state.remaining)
+            };
+
+            match expiry {
+                Some(expiry) if expiry <= Instant::now() => return,
+                // Checks the state again after sleeping, in case the clock was paused in the meantime.
+                Some(expiry) => select! {
+                    _ = tokio::time::sleep_until(expiry) => {}
+                    _ = changed => {}
+                },
+                None => changed.await,
+            }
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Restarts the clock of a [`Deadline`] once every pause has ended.
+pub struct Paused<'a>(&'a Deadline);
+
+impl Drop for Paused<'_> {
+    fn drop(&mut self) {
+        let mut state = self.0.lock();
+
+        state.pauses -= 1;
+
+        if state.pauses == 0 {
+            state.started = Instant::now();
+        }
+
+        drop(state);
+        self.0.changed.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn paused() {
+        let deadline = Deadline::new(Duration::from_millis(200));
+        let started = Instant::now();
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        {
+            let _outer = deadline.pause();
+            let _inner = deadline.pause();
+
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+
+        deadline.expired().await;
+
+        let elapsed = started.elapsed();
+
+        assert!(elapsed >= Duration::from_millis(700), "{elapsed:?}");
+        assert!(elapsed < Duration::from_millis(1500), "{elapsed:?}");
+        assert_eq!(deadline.remaining(), Duration::ZERO);
+    }
+}
@@ -1,10 +1,20 @@
 mod about;
 mod context;
+mod deadline;
+mod metrics;
 mod script;
 mod server;
+mod shutdown;
+mod tls;
 mod uri;
 mod variable;
 mod wasm;
 
-pub use script::Script;
-pub use server::{Options, Server};
+pub use context::RequestContext;
+pub use script::{Input, Output, Script};
+pub use server::{
+    DirectoryAccess, LogFormat, Options, Router, Server, ServerBuilder, SymlinkPolicy,
+    WasmDirectory, WasmLimits, WasmPooling,
+};
+pub use shutdown::ShutdownHandle;
+pub use wasm::{compile, Compiled};
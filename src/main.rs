@@ -1,12 +1,15 @@
 use clap::{Parser, Subcommand};
-use std::path::Component::CurDir;
+use std::io;
+use std::net::SocketAddr;
 use std::path::PathBuf;
-use tortuga::Server;
+use std::time::Duration;
+use tortuga::{DirectoryAccess, LogFormat, Server, SymlinkPolicy};
 
 #[derive(Debug, Parser)]
 #[command(author, version, about, long_about)]
 struct Options {
     /// Sets the verbosity of logging.
+    /// Pass once to write access logs to standard output if no format is configured, or twice to also print the options.
     #[arg(short = 'v', long = None, action = clap::ArgAction::Count)]
     verbosity: u8,
 
@@ -18,73 +21,507 @@ struct Options {
 enum Commands {
     /// Serve CGI scripts and static assets from an HTTP server.
     Serve(ServeOptions),
+
+    /// Precompile the WebAssembly scripts in the CGI bin directory into `.cwasm` artifacts the server loads directly.
+    /// Exits with an error if any module is invalid or imports functions the server does not provide.
+    Compile(CompileOptions),
+
+    /// Inspect the server configuration.
+    #[command(subcommand)]
+    Config(ConfigCommands),
+}
+
+#[derive(Debug, Subcommand)]
+enum ConfigCommands {
+    /// Print the effective configuration after merging the configuration file with any command-line flags.
+    Print(ServeOptions),
 }
 
+/// Command-line flags take precedence over any values set in the configuration file.
+/// Boolean flags may be given a value (e.g., `--compression=false`) to turn off a setting the file enables.
 #[derive(Clone, Debug, Parser)]
 struct ServeOptions {
+    /// The path to a TOML configuration file for the server.
+    #[arg(short = 'C', long, value_name = "CONFIG")]
+    config: Option<PathBuf>,
+
     /// Enable an in-memory cache for compiled WebAssembly modules.
-    #[arg(short, long)]
-    wasm_cache: bool,
+    #[arg(short, long, num_args = 0..=1, default_missing_value = "true", require_equals = true, value_name = "BOOL")]
+    wasm_cache: Option<bool>,
 
     /// Pre-load compiled WebAssembly modules into the in-memory cache.
-    #[arg(short, long, requires = "wasm_cache")]
-    preload_wasm: bool,
+    #[arg(short, long, num_args = 0..=1, default_missing_value = "true", require_equals = true, value_name = "BOOL")]
+    preload_wasm: Option<bool>,
+
+    /// A directory to store compiled WebAssembly modules in, so they are not recompiled after a restart.
+    #[arg(long, value_name = "DIRECTORY")]
+    wasm_disk_cache: Option<PathBuf>,
+
+    /// A directory to load precompiled artifacts from, mirroring the CGI bin directory.
+    #[arg(long, value_name = "DIRECTORY")]
+    wasm_artifacts: Option<PathBuf>,
 
     /// The document root path to load CGI scripts and other assets from.
     #[arg(value_name = "DOCUMENT_ROOT")]
-    document_root: PathBuf,
+    document_root: Option<PathBuf>,
 
     /// The path to CGI scripts; may be relative or absolute.
     /// Relative paths are resolved from the document root.
-    #[arg(short, long, default_value=CurDir.as_os_str(), value_name = "CGI_BIN")]
-    cgi_bin: PathBuf,
+    #[arg(short, long, value_name = "CGI_BIN")]
+    cgi_bin: Option<PathBuf>,
 
     /// The hostname of the local TCP interface for the server to listen on.
-    #[arg(
-        short = 'H',
-        long,
-        default_value = "localhost",
-        value_name = "HOSTNAME"
-    )]
-    hostname: String,
+    #[arg(short = 'H', long, value_name = "HOSTNAME")]
+    hostname: Option<String>,
 
     /// The TCP port for the server to listen on.
-    #[arg(short = 'P', long, default_value_t = 0, value_name = "PORT")]
-    port: u16,
+    #[arg(short = 'P', long, value_name = "PORT")]
+    port: Option<u16>,
+
+    /// The maximum number of seconds a CGI script may run before it is terminated.
+    #[arg(long, value_name = "SECONDS")]
+    cgi_timeout: Option<u64>,
+
+    /// Overrides the script timeout for a script name (e.g., `/cgi-bin/git.cgi=300`).
+    #[arg(long, value_name = "SCRIPT_NAME=SECONDS", value_parser = parse_script_timeout)]
+    cgi_script_timeout: Vec<(String, u64)>,
+
+    /// The amount of fuel each WebAssembly script invocation is given to run with.
+    #[arg(long, value_name = "FUEL")]
+    wasm_fuel: Option<u64>,
+
+    /// The maximum number of seconds a WebAssembly script may run before it is stopped. Defaults to the CGI script timeout.
+    #[arg(long, value_name = "SECONDS")]
+    wasm_deadline: Option<u64>,
+
+    /// The most linear memory, in bytes, each WebAssembly script invocation may grow to, or "unlimited".
+    #[arg(long, value_name = "BYTES", value_parser = parse_limit)]
+    wasm_max_memory_bytes: Option<Limit>,
+
+    /// The most elements each table of a WebAssembly script invocation may grow to, or "unlimited".
+    #[arg(long, value_name = "ELEMENTS", value_parser = parse_limit)]
+    wasm_max_table_elements: Option<Limit>,
+
+    /// Allocate WebAssembly instances from pre-allocated pools instead of on demand.
+    #[arg(long, num_args = 0..=1, default_missing_value = "true", require_equals = true, value_name = "BOOL")]
+    wasm_pooling: Option<bool>,
+
+    /// The most WebAssembly scripts that may run concurrently when pooling instances. Implies --wasm-pooling.
+    #[arg(long, value_name = "INSTANCES")]
+    wasm_pool_instances: Option<u32>,
+
+    /// How WebAssembly scripts may access the document root: "none", "read-only" or "read-write".
+    #[arg(long, value_name = "ACCESS")]
+    wasm_document_root: Option<DirectoryAccess>,
+
+    /// Give each WebAssembly script invocation an empty, writable directory at /tmp.
+    #[arg(long, num_args = 0..=1, default_missing_value = "true", require_equals = true, value_name = "BOOL")]
+    wasm_scratch_directory: Option<bool>,
+
+    /// The largest request body, in bytes, the server will pass to a CGI script, or "unlimited".
+    #[arg(long, value_name = "BYTES", value_parser = parse_limit)]
+    max_body_bytes: Option<Limit>,
+
+    /// A script name (e.g., `/cgi-bin/upload.cgi`) that requires a CONTENT_LENGTH.
+    /// Request bodies without a known length are spooled to a temporary file before invoking the script.
+    #[arg(long, value_name = "SCRIPT_NAME")]
+    require_content_length: Vec<String>,
+
+    /// The number of seconds between scans of the CGI bin directory for WebAssembly modules.
+    #[arg(long, value_name = "SECONDS")]
+    scan_interval: Option<u64>,
+
+    /// The number of seconds in-flight requests are given to finish once the server begins to shut down.
+    #[arg(long, value_name = "SECONDS")]
+    shutdown_grace_period: Option<u64>,
+
+    /// The format of access log entries: common, combined or json.
+    #[arg(long, value_name = "FORMAT")]
+    access_log_format: Option<LogFormat>,
+
+    /// The path of a file to append access log entries to, instead of standard output.
+    #[arg(long, value_name = "PATH")]
+    access_log: Option<PathBuf>,
+
+    /// The path of a file to append diagnostics to, such as the standard error of CGI scripts.
+    #[arg(long, value_name = "PATH")]
+    error_log: Option<PathBuf>,
+
+    /// The most standard error output, in bytes, captured from each script invocation.
+    #[arg(long, value_name = "BYTES")]
+    stderr_max_bytes: Option<usize>,
+
+    /// Include the standard error of failed scripts in error responses. Intended for local development only.
+    #[arg(long, num_args = 0..=1, default_missing_value = "true", require_equals = true, value_name = "BOOL")]
+    development: Option<bool>,
+
+    /// A file served (or run, for scripts) for requests of a directory, replacing the default index files.
+    /// May be given more than once, in order of preference.
+    #[arg(long, value_name = "NAME")]
+    index_file: Vec<String>,
+
+    /// List the contents of directories without an index file.
+    #[arg(long, num_args = 0..=1, default_missing_value = "true", require_equals = true, value_name = "BOOL")]
+    autoindex: Option<bool>,
+
+    /// Whether symbolic links are followed: "follow", "follow-if-owner-matches" or "deny".
+    #[arg(long, value_name = "POLICY")]
+    symlinks: Option<SymlinkPolicy>,
+
+    /// Serves static files with the given extension as a media type (e.g., `md=text/markdown`).
+    #[arg(long, value_name = "EXTENSION=TYPE", value_parser = parse_mime_type)]
+    mime_type: Vec<(String, String)>,
+
+    /// Send `X-Content-Type-Options: nosniff` with static files.
+    #[arg(long, num_args = 0..=1, default_missing_value = "true", require_equals = true, value_name = "BOOL")]
+    nosniff: Option<bool>,
+
+    /// Serve precompressed .br or .gz siblings of static files. Use `--precompressed=false` to never serve them.
+    #[arg(long, num_args = 0..=1, default_missing_value = "true", require_equals = true, value_name = "BOOL")]
+    precompressed: Option<bool>,
+
+    /// Compress the responses of scripts with gzip, brotli or zstd.
+    #[arg(long, num_args = 0..=1, default_missing_value = "true", require_equals = true, value_name = "BOOL")]
+    compression: Option<bool>,
+
+    /// The size in bytes a script response must reach before it is compressed.
+    #[arg(long, value_name = "BYTES")]
+    compression_min_bytes: Option<u64>,
+
+    /// The address of a separate listener serving Prometheus metrics at /metrics (e.g., 127.0.0.1:9090).
+    #[arg(long, value_name = "ADDRESS")]
+    metrics_address: Option<SocketAddr>,
+
+    /// The path to a PEM-encoded certificate chain to terminate TLS connections with.
+    #[arg(long, value_name = "PATH")]
+    tls_cert: Option<PathBuf>,
+
+    /// The path to the PEM-encoded private key for the TLS certificate.
+    #[arg(long, value_name = "PATH")]
+    tls_key: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, Parser)]
+struct CompileOptions {
+    /// The directory to write artifacts to, mirroring the CGI bin directory.
+    /// Defaults to the `wasm_artifacts` directory, or to writing each artifact next to its module.
+    #[arg(short, long, value_name = "DIRECTORY")]
+    output: Option<PathBuf>,
+
+    #[command(flatten)]
+    serve: ServeOptions,
+}
+
+impl ServeOptions {
+    /// Merges the configuration file (if any) with the command-line flags.
+    fn load(self) -> io::Result<tortuga::Options> {
+        let mut options = match self.config.as_ref() {
+            Some(path) => tortuga::Options::from_file(path)?,
+            None => tortuga::Options::default(),
+        };
+
+        if let Some(wasm_cache) = self.wasm_cache {
+            options.wasm_cache = wasm_cache;
+        }
+
+        if let Some(preload_wasm) = self.preload_wasm {
+            options.preload_wasm = preload_wasm;
+        }
+
+        if let Some(wasm_disk_cache) = self.wasm_disk_cache {
+            options.wasm_disk_cache = Some(wasm_disk_cache);
+        }
+
+        if let Some(wasm_artifacts) = self.wasm_artifacts {
+            options.wasm_artifacts = Some(wasm_artifacts);
+        }
+
+        if let Some(document_root) = self.document_root {
+            options.document_root = document_root;
+        }
+
+        if let Some(cgi_bin) = self.cgi_bin {
+            options.cgi_bin = cgi_bin;
+        }
+
+        if let Some(hostname) = self.hostname {
+            options.hostname = hostname;
+        }
+
+        if let Some(port) = self.port {
+            options.port = port;
+        }
+
+        if let Some(cgi_timeout) = self.cgi_timeout {
+            options.cgi_timeout = Duration::from_secs(cgi_timeout);
+        }
+
+        for (script_name, seconds) in self.cgi_script_timeout {
+            options
+                .cgi_timeouts
+                .insert(script_name, Duration::from_secs(seconds));
+        }
+
+        if let Some(wasm_fuel) = self.wasm_fuel {
+            options.wasm_fuel = wasm_fuel;
+        }
+
+        if let Some(wasm_deadline) = self.wasm_deadline {
+            options.wasm_deadline = Some(Duration::from_secs(wasm_deadline));
+        }
+
+        if let Some(Limit(wasm_max_memory_bytes)) = self.wasm_max_memory_bytes {
+            options.wasm_max_memory_bytes = wasm_max_memory_bytes;
+        }
+
+        if let Some(Limit(wasm_max_table_elements)) = self.wasm_max_table_elements {
+            options.wasm_max_table_elements = wasm_max_table_elements;
+        }
+
+        match self.wasm_pooling {
+            Some(true) => {
+                options.wasm_pooling.get_or_insert_with(Default::default);
+            }
+            Some(false) => options.wasm_pooling = None,
+            None => {}
+        }
+
+        if let Some(instances) = self.wasm_pool_instances {
+            options
+                .wasm_pooling
+                .get_or_insert_with(Default::default)
+                .instances = instances;
+        }
+
+        if let Some(wasm_document_root) = self.wasm_document_root {
+            options.wasm_document_root = wasm_document_root;
+        }
+
+        if let Some(wasm_scratch_directory) = self.wasm_scratch_directory {
+            options.wasm_scratch_directory = wasm_scratch_directory;
+        }
+
+        if let Some(Limit(max_body_bytes)) = self.max_body_bytes {
+            options.max_body_bytes = max_body_bytes;
+        }
+
+        options
+            .require_content_length
+            .extend(self.require_content_length);
+
+        if let Some(scan_interval) = self.scan_interval {
+            options.scan_interval = Duration::from_secs(scan_interval);
+        }
+
+        if let Some(shutdown_grace_period) = self.shutdown_grace_period {
+            options.shutdown_grace_period = Duration::from_secs(shutdown_grace_period);
+        }
+
+        if let Some(access_log_format) = self.access_log_format {
+            options.access_log_format = Some(access_log_format);
+        }
+
+        if let Some(access_log) = self.access_log {
+            options.access_log = Some(access_log);
+        }
+
+        if let Some(error_log) = self.error_log {
+            options.error_log = Some(error_log);
+        }
+
+        if let Some(stderr_max_bytes) = self.stderr_max_bytes {
+            options.stderr_max_bytes = stderr_max_bytes;
+        }
+
+        if let Some(development) = self.development {
+            options.development = development;
+        }
+
+        if !self.index_file.is_empty() {
+            options.index_files = self.index_file;
+        }
+
+        if let Some(autoindex) = self.autoindex {
+            options.autoindex = autoindex;
+        }
+
+        if let Some(symlinks) = self.symlinks {
+            options.symlinks = symlinks;
+        }
+
+        options.mime_types.extend(self.mime_type);
+
+        if let Some(nosniff) = self.nosniff {
+            options.nosniff = nosniff;
+        }
+
+        if let Some(precompressed) = self.precompressed {
+            options.precompressed = precompressed;
+        }
+
+        if let Some(compression) = self.compression {
+            options.compression = compression;
+        }
+
+        if let Some(compression_min_bytes) = self.compression_min_bytes {
+            options.compression_min_bytes = compression_min_bytes;
+        }
+
+        if let Some(metrics_address) = self.metrics_address {
+            options.metrics_address = Some(metrics_address);
+        }
+
+        if let Some(tls_cert) = self.tls_cert {
+            options.tls_cert = Some(tls_cert);
+        }
+
+        if let Some(tls_key) = self.tls_key {
+            options.tls_key = Some(tls_key);
+        }
+
+        options.validate()?;
+
+        Ok(options)
+    }
+}
+
+/// A maximum size, where `None` means the size is unlimited.
+#[derive(Clone, Copy, Debug)]
+struct Limit(Option<u64>);
+
+fn parse_limit(value: &str) -> Result<Limit, String> {
+    match value {
+        "unlimited" => Ok(Limit(None)),
+        _ => value
+            .parse::<u64>()
+            .map(|limit| Limit(Some(limit)))
+            .map_err(|e| e.to_string()),
+    }
+}
+
+fn parse_script_timeout(value: &str) -> Result<(String, u64), String> {
+    let (script_name, seconds) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected SCRIPT_NAME=SECONDS, found `{value}`"))?;
+    let seconds = seconds.parse::<u64>().map_err(|e| e.to_string())?;
+
+    Ok((script_name.to_string(), seconds))
+}
+
+fn parse_mime_type(value: &str) -> Result<(String, String), String> {
+    let (extension, media_type) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected EXTENSION=TYPE, found `{value}`"))?;
+
+    Ok((extension.to_string(), media_type.to_string()))
+}
+
+/// Waits for a SIGINT (i.e., Ctrl+C) or, on Unix, a SIGTERM.
+async fn wait_for_shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate =
+            signal(SignalKind::terminate()).expect("Unable to listen for the SIGTERM signal.");
+
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+fn load_or_exit(options: ServeOptions) -> tortuga::Options {
+    options.load().unwrap_or_else(|e| {
+        eprintln!("Invalid configuration: {e}");
+        std::process::exit(1);
+    })
 }
 
 pub fn main() {
     let options = Options::parse();
 
-    eprintln!("Starting server with options: {:?}", options);
+    let verbosity = options.verbosity;
+
+    if verbosity > 1 {
+        eprintln!("Starting server with options: {:?}", options);
+    }
 
     // You can check for the existence of subcommands, and if found use their
     // matches just as you would the top level cmd
     match options.command {
         Some(Commands::Serve(serve_options)) => {
+            let mut options = load_or_exit(serve_options);
+
+            if verbosity > 0 && options.access_log_format.is_none() {
+                options.access_log_format = Some(LogFormat::Common);
+            }
             let runtime = tokio::runtime::Builder::new_multi_thread()
                 .enable_all()
                 .build()
                 .expect("Unable to start an async runtime");
 
-            let options = tortuga::Options {
-                document_root: serve_options.document_root,
-                cgi_bin: serve_options.cgi_bin,
-                hostname: serve_options.hostname,
-                port: serve_options.port,
-                wasm_cache: serve_options.wasm_cache,
-                preload_wasm: serve_options.preload_wasm,
-            };
             let server = runtime
                 .block_on(Server::bind(options))
                 .expect("Unable to start the server.");
 
             println!("Server listening on port {}", server.address().unwrap());
 
+            let shutdown = server.shutdown_handle();
+
+            runtime.spawn(async move {
+                wait_for_shutdown_signal().await;
+                eprintln!("Shutting down the server.");
+                shutdown.shutdown();
+            });
+
             runtime
                 .block_on(server.serve())
                 .expect("Unable to start the server");
         }
-        _ => {}
+        Some(Commands::Compile(compile_options)) => {
+            let options = load_or_exit(compile_options.serve);
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("Unable to start an async runtime");
+
+            let compiled = runtime
+                .block_on(tortuga::compile(options, compile_options.output))
+                .unwrap_or_else(|e| {
+                    eprintln!("Unable to compile the CGI bin directory: {e}");
+                    std::process::exit(1);
+                });
+            let mut failed = false;
+
+            for tortuga::Compiled { module, result } in compiled {
+                match result {
+                    Ok(artifact) => println!("{} -> {}", module.display(), artifact.display()),
+                    Err(e) => {
+                        failed = true;
+                        eprintln!("{}: {e}", module.display());
+                    }
+                }
+            }
+
+            if failed {
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Config(ConfigCommands::Print(serve_options))) => {
+            let options = load_or_exit(serve_options);
+
+            print!(
+                "{}",
+                options
+                    .to_toml()
+                    .expect("Unable to render the configuration.")
+            );
+        }
+        None => {}
     }
 }
@@ -0,0 +1,311 @@
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// The upper bounds, in seconds, of the request latency histogram buckets.
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// The kind of route that handled a request.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum RouteKind {
+    Static,
+    Cgi,
+}
+
+impl RouteKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            RouteKind::Static => "static",
+            RouteKind::Cgi => "cgi",
+        }
+    }
+}
+
+/// Statistics about requests, scripts, the WebAssembly module cache and the access log,
+/// rendered in the Prometheus text exposition format.
+#[derive(Default)]
+pub struct Metrics {
+    requests: Mutex<BTreeMap<(RouteKind, u16), u64>>,
+    latency: Mutex<BTreeMap<RouteKind, Histogram>>,
+    invocations: Counters,
+    exit_failures: Counters,
+    timeouts: Counters,
+    fuel_consumed: Counters,
+    traps: Counters,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    cache_evictions: AtomicU64,
+    access_log_dropped: AtomicU64,
+}
+
+impl Metrics {
+    /// Records a response to a request, where the latency is the time until the response head was ready.
+    pub fn request(&self, route: RouteKind, status: u16, latency: Duration) {
+        *lock(&self.requests).entry((route, status)).or_default() += 1;
+        lock(&self.latency)
+            .entry(route)
+            .or_default()
+            .observe(latency);
+    }
+
+    pub fn invocation(&self, script_name: &str) {
+        self.invocations.increment(script_name, 1);
+    }
+
+    pub fn exit_failure(&self, script_name: &str) {
+        self.exit_failures.increment(script_name, 1);
+    }
+
+    pub fn timeout(&self, script_name: &str) {
+        self.timeouts.increment(script_name, 1);
+    }
+
+    pub fn fuel_consumed(&self, script_name: &str, fuel: u64) {
+        self.fuel_consumed.increment(script_name, fuel);
+    }
+
+    pub fn trap(&self, script_name: &str) {
+        self.traps.increment(script_name, 1);
+    }
+
+    pub fn cache_hit(&self) {
+        self.cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn cache_miss(&self) {
+        self.cache_misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn cache_evictions(&self, count: u64) {
+        self.cache_evictions.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Records an access log entry dropped because the log fell behind.
+    pub fn access_log_dropped(&self) {
+        self.access_log_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Renders every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut output = String::new();
+
+        self.write(&mut output)
+            .expect("Writing to a string cannot fail.");
+
+        output
+    }
+
+    fn write(&self, output: &mut String) -> fmt::Result {
+        header(
+            output,
+            "tortuga_requests_total",
+            "counter",
+            "Requests handled, by route and status code.",
+        )?;
+        for ((route, status), count) in lock(&self.requests).iter() {
+            writeln!(
+                output,
+                "tortuga_requests_total{{route=\"{}\",status=\"{status}\"}} {count}",
+                route.as_str()
+            )?;
+        }
+
+        header(
+            output,
+            "tortuga_request_duration_seconds",
+            "histogram",
+            "Time until the response head was ready, by route.",
+        )?;
+        for (route, histogram) in lock(&self.latency).iter() {
+            histogram.write(output, "tortuga_request_duration_seconds", route.as_str())?;
+        }
+
+        self.invocations.write(
+            output,
+            "tortuga_script_invocations_total",
+            "Script invocations, by script name.",
+        )?;
+        self.exit_failures.write(
+            output,
+            "tortuga_script_exit_failures_total",
+            "CGI processes that exited unsuccessfully, by script name.",
+        )?;
+        self.timeouts.write(
+            output,
+            "tortuga_script_timeouts_total",
+            "CGI processes terminated for exceeding their timeout, by script name.",
+        )?;
+        self.fuel_consumed.write(
+            output,
+            "tortuga_wasm_fuel_consumed_total",
+            "Fuel consumed by WebAssembly scripts, by script name.",
+        )?;
+        self.traps.write(
+            output,
+            "tortuga_wasm_traps_total",
+            "WebAssembly scripts that trapped, by script name.",
+        )?;
+
+        for (name, help, value) in [
+            (
+                "tortuga_wasm_cache_hits_total",
+                "WebAssembly modules loaded from the in-memory cache.",
+                &self.cache_hits,
+            ),
+            (
+                "tortuga_wasm_cache_misses_total",
+                "WebAssembly modules compiled because they were not in the in-memory cache.",
+                &self.cache_misses,
+            ),
+            (
+                "tortuga_wasm_cache_evictions_total",
+                "WebAssembly modules purged from the in-memory cache.",
+                &self.cache_evictions,
+            ),
+            (
+                "tortuga_access_log_dropped_total",
+                "Access log entries dropped because the log could not keep up with requests.",
+                &self.access_log_dropped,
+            ),
+        ] {
+            header(output, name, "counter", help)?;
+            writeln!(output, "{name} {}", value.load(Ordering::Relaxed))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Counters labeled by script name.
+#[derive(Default)]
+struct Counters(Mutex<BTreeMap<String, u64>>);
+
+impl Counters {
+    fn increment(&self, script_name: &str, amount: u64) {
+        let mut counters = lock(&self.0);
+
+        match counters.get_mut(script_name) {
+            Some(count) => *count += amount,
+            None => {
+                counters.insert(script_name.to_string(), amount);
+            }
+        }
+    }
+
+    fn write(&self, output: &mut String, name: &str, help: &str) -> fmt::Result {
+        header(output, name, "counter", help)?;
+
+        for (script_name, count) in lock(&self.0).iter() {
+            writeln!(
+                output,
+                "{name}{{script=\"{}\"}} {count}",
+                escape_label(script_name)
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+
+        for (bucket, bound) in self.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            if seconds <= bound {
+                *bucket += 1;
+            }
+        }
+
+        self.count += 1;
+        self.sum += seconds;
+    }
+
+    fn write(&self, output: &mut String, name: &str, route: &str) -> fmt::Result {
+        for (count, bound) in self.buckets.iter().zip(LATENCY_BUCKETS) {
+            writeln!(
+                output,
+                "{name}_bucket{{route=\"{route}\",le=\"{bound}\"}} {count}"
+            )?;
+        }
+
+        writeln!(
+            output,
+            "{name}_bucket{{route=\"{route}\",le=\"+Inf\"}} {}",
+            self.count
+        )?;
+        writeln!(output, "{name}_sum{{route=\"{route}\"}} {}", self.sum)?;
+        writeln!(output, "{name}_count{{route=\"{route}\"}} {}", self.count)
+    }
+}
+
+fn header(output: &mut String, name: &str, kind: &str, help: &str) -> fmt::Result {
+    writeln!(output, "# HELP {name} {help}")?;
+    writeln!(output, "# TYPE {name} {kind}")
+}
+
+/// Escapes a label value for the Prometheus text format, which only escapes backslashes, double quotes and newlines.
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render() {
+        let metrics = Metrics::default();
+
+        metrics.request(RouteKind::Cgi, 200, Duration::from_millis(20));
+        metrics.request(RouteKind::Cgi, 200, Duration::from_secs(20));
+        metrics.invocation("/cgi-bin/hello.cgi");
+        metrics.fuel_consumed("/cgi-bin/echo.wcgi", 42);
+        metrics.cache_hit();
+
+        let output = metrics.render();
+
+        assert!(output.contains("tortuga_requests_total{route=\"cgi\",status=\"200\"} 2\n"));
+        assert!(output
+            .contains("tortuga_request_duration_seconds_bucket{route=\"cgi\",le=\"0.025\"} 1\n"));
+        assert!(output
+            .contains("tortuga_request_duration_seconds_bucket{route=\"cgi\",le=\"+Inf\"} 2\n"));
+        assert!(
+            output.contains("tortuga_script_invocations_total{script=\"/cgi-bin/hello.cgi\"} 1\n")
+        );
+        assert!(
+            output.contains("tortuga_wasm_fuel_consumed_total{script=\"/cgi-bin/echo.wcgi\"} 42\n")
+        );
+        assert!(output.contains("tortuga_wasm_cache_hits_total 1\n"));
+        assert!(output.contains("# TYPE tortuga_script_timeouts_total counter\n"));
+    }
+
+    #[test]
+    fn label_escaping() {
+        let metrics = Metrics::default();
+
+        metrics.invocation("/cgi-bin/caf\u{e9} \"\\\n\t.cgi");
+
+        assert!(metrics.render().contains(
+            "tortuga_script_invocations_total{script=\"/cgi-bin/caf\u{e9} \\\"\\\\\\n\t.cgi\"} 1\n"
+        ));
+    }
+}
@@ -0,0 +1,208 @@
+use bytes::{Bytes, BytesMut};
+use http_body_util::combinators::UnsyncBoxBody;
+use http_body_util::BodyExt;
+use hyper::body::Body;
+use std::error::Error;
+use std::io;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+/// The largest chunk of a spooled request body read at a time.
+const SPOOL_CHUNK_BYTES: usize = 1024 * 8;
+
+/// The body of a request to a CGI script.
+/// Chunks are read from the client as the script consumes them, unless the body was buffered or spooled beforehand.
+pub struct Input {
+    source: Source,
+    content_length: Option<u64>,
+    limit: Option<u64>,
+    received: u64,
+}
+
+enum Source {
+    Buffered(Bytes),
+    Streaming(UnsyncBoxBody<Bytes, io::Error>),
+    Spooled(File),
+}
+
+impl Input {
+    /// Creates an input from a request body that fails once more than `limit` bytes are read.
+    pub fn new<B>(body: B, limit: Option<u64>) -> Self
+    where
+        B: Body<Data = Bytes> + Send + 'static,
+        B::Error: Into<Box<dyn Error + Send + Sync>>,
+    {
+        let content_length = body.size_hint().exact();
+        let source = if body.is_end_stream() {
+            Source::Buffered(Bytes::new())
+        } else {
+            Source::Streaming(body.map_err(io::Error::other).boxed_unsync())
+        };
+
+        Self {
+            source,
+            content_length,
+            limit,
+            received: 0,
+        }
+    }
+
+    /// The length of the body, if known before reading it.
+    pub fn content_length(&self) -> Option<u64> {
+        self.content_length
+    }
+
+    /// Reads the next chunk of the body, if any.
+    pub async fn chunk(&mut self) -> Option<io::Result<Bytes>> {
+        let chunk = match &mut self.source {
+            Source::Buffered(bytes) if bytes.is_empty() => return None,
+            Source::Buffered(bytes) => std::mem::take(bytes),
+            Source::Streaming(body) => loop {
+                match body.frame().await? {
+                    Ok(frame) => match frame.into_data() {
+                        Ok(data) if !data.is_empty() => break data,
+                        _ => continue,
+                    },
+                    Err(e) => return Some(Err(e)),
+                }
+            },
+            Source::Spooled(file) => {
+                let mut buffer = BytesMut::with_capacity(SPOOL_CHUNK_BYTES);
+
+                match file.read_buf(&mut buffer).await {
+                    Ok(0) => return None,
+                    Ok(_) => buffer.freeze(),
+                    Err(e) => return Some(Err(e)),
+                }
+            }
+        };
+
+        self.received += chunk.len() as u64;
+
+        match self.limit {
+            Some(limit) if self.received > limit => Some(Err(io::Error::new(
+                io::ErrorKind::FileTooLarge,
+                format!("Body size is too large. The largest supported body is {limit} bytes."),
+            ))),
+            _ => Some(Ok(chunk)),
+        }
+    }
+
+    /// Reads the entire body into memory.
+    pub async fn buffer(&mut self) -> io::Result<Bytes> {
+        let mut buffer = BytesMut::new();
+
+        while let Some(chunk) = self.chunk().await {
+            buffer.extend_from_slice(&chunk?);
+        }
+
+        let bytes = buffer.freeze();
+
+        self.content_length = Some(bytes.len() as u64);
+        self.source = Source::Buffered(bytes.clone());
+        self.received = 0;
+
+        Ok(bytes)
+    }
+
+    /// Writes the entire body to an anonymous temporary file in order to determine its length.
+    pub async fn spool(&mut self) -> io::Result<()> {
+        let mut file = File::from_std(tokio::task::spawn_blocking(tempfile::tempfile).await??);
+        let mut length = 0;
+
+        while let Some(chunk) = self.chunk().await {
+            let chunk = chunk?;
+
+            file.write_all(&chunk).await?;
+            length += chunk.len() as u64;
+        }
+
+        file.flush().await?;
+        file.rewind().await?;
+
+        self.content_length = Some(length);
+        self.source = Source::Spooled(file);
+        self.received = 0;
+
+        Ok(())
+    }
+}
+
+impl Default for Input {
+    fn default() -> Self {
+        Self::from(Bytes::new())
+    }
+}
+
+impl From<Bytes> for Input {
+    fn from(value: Bytes) -> Self {
+        Self {
+            content_length: Some(value.len() as u64),
+            source: Source::Buffered(value),
+            limit: None,
+            received: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::Full;
+    use hyper::body::Frame;
+    use std::collections::VecDeque;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    /// A body without a known length, like one sent with a chunked transfer-encoding.
+    struct Chunked(VecDeque<Bytes>);
+
+    impl Body for Chunked {
+        type Data = Bytes;
+        type Error = io::Error;
+
+        fn poll_frame(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+        ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+            Poll::Ready(self.0.pop_front().map(|chunk| Ok(Frame::data(chunk))))
+        }
+    }
+
+    fn streaming(chunks: &[&'static str], limit: Option<u64>) -> Input {
+        Input::new(
+            Chunked(chunks.iter().copied().map(Bytes::from).collect()),
+            limit,
+        )
+    }
+
+    #[tokio::test]
+    async fn known_length() {
+        let mut input = Input::new(Full::new(Bytes::from("foobar")), None);
+
+        assert_eq!(input.content_length(), Some(6));
+        assert_eq!(input.buffer().await.unwrap(), Bytes::from("foobar"));
+    }
+
+    #[tokio::test]
+    async fn unknown_length() {
+        let mut input = streaming(&["foo", "bar"], None);
+
+        assert_eq!(input.content_length(), None);
+
+        input.spool().await.unwrap();
+
+        assert_eq!(input.content_length(), Some(6));
+        assert_eq!(input.buffer().await.unwrap(), Bytes::from("foobar"));
+    }
+
+    #[tokio::test]
+    async fn too_large() {
+        let mut input = streaming(&["foo", "bar"], Some(4));
+
+        assert_eq!(
+            input.buffer().await.unwrap_err().kind(),
+            io::ErrorKind::FileTooLarge
+        );
+    }
+}
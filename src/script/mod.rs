@@ -1,18 +1,39 @@
 use crate::context::RequestContext;
-use bytes::Bytes;
 use std::future::Future;
 use std::io;
+use std::pin::Pin;
 
+mod input;
+mod output;
 mod process;
+mod stderr;
 mod wasm;
 
+pub use input::Input;
+pub use output::Output;
 pub use process::Process;
-pub use wasm::Wasm;
+pub use stderr::StderrLog;
+pub use wasm::{Limits, Wasm};
 
+/// Handles a CGI request by producing the script's standard output from its meta-variables and request body.
 pub trait Script {
     fn invoke(
         &self,
         context: RequestContext,
-        body: Bytes,
-    ) -> impl Future<Output = io::Result<Bytes>> + Send;
+        input: Input,
+    ) -> impl Future<Output = io::Result<Output>> + Send;
+}
+
+/// A future returned by a [`BoxedScript`].
+pub type ScriptFuture<'a> = Pin<Box<dyn Future<Output = io::Result<Output>> + Send + 'a>>;
+
+/// An object-safe form of [`Script`], so scripts of different types can be registered together.
+pub trait BoxedScript: Send + Sync {
+    fn invoke_boxed(&self, context: RequestContext, input: Input) -> ScriptFuture<'_>;
+}
+
+impl<S: Script + Send + Sync> BoxedScript for S {
+    fn invoke_boxed(&self, context: RequestContext, input: Input) -> ScriptFuture<'_> {
+        Box::pin(self.invoke(context, input))
+    }
 }
@@ -0,0 +1,154 @@
+use bytes::Bytes;
+use hyper::body::{Body, Frame, SizeHint};
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::sync::mpsc;
+
+/// The standard output of a CGI script.
+/// Chunks are yielded as the script produces them, with back-pressure applied by the bounded channel.
+pub struct Output {
+    head: Option<Bytes>,
+    receiver: Option<mpsc::Receiver<io::Result<Bytes>>>,
+}
+
+impl Output {
+    /// Creates a streaming output along with the sender a script writes chunks of its output to.
+    pub fn channel(capacity: usize) -> (mpsc::Sender<io::Result<Bytes>>, Self) {
+        let (sender, receiver) = mpsc::channel(capacity);
+        let output = Self {
+            head: None,
+            receiver: Some(receiver),
+        };
+
+        (sender, output)
+    }
+
+    /// Reads the next chunk of output, if any.
+    pub async fn chunk(&mut self) -> Option<io::Result<Bytes>> {
+        if let Some(head) = self.head.take() {
+            return Some(Ok(head));
+        }
+
+        let next = self.receiver.as_mut()?.recv().await;
+
+        if next.is_none() {
+            self.receiver = None;
+        }
+
+        next
+    }
+
+    /// Pushes a chunk back to the front of the output to be read again.
+    pub fn unread(&mut self, chunk: Bytes) {
+        self.head = match self.head.take() {
+            None => Some(chunk),
+            Some(head) => {
+                let mut buffer = Vec::with_capacity(chunk.len() + head.len());
+
+                buffer.extend_from_slice(&chunk);
+                buffer.extend_from_slice(&head);
+
+                Some(Bytes::from(buffer))
+            }
+        }
+        .filter(|chunk| !chunk.is_empty());
+    }
+}
+
+impl From<Bytes> for Output {
+    fn from(value: Bytes) -> Self {
+        Self {
+            head: Some(value).filter(|value| !value.is_empty()),
+            receiver: None,
+        }
+    }
+}
+
+impl Body for Output {
+    type Data = Bytes;
+    type Error = io::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        if let Some(head) = self.head.take() {
+            return Poll::Ready(Some(Ok(Frame::data(head))));
+        }
+
+        let Some(receiver) = self.receiver.as_mut() else {
+            return Poll::Ready(None);
+        };
+
+        match receiver.poll_recv(cx) {
+            Poll::Ready(Some(chunk)) => Poll::Ready(Some(chunk.map(Frame::data))),
+            Poll::Ready(None) => {
+                self.receiver = None;
+                Poll::Ready(None)
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.head.is_none() && self.receiver.is_none()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        match (&self.head, &self.receiver) {
+            (None, None) => SizeHint::with_exact(0),
+            (Some(head), None) => SizeHint::with_exact(head.len() as u64),
+            (head, Some(_)) => {
+                let mut hint = SizeHint::new();
+
+                hint.set_lower(head.as_ref().map(Bytes::len).unwrap_or_default() as u64);
+                hint
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::BodyExt;
+
+    #[tokio::test]
+    async fn unread() {
+        let (sender, mut output) = Output::channel(2);
+
+        sender.send(Ok(Bytes::from("Hello, "))).await.unwrap();
+        sender.send(Ok(Bytes::from("World!"))).await.unwrap();
+        drop(sender);
+
+        let chunk = output.chunk().await.unwrap().unwrap();
+
+        output.unread(chunk.slice(5..));
+
+        assert!(!output.is_end_stream());
+        assert_eq!(
+            output.collect().await.unwrap().to_bytes(),
+            Bytes::from(", World!")
+        );
+    }
+
+    #[tokio::test]
+    async fn buffered() {
+        let output = Output::from(Bytes::from("Hello, World!"));
+
+        assert_eq!(output.size_hint().exact(), Some(13));
+        assert_eq!(
+            output.collect().await.unwrap().to_bytes(),
+            Bytes::from("Hello, World!")
+        );
+    }
+
+    #[tokio::test]
+    async fn empty() {
+        let output = Output::from(Bytes::new());
+
+        assert!(output.is_end_stream());
+        assert_eq!(output.size_hint().exact(), Some(0));
+    }
+}
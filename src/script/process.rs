@@ -1,25 +1,142 @@
 use crate::context::RequestContext;
-use crate::script::Script;
-use bytes::Bytes;
+use crate::deadline::Deadline;
+use crate::metrics::Metrics;
+use crate::script::stderr::{StderrLog, Tag};
+use crate::script::{Input, Output, Script};
+use crate::shutdown::{self, ShutdownSignal};
+use bytes::{Bytes, BytesMut};
+use std::collections::BTreeMap;
 use std::io;
-use std::process::Stdio;
+use std::process::{ExitStatus, Stdio};
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::process::Command;
-use tokio::{select, try_join};
-use tokio_util::sync::CancellationToken;
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use tokio::select;
+use tokio::sync::mpsc;
 
-pub struct Process {}
+/// The number of chunks of standard output buffered before the script is made to wait on the client.
+const OUTPUT_CHUNKS: usize = 4;
+
+/// The largest chunk of standard output read from the script at a time.
+const OUTPUT_CHUNK_BYTES: usize = 1024 * 8;
+
+/// How long to wait for the standard error of a failed script to close after it is terminated.
+const STDERR_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub struct Process {
+    timeout: Duration,
+    overrides: BTreeMap<String, Duration>,
+    shutdown: ShutdownSignal,
+    stderr: StderrLog,
+    metrics: Arc<Metrics>,
+}
 
 impl Process {
-    pub fn new() -> Self {
-        Self {}
+    pub fn new(
+        timeout: Duration,
+        overrides: BTreeMap<String, Duration>,
+        shutdown: ShutdownSignal,
+        stderr: StderrLog,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        Self {
+            timeout,
+            overrides,
+            shutdown,
+            stderr,
+            metrics,
+        }
+    }
+
+    fn timeout(&self, script_name: &str) -> Duration {
+        self.overrides
+            .get(script_name)
+            .copied()
+            .unwrap_or(self.timeout)
+    }
+
+    /// Writes the request body to the script's standard input as it is received from the client.
+    /// The deadline is paused while waiting for the next chunk, since the script is then waiting on the client.
+    async fn feed(
+        stdin: Option<ChildStdin>,
+        mut input: Input,
+        deadline: &Deadline,
+    ) -> io::Result<()> {
+        if let Some(mut stdin) = stdin {
+            loop {
+                let paused = deadline.pause();
+                let Some(chunk) = input.chunk().await else {
+                    break;
+                };
+
+                drop(paused);
+
+                match stdin.write_all(&chunk?).await {
+                    // The script closed its standard input without reading the entire body.
+                    Err(e) if e.kind() == io::ErrorKind::BrokenPipe => break,
+                    result => result?,
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sends the script's standard output to the channel as it is produced, then waits for the script to exit.
+    /// The deadline is paused while the channel is full, since the script is then waiting on the client.
+    async fn forward(
+        child: &mut Child,
+        stdout: Option<ChildStdout>,
+        sender: &mpsc::Sender<io::Result<Bytes>>,
+        deadline: &Deadline,
+    ) -> io::Result<ExitStatus> {
+        if let Some(mut stdout) = stdout {
+            loop {
+                let mut buffer = BytesMut::with_capacity(OUTPUT_CHUNK_BYTES);
+
+                if stdout.read_buf(&mut buffer).await? == 0 {
+                    break;
+                }
+
+                let _paused = deadline.pause();
+
+                sender
+                    .send(Ok(buffer.freeze()))
+                    .await
+                    .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+            }
+        }
+
+        child.wait().await
+    }
+
+    async fn terminate(child: &mut Child) -> io::Result<()> {
+        // The script leads its own process group, so this also kills any processes it spawned.
+        #[cfg(unix)]
+        if let Some(id) = child.id() {
+            unsafe {
+                libc::killpg(id as libc::pid_t, libc::SIGKILL);
+            }
+        }
+
+        if child.id().is_some() {
+            child.kill().await?;
+        }
+
+        Ok(())
     }
 }
 
 impl Script for Process {
-    async fn invoke(&self, context: RequestContext, body: Bytes) -> io::Result<Bytes> {
-        let mut child = Command::new(context.script()?)
+    async fn invoke(&self, context: RequestContext, input: Input) -> io::Result<Output> {
+        let timeout = self.timeout(context.script_name());
+        let mut command = Command::new(context.script()?);
+
+        #[cfg(unix)]
+        command.process_group(0);
+
+        let mut child = command
             .kill_on_drop(true)
             .current_dir(context.working_directory())
             .args(context.arguments())
@@ -27,60 +144,76 @@ impl Script for Process {
             .envs(context.variables())
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
-            .stderr(Stdio::inherit())
+            .stderr(Stdio::piped())
             .spawn()?;
 
-        let mut stdin = child.stdin.take();
-        let mut stdout = child.stdout.take();
+        let stdin = child.stdin.take();
+        let stdout = child.stdout.take();
+        let tag = Tag::new(&context);
+        let stderr = child.stderr.take().map(|stderr| {
+            let log = self.stderr.clone();
+            let tag = tag.clone();
 
-        let cancel = CancellationToken::new();
-        let stdin_cancel = cancel.child_token();
-        let _cancel_guard = cancel.drop_guard();
+            tokio::spawn(async move { log.capture(tag, stderr).await })
+        });
+        let stderr_log = self.stderr.clone();
+        let metrics = self.metrics.clone();
+        let script_name = context.script_name().to_string();
 
-        let mut input = body.clone();
+        let (sender, output) = Output::channel(OUTPUT_CHUNKS);
+        let shutdown = self.shutdown.clone();
 
         tokio::spawn(async move {
-            if let Some(mut stdin) = stdin.take() {
+            let deadline = Deadline::new(timeout);
+            // Stop feeding the script its input once it exits, since it may not read all of it.
+            let exchange = async {
                 select! {
-                    _ = stdin.write_all_buf(&mut input) => {}
-                    _ = stdin_cancel.cancelled() => {}
+                    status = Self::forward(&mut child, stdout, &sender, &deadline) => status,
+                    Err(e) = Self::feed(stdin, input, &deadline) => Err(e),
                 }
+            };
 
-                drop(stdin);
-            }
-        });
+            let result = select! {
+                result = exchange => Some(result),
+                _ = deadline.expired() => None,
+                _ = shutdown.terminating() => Some(Err(shutdown::interrupted())),
+            };
 
-        let stdout_task = async {
-            let mut output = Vec::with_capacity(1024 * 8);
+            let error = match result {
+                Some(Ok(status)) if status.success() => return,
+                Some(Ok(status)) => {
+                    metrics.exit_failure(&script_name);
+                    io::Error::other(format!("The child process exited with {status}."))
+                }
+                Some(Err(e)) => e,
+                None => {
+                    metrics.timeout(&script_name);
+                    io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "Timed out waiting for the child process.",
+                    )
+                }
+            };
 
-            if let Some(stdout) = stdout.as_mut() {
-                stdout.read_to_end(&mut output).await?;
+            if let Err(e) = Self::terminate(&mut child).await {
+                stderr_log.write(
+                    &tag,
+                    format_args!("Unable to terminate the child process: {e}"),
+                );
             }
 
-            Ok::<Vec<u8>, io::Error>(output)
-        };
-
-        match try_join!(
-            tokio::time::timeout(Duration::from_secs(1), child.wait()),
-            tokio::time::timeout(Duration::from_secs(1), stdout_task),
-        ) {
-            Ok((Ok(status), Ok(output))) if status.success() => Ok(Bytes::from(output)),
-            Ok(_) => {
-                child.kill().await?;
-
-                Err(io::Error::new(
-                    io::ErrorKind::TimedOut,
-                    "Unable to wait for the child process to terminate.",
-                ))
-            }
-            Err(_) => {
-                child.kill().await?;
+            // The pipe closes once the process group is killed, unless a descendant escaped the group.
+            let error = match stderr {
+                Some(stderr) => match tokio::time::timeout(STDERR_TIMEOUT, stderr).await {
+                    Ok(Ok(captured)) => stderr_log.annotate(error, &captured),
+                    _ => error,
+                },
+                None => error,
+            };
 
-                Err(io::Error::new(
-                    io::ErrorKind::TimedOut,
-                    "Timed out waiting for the child process.",
-                ))
-            }
-        }
+            let _ = sender.send(Err(error)).await;
+        });
+
+        Ok(output)
     }
 }
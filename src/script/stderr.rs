@@ -0,0 +1,196 @@
+use crate::context::RequestContext;
+use crate::server::ErrorLog;
+use std::io::{self, Write};
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// The largest chunk of standard error read from a script at a time.
+const CHUNK_BYTES: usize = 1024 * 4;
+
+/// Logs the standard error of scripts, tagged with the ID of the request and the path of the script.
+#[derive(Clone)]
+pub struct StderrLog {
+    log: Arc<ErrorLog>,
+    max_bytes: usize,
+    development: bool,
+}
+
+/// Identifies the invocation a script's standard error came from.
+#[derive(Clone)]
+pub struct Tag {
+    request_id: Option<String>,
+    script: Option<String>,
+}
+
+impl Tag {
+    pub fn new(context: &RequestContext) -> Self {
+        Self {
+            request_id: context.request_id().map(ToString::to_string),
+            script: context
+                .script()
+                .ok()
+                .map(|script| script.display().to_string()),
+        }
+    }
+}
+
+/// The standard error captured from a script, up to the size limit.
+#[derive(Default)]
+pub struct Captured {
+    buffer: Vec<u8>,
+    truncated: bool,
+    max_bytes: usize,
+}
+
+impl Captured {
+    fn new(max_bytes: usize) -> Self {
+        Self {
+            max_bytes,
+            ..Default::default()
+        }
+    }
+
+    /// Appends the bytes that fit within the size limit, returning the portion that was kept.
+    fn push<'a>(&mut self, bytes: &'a [u8]) -> &'a [u8] {
+        let kept = &bytes[..bytes.len().min(self.max_bytes - self.buffer.len())];
+
+        self.buffer.extend_from_slice(kept);
+        self.truncated |= kept.len() < bytes.len();
+
+        kept
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+}
+
+impl Write for Captured {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.push(bytes);
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl StderrLog {
+    pub fn new(log: Arc<ErrorLog>, max_bytes: usize, development: bool) -> Self {
+        Self {
+            log,
+            max_bytes,
+            development,
+        }
+    }
+
+    /// An empty buffer for scripts that write their standard error to memory.
+    pub fn buffer(&self) -> Captured {
+        Captured::new(self.max_bytes)
+    }
+
+    /// Reads the stream to its end, logging each line as it is written.
+    /// Output past the size limit is read but discarded, so the script never blocks on a full pipe.
+    pub async fn capture(&self, tag: Tag, mut stderr: impl AsyncRead + Unpin) -> Captured {
+        let mut captured = self.buffer();
+        let mut line = Vec::new();
+        let mut chunk = vec![0; CHUNK_BYTES];
+
+        loop {
+            let read = match stderr.read(&mut chunk).await {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(e) => {
+                    self.write(&tag, format_args!("Unable to read standard error: {e}"));
+                    break;
+                }
+            };
+
+            line.extend_from_slice(captured.push(&chunk[..read]));
+
+            while let Some(end) = line.iter().position(|b| *b == b'\n') {
+                self.write_line(&tag, &line[..end]);
+                line.drain(..=end);
+            }
+        }
+
+        self.finish(&tag, &captured, &line);
+
+        captured
+    }
+
+    /// Logs standard error that was written to memory by the script.
+    pub fn record(&self, tag: &Tag, captured: &Captured) {
+        let mut lines = captured.buffer.split(|b| *b == b'\n').peekable();
+        let mut last: &[u8] = &[];
+
+        while let Some(line) = lines.next() {
+            if lines.peek().is_some() {
+                self.write_line(tag, line);
+            } else {
+                last = line;
+            }
+        }
+
+        self.finish(tag, captured, last);
+    }
+
+    /// Includes the captured standard error in the error message when in development mode.
+    pub fn annotate(&self, error: io::Error, captured: &Captured) -> io::Error {
+        if !self.development || captured.is_empty() {
+            return error;
+        }
+
+        let stderr = String::from_utf8_lossy(&captured.buffer);
+
+        io::Error::new(
+            error.kind(),
+            format!("{error}\n\nStandard error:\n{stderr}"),
+        )
+    }
+
+    fn finish(&self, tag: &Tag, captured: &Captured, remainder: &[u8]) {
+        if !remainder.is_empty() {
+            self.write_line(tag, remainder);
+        }
+
+        if captured.truncated {
+            self.write(
+                tag,
+                format_args!(
+                    "Standard error was truncated after {} bytes.",
+                    captured.max_bytes
+                ),
+            );
+        }
+    }
+
+    fn write_line(&self, tag: &Tag, line: &[u8]) {
+        let line = String::from_utf8_lossy(line);
+
+        self.write(tag, line.trim_end_matches('\r'));
+    }
+
+    /// Writes a message about the invocation to the error log.
+    pub fn write(&self, tag: &Tag, message: impl std::fmt::Display) {
+        self.log
+            .write(tag.request_id.as_deref(), tag.script.as_deref(), message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncated() {
+        let mut captured = Captured::new(4);
+
+        assert_eq!(captured.push(b"foo"), b"foo");
+        assert_eq!(captured.push(b"bar"), b"b");
+        assert_eq!(captured.push(b"baz"), b"");
+        assert_eq!(captured.buffer, b"foob");
+        assert!(captured.truncated);
+    }
+}
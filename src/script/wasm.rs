@@ -1,77 +1,563 @@
 use crate::context::RequestContext;
-use crate::{wasm::ModuleLoader, Script};
+use crate::deadline::Deadline;
+use crate::metrics::Metrics;
+use crate::script::stderr::{Captured, StderrLog, Tag};
+use crate::script::{Input, Output};
+use crate::server::{Options, WasmLimits};
+use crate::shutdown::{self, ShutdownSignal};
+use crate::wasm::{
+    incoming_request, HttpCtx, LimitExceeded, Limiter, Loaded, ModuleCtx, ModuleLoader, Preopens,
+    State,
+};
+use crate::Script;
 use bytes::Bytes;
-use std::io;
-use wasi_common::pipe::{ReadPipe, WritePipe};
-use wasmtime_wasi::WasiCtxBuilder;
+use http_body_util::BodyExt;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc::{self, error::TrySendError, OwnedPermit};
+use tokio::sync::oneshot;
+use wasmtime::{component, InstancePre, Trap, WasmBacktrace};
+use wasmtime_wasi::preview2::pipe::MemoryInputPipe;
+use wasmtime_wasi::preview2::{
+    self, HostOutputStream, StdoutStream, StreamError, StreamResult, Subscribe,
+};
+use wasmtime_wasi_http::proxy::Proxy;
+use wasmtime_wasi_http::WasiHttpView;
+
+/// How much fuel a script consumes between yields to other tasks.
+const FUEL_YIELD_INTERVAL: u64 = 50_000;
+
+/// The number of chunks of a script's output buffered before it is made to wait on the client.
+const OUTPUT_CHUNKS: usize = 4;
+
+/// How many bytes a module may write to its standard output at a time, which becomes one chunk of output.
+const STDOUT_WRITE_BYTES: usize = 1024 * 4;
+
+/// How many bytes a component may write to its standard error at a time.
+const STDERR_WRITE_BYTES: usize = 1024 * 4;
+
+/// The resource limits of WebAssembly scripts, with overrides for specific script names.
+pub struct Limits {
+    fuel: u64,
+    max_memory_bytes: Option<u64>,
+    max_table_elements: Option<u64>,
+    deadline: Option<Duration>,
+    timeout: Duration,
+    scripts: BTreeMap<String, WasmLimits>,
+    timeouts: BTreeMap<String, Duration>,
+}
+
+impl Limits {
+    pub fn new(options: &Options) -> Self {
+        Self {
+            fuel: options.wasm_fuel,
+            max_memory_bytes: options.wasm_max_memory_bytes,
+            max_table_elements: options.wasm_max_table_elements,
+            deadline: options.wasm_deadline,
+            timeout: options.cgi_timeout,
+            scripts: options.wasm_limits.clone(),
+            timeouts: options.cgi_timeouts.clone(),
+        }
+    }
+
+    fn fuel(&self, script_name: &str) -> u64 {
+        self.script(script_name).fuel.unwrap_or(self.fuel)
+    }
+
+    fn limiter(&self, script_name: &str) -> Limiter {
+        let limits = self.script(script_name);
+
+        Limiter::new(
+            limits.max_memory_bytes.or(self.max_memory_bytes),
+            limits.max_table_elements.or(self.max_table_elements),
+        )
+    }
+
+    /// The script's own deadline, then the server's WebAssembly deadline, then the CGI script timeout.
+    fn deadline(&self, script_name: &str) -> Duration {
+        self.script(script_name)
+            .deadline
+            .or(self.deadline)
+            .or_else(|| self.timeouts.get(script_name).copied())
+            .unwrap_or(self.timeout)
+    }
+
+    fn script(&self, script_name: &str) -> WasmLimits {
+        self.scripts.get(script_name).copied().unwrap_or_default()
+    }
+}
 
 pub struct Wasm {
     loader: ModuleLoader,
+    limits: Limits,
+    preopens: Preopens,
+    shutdown: ShutdownSignal,
+    stderr: StderrLog,
+    metrics: Arc<Metrics>,
 }
 
 impl Wasm {
-    pub fn new(loader: ModuleLoader) -> Self {
-        Self { loader }
+    pub fn new(
+        loader: ModuleLoader,
+        limits: Limits,
+        preopens: Preopens,
+        shutdown: ShutdownSignal,
+        stderr: StderrLog,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        Self {
+            loader,
+            limits,
+            preopens,
+            shutdown,
+            stderr,
+            metrics,
+        }
+    }
+
+    /// Runs a core module as a CGI script, with the request body as its standard input.
+    /// Its standard output is sent on to the client as the module writes it,
+    /// and the module waits while the client is slower than the module.
+    async fn run(
+        &self,
+        module: InstancePre<State>,
+        context: RequestContext,
+        body: Bytes,
+    ) -> io::Result<Output> {
+        let script_name = context.script_name().to_string();
+        let deadline = Arc::new(Deadline::new(self.limits.deadline(&script_name)));
+        let (sender, output) = Output::channel(OUTPUT_CHUNKS);
+        let stderr = SharedStderr::new(self.stderr.buffer());
+        let mut builder = preview2::WasiCtxBuilder::new();
+
+        builder
+            .args(&context.arguments().collect::<Vec<_>>())
+            .envs(&context.variables().collect::<Vec<_>>())
+            .stdin(MemoryInputPipe::new(body))
+            .stdout(StdoutChannel::new(sender.clone(), deadline.clone()))
+            .stderr(stderr.clone());
+
+        // Removed once the invocation finishes.
+        let scratch = self.preopens.apply(&mut builder)?;
+        let tag = Tag::new(&context);
+        let fuel = self.limits.fuel(&script_name);
+        let state = State {
+            wasi: ModuleCtx::new(builder.build()),
+            limiter: self.limits.limiter(&script_name),
+        };
+        let mut store = self.loader.new_store(state, deadline);
+
+        store.set_fuel(fuel).map_err(io::Error::other)?;
+        store
+            .fuel_async_yield_interval(Some(FUEL_YIELD_INTERVAL))
+            .map_err(io::Error::other)?;
+
+        let metrics = self.metrics.clone();
+        let log = self.stderr.clone();
+        let shutdown = self.shutdown.clone();
+
+        tokio::spawn(async move {
+            let _scratch = scratch;
+            let result = async {
+                let instance = module
+                    .instantiate_async(&mut store)
+                    .await
+                    .map_err(|e| script_error(e, &metrics, &log, &script_name, &tag))?;
+                let mut function = instance.get_typed_func::<(), ()>(&mut store, "");
+
+                if function.is_err() {
+                    function = instance.get_typed_func(&mut store, "_start");
+                }
+
+                let function = function.map_err(io::Error::other)?;
+
+                // The store is dropped along with the call if the server terminates the script.
+                tokio::select! {
+                    result = function.call_async(&mut store, ()) => match result {
+                        Err(e) if exit_code(&e) == Some(0) => Ok(()),
+                        result => result.map_err(|e| script_error(e, &metrics, &log, &script_name, &tag)),
+                    },
+                    _ = shutdown.terminating() => Err(shutdown::interrupted()),
+                }
+            }
+            .await;
+
+            if let Ok(remaining) = store.get_fuel() {
+                metrics.fuel_consumed(&script_name, fuel.saturating_sub(remaining));
+            }
+
+            // Closes the standard output, which ends the response once the rest of it is sent.
+            drop(store);
+
+            let captured = stderr.into_inner();
+
+            log.record(&tag, &captured);
+
+            if let Err(e) = result {
+                let _ = sender.send(Err(log.annotate(e, &captured))).await;
+            }
+        });
+
+        Ok(output)
+    }
+
+    /// Passes the request to a component's `wasi:http/incoming-handler`.
+    /// The response head is written as a CGI header block once the component sets it,
+    /// followed by the response body as the component writes it.
+    async fn handle(
+        &self,
+        component: component::InstancePre<State<HttpCtx>>,
+        context: RequestContext,
+        body: Bytes,
+    ) -> io::Result<Output> {
+        let stderr = SharedStderr::new(self.stderr.buffer());
+        let mut builder = preview2::WasiCtxBuilder::new();
+
+        builder
+            .args(&context.arguments().collect::<Vec<_>>())
+            .envs(&context.variables().collect::<Vec<_>>())
+            .stderr(stderr.clone());
+
+        let scratch = self.preopens.apply(&mut builder)?;
+        let script_name = context.script_name().to_string();
+        let tag = Tag::new(&context);
+        let fuel = self.limits.fuel(&script_name);
+        let state = State {
+            wasi: HttpCtx::new(builder.build()),
+            limiter: self.limits.limiter(&script_name),
+        };
+        let deadline = Arc::new(Deadline::new(self.limits.deadline(&script_name)));
+        let mut store = self.loader.new_store(state, deadline.clone());
+
+        store.set_fuel(fuel).map_err(io::Error::other)?;
+        store
+            .fuel_async_yield_interval(Some(FUEL_YIELD_INTERVAL))
+            .map_err(io::Error::other)?;
+
+        let (sender, receiver) = oneshot::channel();
+        let request = incoming_request(&context, body)?;
+        let request = store
+            .data_mut()
+            .new_incoming_request(request)
+            .map_err(io::Error::other)?;
+        let response = store
+            .data_mut()
+            .new_response_outparam(sender)
+            .map_err(io::Error::other)?;
+        let (proxy, _) = Proxy::instantiate_pre(&mut store, &component)
+            .await
+            .map_err(|e| script_error(e, &self.metrics, &self.stderr, &script_name, &tag))?;
+
+        let metrics = self.metrics.clone();
+        let log = self.stderr.clone();
+        let shutdown = self.shutdown.clone();
+
+        // The component keeps running after setting the response, in order to write the response body.
+        let call = tokio::spawn(async move {
+            let _scratch = scratch;
+            let handler = proxy.wasi_http_incoming_handler();
+            let result = tokio::select! {
+                result = handler.call_handle(&mut store, request, response) => match result {
+                    Err(e) if exit_code(&e) == Some(0) => Ok(()),
+                    result => result.map_err(|e| script_error(e, &metrics, &log, &script_name, &tag)),
+                },
+                _ = shutdown.terminating() => Err(shutdown::interrupted()),
+            };
+
+            if let Ok(remaining) = store.get_fuel() {
+                metrics.fuel_consumed(&script_name, fuel.saturating_sub(remaining));
+            }
+
+            drop(store);
+
+            let captured = stderr.into_inner();
+
+            log.record(&tag, &captured);
+            result.map_err(|e| log.annotate(e, &captured))
+        });
+
+        let response = match receiver.await {
+            Ok(Ok(response)) => response,
+            Ok(Err(code)) => {
+                return Err(io::Error::other(format!(
+                    "The component responded with an error: {code:?}"
+                )))
+            }
+            // The component returned or trapped without setting a response.
+            Err(_) => {
+                return Err(match call.await {
+                    Ok(Err(e)) => e,
+                    Ok(Ok(())) => io::Error::other("The component did not set a response."),
+                    Err(e) => io::Error::other(e),
+                })
+            }
+        };
+
+        let (head, mut body) = response.into_parts();
+        let (sender, mut output) = Output::channel(OUTPUT_CHUNKS);
+
+        output.unread(header_block(&head));
+
+        tokio::spawn(async move {
+            while let Some(frame) = body.frame().await {
+                let chunk = match frame {
+                    Ok(frame) => match frame.into_data() {
+                        Ok(data) => Ok(data),
+                        Err(_) => continue,
+                    },
+                    Err(code) => Err(io::Error::other(format!(
+                        "The component failed to write the response body: {code:?}"
+                    ))),
+                };
+                let failed = chunk.is_err();
+                let paused = deadline.pause();
+
+                // Stop reading the body once the client is gone, so the component sees its stream close.
+                if sender.send(chunk).await.is_err() || failed {
+                    break;
+                }
+
+                drop(paused);
+            }
+
+            drop(body);
+
+            if let Ok(Err(e)) = call.await {
+                let _ = sender.send(Err(e)).await;
+            }
+        });
+
+        Ok(output)
     }
 }
 
 impl Script for Wasm {
-    async fn invoke(&self, context: RequestContext, body: Bytes) -> io::Result<Bytes> {
+    async fn invoke(&self, context: RequestContext, mut input: Input) -> io::Result<Output> {
+        let body = input.buffer().await?;
         let file = context.script()?;
-        let module = self.loader.load(file).await?;
 
-        let stdout = WritePipe::new_in_memory();
-        let mut builder = WasiCtxBuilder::new();
+        match self.loader.load(file).await? {
+            Loaded::Module(module) => self.run(module, context, body).await,
+            Loaded::Component(component) => self.handle(component, context, body).await,
+        }
+    }
+}
+
+/// Maps a script failing to an error with a distinct status code, logging why it failed.
+/// Exiting with a non-zero code is a `502 Bad Gateway`, running out of fuel or finding the instance pool
+/// exhausted a `503 Service Unavailable`, missing the deadline a `504 Gateway Timeout`, and growing memory or tables past their limit
+/// or trapping a `500 Internal Server Error`.
+/// Backtraces are only written to the error log, since they reveal the internals of the script.
+fn script_error(
+    error: wasmtime::Error,
+    metrics: &Metrics,
+    stderr: &StderrLog,
+    script_name: &str,
+    tag: &Tag,
+) -> io::Error {
+    if let Some(code) = exit_code(&error) {
+        let error = io::Error::new(
+            io::ErrorKind::ConnectionAborted,
+            format!("The script exited with code {code}."),
+        );
 
-        for argument in context.arguments() {
-            builder
-                .arg(argument)
-                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        metrics.exit_failure(script_name);
+        stderr.write(tag, &error);
+
+        return error;
+    }
+
+    if crate::wasm::is_pool_exhausted(&error) {
+        let mapped = io::Error::new(
+            io::ErrorKind::QuotaExceeded,
+            "No WebAssembly instance is available.",
+        );
+
+        stderr.write(tag, format!("{mapped} {}", error.root_cause()));
+
+        return mapped;
+    }
+
+    let trap = error.downcast_ref::<Trap>();
+
+    if trap.is_some() {
+        metrics.trap(script_name);
+    }
+
+    let mapped = match (trap, error.downcast_ref::<LimitExceeded>()) {
+        (Some(Trap::OutOfFuel), _) => {
+            io::Error::new(io::ErrorKind::QuotaExceeded, "The script ran out of fuel.")
+        }
+        (Some(Trap::Interrupt), _) => {
+            metrics.timeout(script_name);
+            io::Error::new(
+                io::ErrorKind::TimedOut,
+                "The script did not finish before its deadline.",
+            )
         }
+        (_, Some(limit)) => io::Error::new(io::ErrorKind::OutOfMemory, limit.to_string()),
+        (Some(trap), None) => io::Error::other(format!("The script failed with a {trap}.")),
+        (None, None) => io::Error::other(error.root_cause().to_string()),
+    };
 
-        for (variable, value) in context.variables() {
-            builder
-                .env(variable, value)
-                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    stderr.write(tag, &mapped);
+
+    if let Some(backtrace) = error.downcast_ref::<WasmBacktrace>() {
+        for line in backtrace.to_string().lines() {
+            stderr.write(tag, line);
         }
+    }
 
-        let wasi = builder
-            .stdin(Box::new(ReadPipe::from(body.as_ref())))
-            .stdout(Box::new(stdout.clone()))
-            .inherit_stderr()
-            .build();
+    mapped
+}
 
-        let mut store = self.loader.new_store(wasi);
+/// The code a script exited with through WASI, if that is why it stopped.
+fn exit_code(error: &wasmtime::Error) -> Option<i32> {
+    error.downcast_ref::<preview2::I32Exit>().map(|exit| exit.0)
+}
 
-        store
-            .set_fuel(1_000_000)
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-        store
-            .fuel_async_yield_interval(Some(50_000))
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+/// The CGI header block of a component's response, with the status code in a `Status` header.
+fn header_block(head: &http::response::Parts) -> Bytes {
+    let mut block = format!("Status: {}\r\n", head.status.as_u16()).into_bytes();
 
-        let instance = module
-            .instantiate_async(&mut store)
-            .await
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-        let mut function = instance.get_typed_func(&mut store, "");
+    for (name, value) in &head.headers {
+        block.extend_from_slice(name.as_str().as_bytes());
+        block.extend_from_slice(b": ");
+        block.extend_from_slice(value.as_bytes());
+        block.extend_from_slice(b"\r\n");
+    }
 
-        if function.is_err() {
-            function = instance.get_typed_func(&mut store, "_start");
+    block.extend_from_slice(b"\r\n");
+
+    Bytes::from(block)
+}
+
+/// The standard output of a core module, sent on to the client as the module writes it.
+/// The module waits for room in the output channel while the client is slower than the module,
+/// with its deadline paused in the meantime.
+struct StdoutChannel {
+    sender: mpsc::Sender<io::Result<Bytes>>,
+    deadline: Arc<Deadline>,
+    /// Room for the next write, reserved once the module checks whether it may write.
+    permit: Option<OwnedPermit<io::Result<Bytes>>>,
+}
+
+impl StdoutChannel {
+    fn new(sender: mpsc::Sender<io::Result<Bytes>>, deadline: Arc<Deadline>) -> Self {
+        Self {
+            sender,
+            deadline,
+            permit: None,
         }
+    }
+}
 
-        function
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?
-            .call_async(&mut store, ())
-            .await
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+impl StdoutStream for StdoutChannel {
+    fn stream(&self) -> Box<dyn HostOutputStream> {
+        Box::new(Self::new(self.sender.clone(), self.deadline.clone()))
+    }
+
+    fn isatty(&self) -> bool {
+        false
+    }
+}
 
-        drop(store);
+impl HostOutputStream for StdoutChannel {
+    fn write(&mut self, bytes: Bytes) -> StreamResult<()> {
+        let permit = self
+            .permit
+            .take()
+            .ok_or_else(|| StreamError::trap("write not permitted"))?;
 
-        let bytes = stdout
-            .try_into_inner()
-            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        permit.send(Ok(bytes));
 
-        Ok(Bytes::from(bytes.into_inner()))
+        Ok(())
     }
+
+    fn flush(&mut self) -> StreamResult<()> {
+        Ok(())
+    }
+
+    /// The module sees its standard output close once the client is gone.
+    fn check_write(&mut self) -> StreamResult<usize> {
+        if self.permit.is_none() {
+            match self.sender.clone().try_reserve_owned() {
+                Ok(permit) => self.permit = Some(permit),
+                Err(TrySendError::Full(_)) => return Ok(0),
+                Err(TrySendError::Closed(_)) => return Err(StreamError::Closed),
+            }
+        }
+
+        Ok(STDOUT_WRITE_BYTES)
+    }
+}
+
+#[async_trait::async_trait]
+impl Subscribe for StdoutChannel {
+    async fn ready(&mut self) {
+        if self.permit.is_some() {
+            return;
+        }
+
+        let _paused = self.deadline.pause();
+
+        // A closed channel is reported by the next check for room instead.
+        if let Ok(permit) = self.sender.clone().reserve_owned().await {
+            self.permit = Some(permit);
+        }
+    }
+}
+
+/// The standard error of a script, captured in memory up to the size limit.
+#[derive(Clone)]
+struct SharedStderr(Arc<Mutex<Captured>>);
+
+impl SharedStderr {
+    fn new(captured: Captured) -> Self {
+        Self(Arc::new(Mutex::new(captured)))
+    }
+
+    /// Takes the captured standard error, once the component's store has been dropped.
+    fn into_inner(self) -> Captured {
+        match Arc::try_unwrap(self.0) {
+            Ok(captured) => captured.into_inner().unwrap_or_else(|e| e.into_inner()),
+            Err(shared) => std::mem::take(&mut *shared.lock().unwrap_or_else(|e| e.into_inner())),
+        }
+    }
+}
+
+impl StdoutStream for SharedStderr {
+    fn stream(&self) -> Box<dyn HostOutputStream> {
+        Box::new(self.clone())
+    }
+
+    fn isatty(&self) -> bool {
+        false
+    }
+}
+
+impl HostOutputStream for SharedStderr {
+    fn write(&mut self, bytes: Bytes) -> StreamResult<()> {
+        let mut captured = self.0.lock().unwrap_or_else(|e| e.into_inner());
+
+        // Output past the size limit is discarded, so the script never fails to write its standard error.
+        let _ = captured.write_all(&bytes);
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> StreamResult<()> {
+        Ok(())
+    }
+
+    fn check_write(&mut self) -> StreamResult<usize> {
+        Ok(STDERR_WRITE_BYTES)
+    }
+}
+
+#[async_trait::async_trait]
+impl Subscribe for SharedStderr {
+    async fn ready(&mut self) {}
 }
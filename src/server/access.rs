@@ -0,0 +1,362 @@
+use crate::metrics::Metrics;
+use crate::server::log::{civil, LogWriter, Rfc3339};
+use bytes::Bytes;
+use hyper::body::{Body, Frame, SizeHint};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::{mpsc, Arc};
+use std::task::{Context, Poll};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+
+/// The number of entries queued for the writer thread before new entries are dropped.
+const QUEUED_ENTRIES: usize = 1024 * 8;
+
+/// The format of access log entries.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// The Apache Common Log Format.
+    #[default]
+    Common,
+    /// The Apache Combined Log Format, which adds the referer and user agent to the common format.
+    Combined,
+    /// One JSON object per line, including the script path and request duration.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "common" => Ok(Self::Common),
+            "combined" => Ok(Self::Combined),
+            "json" => Ok(Self::Json),
+            _ => Err(format!(
+                "expected one of `common`, `combined` or `json`, found `{value}`"
+            )),
+        }
+    }
+}
+
+/// Writes an entry for every request once its response body has been sent.
+/// Entries are formatted and written on a dedicated thread, so responses never wait on the log's destination.
+/// Entries are dropped and counted in the metrics while the queue for the thread is full.
+pub struct AccessLog {
+    sender: Option<mpsc::SyncSender<Entry>>,
+    writer: Option<JoinHandle<()>>,
+    metrics: Arc<Metrics>,
+}
+
+impl AccessLog {
+    pub fn stdout(format: LogFormat, metrics: Arc<Metrics>) -> io::Result<Self> {
+        Self::spawn(format, LogWriter::Stdout, metrics)
+    }
+
+    /// Appends entries to the file, rotating it once it grows past `max_bytes`.
+    pub fn file(
+        format: LogFormat,
+        path: PathBuf,
+        max_bytes: u64,
+        max_files: usize,
+        metrics: Arc<Metrics>,
+    ) -> io::Result<Self> {
+        Self::spawn(
+            format,
+            LogWriter::file(path, max_bytes, max_files)?,
+            metrics,
+        )
+    }
+
+    fn spawn(format: LogFormat, writer: LogWriter, metrics: Arc<Metrics>) -> io::Result<Self> {
+        let (sender, receiver) = mpsc::sync_channel::<Entry>(QUEUED_ENTRIES);
+        let writer = thread::Builder::new()
+            .name("access-log".to_string())
+            .spawn(move || {
+                for entry in receiver {
+                    Self::write(format, &writer, &entry);
+                }
+            })?;
+
+        Ok(Self {
+            sender: Some(sender),
+            writer: Some(writer),
+            metrics,
+        })
+    }
+
+    /// Queues the entry for the writer thread without blocking, dropping it if the queue is full.
+    pub fn send(&self, entry: Entry) {
+        if let Some(sender) = &self.sender {
+            if let Err(mpsc::TrySendError::Full(_)) = sender.try_send(entry) {
+                self.metrics.access_log_dropped();
+            }
+        }
+    }
+
+    fn write(format: LogFormat, writer: &LogWriter, entry: &Entry) {
+        let line = match format {
+            LogFormat::Common => format!("{}\n", Common(entry)),
+            LogFormat::Combined => format!("{}\n", Combined(entry)),
+            LogFormat::Json => match serde_json::to_string(entry) {
+                Ok(json) => format!("{json}\n"),
+                Err(e) => {
+                    eprintln!("Unable to serialize an access log entry: {e}");
+                    return;
+                }
+            },
+        };
+
+        if let Err(e) = writer.write(line.as_bytes()) {
+            eprintln!("Unable to write to the access log: {e}");
+        }
+    }
+}
+
+/// Waits for the queued entries to be written, so none are lost when the server exits.
+impl Drop for AccessLog {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+/// The details of a request to log once its response is complete.
+#[derive(Debug, Serialize)]
+pub struct Entry {
+    #[serde(serialize_with = "rfc3339")]
+    pub time: SystemTime,
+    pub request_id: String,
+    pub remote_address: String,
+    pub method: String,
+    pub uri: String,
+    pub protocol: &'static str,
+    pub status: u16,
+    pub bytes: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub referer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub script: Option<String>,
+    #[serde(rename = "duration_ms", serialize_with = "milliseconds")]
+    pub duration: Duration,
+}
+
+struct Common<'a>(&'a Entry);
+
+impl fmt::Display for Common<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let entry = self.0;
+        let bytes = if entry.bytes == 0 {
+            "-".to_string()
+        } else {
+            entry.bytes.to_string()
+        };
+
+        write!(
+            f,
+            "{} - - [{}] \"{} {} {}\" {} {}",
+            entry.remote_address,
+            Timestamp(entry.time),
+            entry.method.escape_default(),
+            entry.uri.escape_default(),
+            entry.protocol,
+            entry.status,
+            bytes
+        )
+    }
+}
+
+struct Combined<'a>(&'a Entry);
+
+impl fmt::Display for Combined<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let entry = self.0;
+
+        write!(
+            f,
+            "{} \"{}\" \"{}\"",
+            Common(entry),
+            entry.referer.as_deref().unwrap_or("-").escape_default(),
+            entry.user_agent.as_deref().unwrap_or("-").escape_default()
+        )
+    }
+}
+
+/// A timestamp in the Common Log Format (e.g., `10/Oct/2000:13:55:36 +0000`).
+struct Timestamp(SystemTime);
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const MONTHS: [&str; 12] = [
+            "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+        ];
+
+        let (year, month, day, hour, minute, second) = civil(self.0);
+
+        write!(
+            f,
+            "{day:02}/{}/{year}:{hour:02}:{minute:02}:{second:02} +0000",
+            MONTHS[month as usize - 1]
+        )
+    }
+}
+
+fn rfc3339<S: serde::Serializer>(time: &SystemTime, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&Rfc3339(*time))
+}
+
+fn milliseconds<S: serde::Serializer>(
+    duration: &Duration,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64() * 1_000.0)
+}
+
+/// A response body that counts the bytes sent and queues the access log entry once it is dropped.
+pub struct Logged<B> {
+    body: B,
+    entry: Option<Entry>,
+    started: Instant,
+    log: Arc<AccessLog>,
+}
+
+impl<B> Logged<B> {
+    pub fn new(body: B, entry: Entry, started: Instant, log: Arc<AccessLog>) -> Self {
+        Self {
+            body,
+            entry: Some(entry),
+            started,
+            log,
+        }
+    }
+}
+
+impl<B> Body for Logged<B>
+where
+    B: Body<Data = Bytes> + Unpin,
+{
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let poll = Pin::new(&mut self.body).poll_frame(cx);
+
+        if let Poll::Ready(Some(Ok(frame))) = &poll {
+            if let (Some(data), Some(entry)) = (frame.data_ref(), self.entry.as_mut()) {
+                entry.bytes += data.len() as u64;
+            }
+        }
+
+        poll
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.body.size_hint()
+    }
+}
+
+impl<B> Drop for Logged<B> {
+    fn drop(&mut self) {
+        if let Some(mut entry) = self.entry.take() {
+            entry.duration = self.started.elapsed();
+            self.log.send(entry);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    fn entry() -> Entry {
+        Entry {
+            time: UNIX_EPOCH + Duration::from_secs(971_186_136),
+            request_id: "0123456789abcdef".to_string(),
+            remote_address: "127.0.0.1".to_string(),
+            method: "GET".to_string(),
+            uri: "/cgi-bin/hello.cgi?foo".to_string(),
+            protocol: "HTTP/1.1",
+            status: 200,
+            bytes: 2326,
+            referer: None,
+            user_agent: Some("curl/8.0".to_string()),
+            script: Some("/var/www/cgi-bin/hello.cgi".to_string()),
+            duration: Duration::from_millis(12),
+        }
+    }
+
+    #[test]
+    fn full_queue() {
+        let metrics = Arc::new(Metrics::default());
+        // A queue whose writer never catches up.
+        let (sender, _receiver) = mpsc::sync_channel(1);
+        let log = AccessLog {
+            sender: Some(sender),
+            writer: None,
+            metrics: metrics.clone(),
+        };
+
+        log.send(entry());
+        log.send(entry());
+        log.send(entry());
+
+        assert!(metrics
+            .render()
+            .contains("tortuga_access_log_dropped_total 2\n"));
+    }
+
+    #[test]
+    fn common() {
+        assert_eq!(
+            Common(&entry()).to_string(),
+            "127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET /cgi-bin/hello.cgi?foo HTTP/1.1\" 200 2326"
+        );
+    }
+
+    #[test]
+    fn common_escapes_request_line() {
+        let entry = Entry {
+            uri: "/search?q=\"quoted\"".to_string(),
+            ..entry()
+        };
+
+        assert!(Common(&entry)
+            .to_string()
+            .contains("\"GET /search?q=\\\"quoted\\\" HTTP/1.1\""));
+    }
+
+    #[test]
+    fn combined() {
+        assert!(Combined(&entry())
+            .to_string()
+            .ends_with("200 2326 \"-\" \"curl/8.0\""));
+    }
+
+    #[test]
+    fn json() {
+        let json = serde_json::to_string(&entry()).unwrap();
+
+        assert!(json.contains("\"time\":\"2000-10-10T13:55:36Z\""));
+        assert!(json.contains("\"script\":\"/var/www/cgi-bin/hello.cgi\""));
+        assert!(json.contains("\"duration_ms\":12.0"));
+        assert!(!json.contains("referer"));
+    }
+}
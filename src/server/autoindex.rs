@@ -0,0 +1,187 @@
+use crate::server::log::Rfc3339;
+use serde::Serialize;
+use std::fmt::Write;
+use std::io;
+use std::path::Path;
+use std::time::SystemTime;
+
+/// A file or directory shown in a directory listing.
+#[derive(Debug, Serialize)]
+pub struct Entry {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: EntryKind,
+    pub size: u64,
+    #[serde(serialize_with = "rfc3339")]
+    pub modified: Option<SystemTime>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    Directory,
+    File,
+}
+
+/// Reads the entries of a directory, with subdirectories first and each group sorted by name.
+/// Hidden entries (e.g., `.git`) and entries that cannot be read are left out.
+pub async fn entries(directory: &Path) -> io::Result<Vec<Entry>> {
+    let mut reader = tokio::fs::read_dir(directory).await?;
+    let mut entries = Vec::new();
+
+    while let Some(entry) = reader.next_entry().await? {
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+
+        if name.starts_with('.') {
+            continue;
+        }
+
+        // Links are listed as what they point to; whether they may be followed is decided when requested.
+        let Ok(metadata) = tokio::fs::metadata(entry.path()).await else {
+            continue;
+        };
+        let kind = if metadata.is_dir() {
+            EntryKind::Directory
+        } else {
+            EntryKind::File
+        };
+
+        entries.push(Entry {
+            name,
+            kind,
+            size: if metadata.is_dir() { 0 } else { metadata.len() },
+            modified: metadata.modified().ok(),
+        });
+    }
+
+    entries.sort_by(|a, b| {
+        (a.kind != EntryKind::Directory, &a.name).cmp(&(b.kind != EntryKind::Directory, &b.name))
+    });
+
+    Ok(entries)
+}
+
+/// Renders the entries as an HTML page for the directory at the request path, which ends with a `/`.
+pub fn html(path: &str, entries: &[Entry]) -> String {
+    let title = escape(path);
+    let mut page = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Index of {title}</title>\n</head>\n<body>\n<h1>Index of {title}</h1>\n<table>\n<tr><th>Name</th><th>Size</th><th>Last Modified</th></tr>\n"
+    );
+
+    if path != "/" {
+        page.push_str("<tr><td><a href=\"../\">../</a></td><td></td><td></td></tr>\n");
+    }
+
+    for entry in entries {
+        let suffix = if entry.kind == EntryKind::Directory {
+            "/"
+        } else {
+            ""
+        };
+        let size = match entry.kind {
+            EntryKind::Directory => String::new(),
+            EntryKind::File => entry.size.to_string(),
+        };
+        let modified = entry
+            .modified
+            .map(|modified| Rfc3339(modified).to_string())
+            .unwrap_or_default();
+
+        let _ = writeln!(
+            page,
+            "<tr><td><a href=\"{}{suffix}\">{}{suffix}</a></td><td>{size}</td><td>{modified}</td></tr>",
+            escape(&encode(&entry.name)),
+            escape(&entry.name),
+        );
+    }
+
+    page.push_str("</table>\n</body>\n</html>\n");
+    page
+}
+
+/// Renders the entries as a JSON array.
+pub fn json(entries: &[Entry]) -> io::Result<String> {
+    serde_json::to_string(entries).map_err(io::Error::other)
+}
+
+fn rfc3339<S: serde::Serializer>(
+    modified: &Option<SystemTime>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match modified {
+        Some(modified) => serializer.collect_str(&Rfc3339(*modified)),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Escapes text for HTML content and attribute values.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Percent-encodes a file name for use as a relative link, so names like `a?b` or `#1` are not misread.
+fn encode(name: &str) -> String {
+    let mut encoded = String::with_capacity(name.len());
+
+    for byte in name.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => {
+                let _ = write!(encoded, "%{byte:02X}");
+            }
+        }
+    }
+
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn listing() {
+        let directory = tempfile::tempdir().unwrap();
+
+        std::fs::write(directory.path().join("b <1>.txt"), "Hello").unwrap();
+        std::fs::write(directory.path().join(".hidden"), "Secret").unwrap();
+        std::fs::create_dir(directory.path().join("z")).unwrap();
+
+        let entries = entries(directory.path()).await.unwrap();
+        let names: Vec<_> = entries.iter().map(|entry| entry.name.as_str()).collect();
+
+        assert_eq!(names, vec!["z", "b <1>.txt"]);
+        assert_eq!(entries[1].size, 5);
+
+        let page = html("/docs/", &entries);
+
+        assert!(page.contains("<title>Index of /docs/</title>"));
+        assert!(page.contains("<a href=\"../\">../</a>"));
+        assert!(page.contains("<a href=\"z/\">z/</a>"));
+        assert!(page.contains("<a href=\"b%20%3C1%3E.txt\">b &lt;1&gt;.txt</a></td><td>5</td>"));
+
+        let json = json(&entries).unwrap();
+
+        assert!(
+            json.starts_with("[{\"name\":\"z\",\"type\":\"directory\",\"size\":0,\"modified\":\"")
+        );
+        assert!(json.contains("{\"name\":\"b <1>.txt\",\"type\":\"file\",\"size\":5,"));
+    }
+}
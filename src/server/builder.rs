@@ -0,0 +1,194 @@
+use crate::context::{ScriptMapping, ServerContext};
+use crate::metrics::Metrics;
+use crate::script::{self, BoxedScript, Script, StderrLog};
+use crate::server::log::LogWriter;
+use crate::server::{AccessLog, ErrorLog, Options, Router, Server};
+use crate::shutdown::ShutdownHandle;
+use crate::tls::CertificateLoader;
+use crate::wasm::{ModuleLoader, Preopens};
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use tokio::net::TcpListener;
+
+/// Configures a [`Server`] for embedding in another application.
+///
+/// ```no_run
+/// # async fn run() -> std::io::Result<()> {
+/// use tortuga::{Options, ServerBuilder};
+///
+/// let listener = tokio::net::TcpListener::bind("127.0.0.1:8080").await?;
+/// let server = ServerBuilder::new(Options::default())
+///     .listener(listener)
+///     .build()
+///     .await?;
+///
+/// server.serve().await
+/// # }
+/// ```
+pub struct ServerBuilder {
+    options: Options,
+    listener: Option<TcpListener>,
+    extensions: Vec<(String, Box<dyn BoxedScript>)>,
+    prefixes: Vec<(String, Box<dyn BoxedScript>)>,
+}
+
+impl ServerBuilder {
+    pub fn new(options: Options) -> Self {
+        Self {
+            options,
+            listener: None,
+            extensions: Vec::new(),
+            prefixes: Vec::new(),
+        }
+    }
+
+    /// Accepts connections from an existing listener instead of binding to the configured hostname and port.
+    pub fn listener(mut self, listener: TcpListener) -> Self {
+        self.listener = Some(listener);
+        self
+    }
+
+    /// Invokes the script for files in the CGI bin directory with the given extension (e.g., `py`).
+    /// Registered extensions take precedence over the built-in `cgi` and `wcgi` extensions.
+    pub fn extension<S>(mut self, extension: impl Into<String>, script: S) -> Self
+    where
+        S: Script + Send + Sync + 'static,
+    {
+        self.extensions.push((extension.into(), Box::new(script)));
+        self
+    }
+
+    /// Invokes the script for every request path that starts with the given prefix (e.g., `/api/`).
+    /// The script is given the prefix as its `SCRIPT_NAME` and the rest of the path as its `PATH_INFO`.
+    pub fn prefix<S>(mut self, prefix: impl Into<String>, script: S) -> Self
+    where
+        S: Script + Send + Sync + 'static,
+    {
+        self.prefixes.push((prefix.into(), Box::new(script)));
+        self
+    }
+
+    /// Binds the server to its listener.
+    pub async fn build(mut self) -> io::Result<Server> {
+        self.options.resolve_paths()?;
+
+        let tls = match (&self.options.tls_cert, &self.options.tls_key) {
+            (Some(certificate), Some(key)) => {
+                Some(CertificateLoader::new(certificate.clone(), key.clone())?)
+            }
+            _ => None,
+        };
+
+        let listener = match self.listener.take() {
+            Some(listener) => listener,
+            None => bind(&self.options).await?,
+        };
+        let address = listener.local_addr()?;
+        let metrics_listener = match self.options.metrics_address {
+            Some(address) => Some(TcpListener::bind(address).await?),
+            None => None,
+        };
+
+        let preload_wasm = self.options.preload_wasm;
+        let scan_interval = self.options.scan_interval;
+        let grace_period = self.options.shutdown_grace_period;
+        let (context, loader) = self.into_context(address)?;
+
+        Ok(Server {
+            preload_wasm,
+            scan_interval,
+            grace_period,
+            shutdown: context.shutdown_handle().clone(),
+            context,
+            listener,
+            metrics_listener,
+            loader,
+            tls,
+        })
+    }
+
+    /// Creates a router to mount inside another application's HTTP server, without binding to a listener.
+    /// WebAssembly modules are loaded on demand, since the CGI bin directory is not scanned.
+    pub fn build_router(mut self) -> io::Result<Router> {
+        self.options.resolve_paths()?;
+
+        let address = SocketAddr::from((Ipv4Addr::UNSPECIFIED, self.options.port));
+        let (context, _) = self.into_context(address)?;
+
+        Ok(Router::detached(context))
+    }
+
+    fn into_context(self, address: SocketAddr) -> io::Result<(Arc<ServerContext>, ModuleLoader)> {
+        let options = self.options;
+        let metrics = Arc::new(Metrics::default());
+        let error_log = Arc::new(ErrorLog::new(match &options.error_log {
+            Some(path) => LogWriter::file(path.clone(), u64::MAX, 0)?,
+            None => LogWriter::Stderr,
+        }));
+        let loader = ModuleLoader::new(&options, metrics.clone(), error_log.clone())
+            .map_err(io::Error::other)?;
+        let stderr = StderrLog::new(
+            error_log.clone(),
+            options.stderr_max_bytes,
+            options.development,
+        );
+
+        let shutdown = ShutdownHandle::new();
+        let process = script::Process::new(
+            options.cgi_timeout,
+            options.cgi_timeouts.clone(),
+            shutdown.signal(),
+            stderr.clone(),
+            metrics.clone(),
+        );
+        let wasm = script::Wasm::new(
+            loader.clone(),
+            script::Limits::new(&options),
+            Preopens::new(&options)?,
+            shutdown.signal(),
+            stderr,
+            metrics.clone(),
+        );
+        let mut scripts = ScriptMapping::new(process, wasm);
+
+        for (extension, script) in self.extensions {
+            scripts.add_extension(extension, script);
+        }
+
+        for (prefix, script) in self.prefixes {
+            scripts.add_prefix(prefix, script);
+        }
+
+        let access_log = match (options.access_log_format, &options.access_log) {
+            (Some(format), Some(path)) => Some(AccessLog::file(
+                format,
+                path.clone(),
+                options.access_log_max_bytes,
+                options.access_log_max_files,
+                metrics.clone(),
+            )?),
+            (Some(format), None) => Some(AccessLog::stdout(format, metrics.clone())?),
+            (None, _) => None,
+        };
+
+        let context = ServerContext::new(
+            address, options, scripts, shutdown, access_log, error_log, metrics,
+        );
+
+        Ok((Arc::new(context), loader))
+    }
+}
+
+async fn bind(options: &Options) -> io::Result<TcpListener> {
+    let mut addresses =
+        tokio::net::lookup_host(format!("{}:{}", options.hostname, options.port)).await?;
+    let address = addresses.next().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::AddrNotAvailable,
+            "unable to resolve interface to a local address",
+        )
+    })?;
+
+    TcpListener::bind(address).await
+}
@@ -0,0 +1,188 @@
+use http::{header, HeaderMap, HeaderValue};
+use std::fs::Metadata;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The `ETag` and `Last-Modified` of a static file, which clients use to revalidate their cached copy.
+pub struct Validators {
+    etag: String,
+    last_modified: Option<SystemTime>,
+}
+
+impl Validators {
+    /// Derives the validators from the file's length and modification time, so they change whenever the file is written.
+    pub fn new(metadata: &Metadata) -> Self {
+        let last_modified = metadata.modified().ok();
+        let modified = last_modified
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or_default();
+
+        Self {
+            etag: format!(
+                "\"{:x}-{:x}.{:x}\"",
+                metadata.len(),
+                modified.as_secs(),
+                modified.subsec_nanos()
+            ),
+            last_modified,
+        }
+    }
+
+    pub fn insert(&self, headers: &mut HeaderMap) {
+        if let Ok(etag) = HeaderValue::try_from(&self.etag) {
+            headers.insert(header::ETAG, etag);
+        }
+
+        if let Some(last_modified) = self.last_modified {
+            if let Ok(value) = HeaderValue::try_from(httpdate::fmt_http_date(last_modified)) {
+                headers.insert(header::LAST_MODIFIED, value);
+            }
+        }
+    }
+
+    /// Whether the client's copy is current, so a `304 Not Modified` can be sent instead of the file.
+    /// `If-Modified-Since` is only considered when there is no `If-None-Match`, as it is less precise.
+    pub fn is_not_modified(&self, headers: &HeaderMap) -> bool {
+        if let Some(if_none_match) = headers.get(header::IF_NONE_MATCH) {
+            return self.matches(if_none_match);
+        }
+
+        match (headers.get(header::IF_MODIFIED_SINCE), self.last_modified) {
+            (Some(since), Some(last_modified)) => {
+                parse_date(since).is_some_and(|since| truncate(last_modified) <= since)
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether a `Range` may be served, which requires an `If-Range` to name the current version of the file.
+    /// Dates only count when they are exactly the `Last-Modified` time, since they are weak validators otherwise.
+    pub fn is_range_current(&self, headers: &HeaderMap) -> bool {
+        let Some(if_range) = headers.get(header::IF_RANGE) else {
+            return true;
+        };
+
+        if if_range.as_bytes().starts_with(b"\"") {
+            return if_range.as_bytes() == self.etag.as_bytes();
+        }
+
+        match (parse_date(if_range), self.last_modified) {
+            (Some(date), Some(last_modified)) => truncate(last_modified) == date,
+            _ => false,
+        }
+    }
+
+    /// Compares a list of entity tags (or `*`) with the file's, ignoring the weak `W/` prefix.
+    fn matches(&self, tags: &HeaderValue) -> bool {
+        let Ok(tags) = tags.to_str() else {
+            return false;
+        };
+
+        tags.split(',')
+            .map(str::trim)
+            .any(|tag| tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == self.etag)
+    }
+}
+
+fn parse_date(value: &HeaderValue) -> Option<SystemTime> {
+    httpdate::parse_http_date(value.to_str().ok()?).ok()
+}
+
+/// Drops the fraction of a second, since HTTP dates only have a resolution of seconds.
+fn truncate(time: SystemTime) -> SystemTime {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    UNIX_EPOCH + Duration::from_secs(seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validators() -> (Validators, HeaderMap) {
+        let file = tempfile::NamedTempFile::new().unwrap();
+
+        std::fs::write(file.path(), "Hello").unwrap();
+
+        let validators = Validators::new(&file.path().metadata().unwrap());
+        let mut headers = HeaderMap::new();
+
+        validators.insert(&mut headers);
+
+        (validators, headers)
+    }
+
+    #[test]
+    fn if_none_match() {
+        let (validators, response) = validators();
+        let etag = response.get(header::ETAG).unwrap().to_str().unwrap();
+        let mut request = HeaderMap::new();
+
+        for (value, not_modified) in [
+            (etag.to_string(), true),
+            (format!("W/{etag}"), true),
+            (format!("\"other\", {etag}"), true),
+            ("*".to_string(), true),
+            ("\"other\"".to_string(), false),
+        ] {
+            request.insert(header::IF_NONE_MATCH, value.parse().unwrap());
+
+            assert_eq!(
+                validators.is_not_modified(&request),
+                not_modified,
+                "{value}"
+            );
+        }
+
+        // A stale date is ignored in favor of the entity tag.
+        request.insert(header::IF_NONE_MATCH, etag.parse().unwrap());
+        request.insert(
+            header::IF_MODIFIED_SINCE,
+            HeaderValue::from_static("Thu, 01 Jan 1970 00:00:00 GMT"),
+        );
+
+        assert!(validators.is_not_modified(&request));
+    }
+
+    #[test]
+    fn if_modified_since() {
+        let (validators, response) = validators();
+        let last_modified = response.get(header::LAST_MODIFIED).unwrap().clone();
+        let mut request = HeaderMap::new();
+
+        assert!(!validators.is_not_modified(&request));
+
+        request.insert(header::IF_MODIFIED_SINCE, last_modified);
+
+        assert!(validators.is_not_modified(&request));
+
+        request.insert(
+            header::IF_MODIFIED_SINCE,
+            HeaderValue::from_static("Thu, 01 Jan 1970 00:00:00 GMT"),
+        );
+
+        assert!(!validators.is_not_modified(&request));
+    }
+
+    #[test]
+    fn if_range() {
+        let (validators, response) = validators();
+        let mut request = HeaderMap::new();
+
+        assert!(validators.is_range_current(&request));
+
+        for name in [header::ETAG, header::LAST_MODIFIED] {
+            request.insert(header::IF_RANGE, response.get(&name).unwrap().clone());
+
+            assert!(validators.is_range_current(&request), "{name}");
+        }
+
+        for stale in ["\"other\"", "Thu, 01 Jan 1970 00:00:00 GMT"] {
+            request.insert(header::IF_RANGE, HeaderValue::from_static(stale));
+
+            assert!(!validators.is_range_current(&request), "{stale}");
+        }
+    }
+}
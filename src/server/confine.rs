@@ -0,0 +1,193 @@
+use crate::server::SymlinkPolicy;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// Why a request path was refused access to the file system.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Violation {
+    /// The path has `..` segments or is absolute, so joining it would leave the root.
+    Traversal,
+    /// The path resolves outside of the root through a symbolic link.
+    Escape,
+    /// The path has a symbolic link, which the policy does not allow.
+    Symlink,
+    /// The path has a symbolic link owned by a different user than its target.
+    SymlinkOwner,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::Traversal => write!(f, "The path traverses outside of its root."),
+            Violation::Escape => write!(f, "The path resolves outside of its root."),
+            Violation::Symlink => write!(f, "The path has a symbolic link, which is not allowed."),
+            Violation::SymlinkOwner => write!(
+                f,
+                "The path has a symbolic link owned by a different user than its target."
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Violation {}
+
+impl From<Violation> for io::Error {
+    fn from(violation: Violation) -> Self {
+        io::Error::new(io::ErrorKind::PermissionDenied, violation)
+    }
+}
+
+/// Whether the error is a request path that was refused by [`confine`], rather than any other failure.
+pub fn is_violation(error: &io::Error) -> bool {
+    error.get_ref().is_some_and(|inner| inner.is::<Violation>())
+}
+
+/// Joins a relative request path onto a canonical root, verifying the result stays within the root.
+///
+/// Every existing component is checked against the symlink policy, and the deepest existing one is
+/// canonicalized to confirm links do not lead elsewhere. Components that do not exist yet are left
+/// for the caller to report, so missing files are still not found rather than refused.
+/// The returned path is not canonical, so script names are preserved.
+pub fn confine(root: &Path, relative: &str, symlinks: SymlinkPolicy) -> io::Result<PathBuf> {
+    let mut path = root.to_path_buf();
+    let mut existing = Some(root.to_path_buf());
+
+    for component in Path::new(relative).components() {
+        match component {
+            Component::Normal(name) => path.push(name),
+            Component::CurDir => continue,
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                return Err(Violation::Traversal.into())
+            }
+        }
+
+        if existing.is_none() {
+            continue;
+        }
+
+        match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                check_symlink(&path, &metadata, symlinks)?;
+                existing = Some(path.clone());
+            }
+            Ok(_) => existing = Some(path.clone()),
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::NotFound | io::ErrorKind::NotADirectory
+                ) =>
+            {
+                let deepest = existing.take().unwrap_or_default();
+
+                check_within(root, &deepest)?;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    if let Some(deepest) = existing {
+        check_within(root, &deepest)?;
+    }
+
+    Ok(path)
+}
+
+fn check_symlink(path: &Path, link: &fs::Metadata, symlinks: SymlinkPolicy) -> io::Result<()> {
+    match symlinks {
+        SymlinkPolicy::Follow => Ok(()),
+        SymlinkPolicy::Deny => Err(Violation::Symlink.into()),
+        SymlinkPolicy::FollowIfOwnerMatches => {
+            let target = fs::metadata(path)?;
+
+            if same_owner(link, &target) {
+                Ok(())
+            } else {
+                Err(Violation::SymlinkOwner.into())
+            }
+        }
+    }
+}
+
+fn check_within(root: &Path, path: &Path) -> io::Result<()> {
+    if path.canonicalize()?.starts_with(root) {
+        Ok(())
+    } else {
+        Err(Violation::Escape.into())
+    }
+}
+
+#[cfg(unix)]
+fn same_owner(link: &fs::Metadata, target: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    link.uid() == target.uid()
+}
+
+/// Files have no owning user ID outside of Unix, so links are only refused for leaving the root.
+#[cfg(not(unix))]
+fn same_owner(_link: &fs::Metadata, _target: &fs::Metadata) -> bool {
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn traversal() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path().canonicalize().unwrap();
+
+        std::fs::write(root.join("index.html"), "Hello").unwrap();
+
+        assert_eq!(
+            confine(&root, "index.html", SymlinkPolicy::Deny).unwrap(),
+            root.join("index.html")
+        );
+        assert_eq!(
+            confine(&root, "./missing/file.txt", SymlinkPolicy::Deny).unwrap(),
+            root.join("missing/file.txt")
+        );
+
+        for relative in ["../etc/passwd", "a/../../etc/passwd", "/etc/passwd"] {
+            let error = confine(&root, relative, SymlinkPolicy::Follow).unwrap_err();
+
+            assert!(is_violation(&error), "{relative}");
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path().canonicalize().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+
+        std::fs::write(root.join("index.html"), "Hello").unwrap();
+        std::fs::write(outside.path().join("secret.txt"), "Secret").unwrap();
+        std::os::unix::fs::symlink(root.join("index.html"), root.join("home.html")).unwrap();
+        std::os::unix::fs::symlink(outside.path(), root.join("outside")).unwrap();
+
+        assert!(confine(&root, "home.html", SymlinkPolicy::Follow).is_ok());
+        assert!(confine(&root, "home.html", SymlinkPolicy::FollowIfOwnerMatches).is_ok());
+
+        let error = confine(&root, "home.html", SymlinkPolicy::Deny).unwrap_err();
+
+        assert_eq!(
+            error.get_ref().unwrap().downcast_ref::<Violation>(),
+            Some(&Violation::Symlink)
+        );
+
+        for relative in ["outside/secret.txt", "outside/missing.txt"] {
+            let error = confine(&root, relative, SymlinkPolicy::Follow).unwrap_err();
+
+            assert_eq!(
+                error.get_ref().unwrap().downcast_ref::<Violation>(),
+                Some(&Violation::Escape),
+                "{relative}"
+            );
+        }
+    }
+}
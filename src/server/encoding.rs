@@ -0,0 +1,331 @@
+use crate::script::Output;
+use bytes::Bytes;
+use http::HeaderValue;
+use std::io::{self, Write};
+
+/// The chunks of compressed output buffered ahead of the client.
+const ENCODED_CHUNKS: usize = 4;
+
+/// A content coding the server can compress responses with.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Encoding {
+    Brotli,
+    Zstd,
+    Gzip,
+}
+
+impl Encoding {
+    /// Every supported coding, in the order the server prefers them when a client accepts several equally.
+    pub const ALL: [Encoding; 3] = [Encoding::Brotli, Encoding::Zstd, Encoding::Gzip];
+
+    /// The codings static files may have precompressed siblings for, named with [`Encoding::extension`].
+    pub const PRECOMPRESSED: [Encoding; 2] = [Encoding::Brotli, Encoding::Gzip];
+
+    /// The name of the coding in `Accept-Encoding` and `Content-Encoding` headers.
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Zstd => "zstd",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    /// The extension of a precompressed sibling file (e.g., `app.js.br`).
+    pub fn extension(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Zstd => "zst",
+            Encoding::Gzip => "gz",
+        }
+    }
+
+    pub fn header_value(self) -> HeaderValue {
+        HeaderValue::from_static(self.name())
+    }
+}
+
+/// Picks the available coding the client most prefers by its `Accept-Encoding` quality values,
+/// falling back to the server's preference between equally acceptable codings.
+pub fn negotiate(
+    accept_encoding: Option<&HeaderValue>,
+    available: &[Encoding],
+) -> Option<Encoding> {
+    let preferences = Preferences::parse(accept_encoding?)?;
+    let quality = |encoding: Encoding| preferences.quality(encoding.name()).unwrap_or(0.0);
+
+    Encoding::ALL
+        .into_iter()
+        .filter(|encoding| available.contains(encoding) && quality(*encoding) > 0.0)
+        .fold(None, |best: Option<Encoding>, encoding| match best {
+            Some(best) if quality(best) >= quality(encoding) => Some(best),
+            _ => Some(encoding),
+        })
+}
+
+/// Whether the client refuses uncompressed responses with `identity;q=0`, or `*;q=0` without an `identity` entry.
+/// Responses the server cannot encode in a coding the client accepts are still sent uncompressed,
+/// as RFC 9110 (section 12.5.3) allows, rather than refused with a `406 Not Acceptable`.
+pub fn refuses_identity(accept_encoding: Option<&HeaderValue>) -> bool {
+    accept_encoding
+        .and_then(Preferences::parse)
+        .and_then(|preferences| preferences.quality("identity"))
+        .is_some_and(|quality| quality <= 0.0)
+}
+
+/// The quality values of an `Accept-Encoding` header, keyed by lowercase coding name.
+struct Preferences {
+    codings: Vec<(String, f32)>,
+    wildcard: Option<f32>,
+}
+
+impl Preferences {
+    fn parse(accept_encoding: &HeaderValue) -> Option<Self> {
+        let accept_encoding = accept_encoding.to_str().ok()?;
+        let mut codings = Vec::new();
+        let mut wildcard = None;
+
+        for coding in accept_encoding.split(',') {
+            let mut parameters = coding.split(';').map(str::trim);
+            let name = parameters.next().unwrap_or_default().to_ascii_lowercase();
+            let quality = parameters
+                .find_map(|parameter| parameter.strip_prefix("q="))
+                .map_or(Some(1.0), |quality| quality.parse::<f32>().ok())
+                .unwrap_or(0.0);
+
+            if name == "*" {
+                wildcard = Some(quality);
+            } else {
+                codings.push((name, quality));
+            }
+        }
+
+        Some(Self { codings, wildcard })
+    }
+
+    /// The quality of the coding, or `None` when the client did not mention it.
+    fn quality(&self, name: &str) -> Option<f32> {
+        self.codings
+            .iter()
+            .find(|(coding, _)| coding == name)
+            .map(|(_, quality)| *quality)
+            .or(self.wildcard)
+    }
+}
+
+/// Whether responses of the media type are worth compressing, unlike images, audio and archives that already are.
+pub fn is_compressible(content_type: &HeaderValue) -> bool {
+    let Ok(content_type) = content_type.to_str() else {
+        return false;
+    };
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+
+    essence.starts_with("text/")
+        || essence.ends_with("+json")
+        || essence.ends_with("+xml")
+        || matches!(
+            essence.as_str(),
+            "application/json"
+                | "application/javascript"
+                | "application/xml"
+                | "application/wasm"
+                | "application/toml"
+                | "application/yaml"
+                | "image/svg+xml"
+        )
+}
+
+/// Compresses the output as it is produced, flushing after each chunk so streamed responses are not held back.
+/// Fails if the encoder cannot be created, before the response is marked with the encoding.
+pub fn encode(mut output: Output, encoding: Encoding) -> io::Result<Output> {
+    let mut encoder = Encoder::new(encoding)?;
+    let (sender, encoded) = Output::channel(ENCODED_CHUNKS);
+
+    tokio::spawn(async move {
+        while let Some(chunk) = output.chunk().await {
+            let compressed = match chunk.and_then(|chunk| encoder.write(&chunk)) {
+                Ok(compressed) if compressed.is_empty() => continue,
+                result => result,
+            };
+            let failed = compressed.is_err();
+
+            if sender.send(compressed).await.is_err() || failed {
+                return;
+            }
+        }
+
+        let _ = sender.send(encoder.finish()).await;
+    });
+
+    Ok(encoded)
+}
+
+enum Encoder {
+    Brotli(Box<brotli::CompressorWriter<Vec<u8>>>),
+    Zstd(zstd::stream::write::Encoder<'static, Vec<u8>>),
+    Gzip(flate2::write::GzEncoder<Vec<u8>>),
+}
+
+impl Encoder {
+    /// Uses moderate levels, since responses are compressed on every request rather than once ahead of time.
+    fn new(encoding: Encoding) -> io::Result<Self> {
+        Ok(match encoding {
+            Encoding::Brotli => Self::Brotli(Box::new(brotli::CompressorWriter::new(
+                Vec::new(),
+                4096,
+                4,
+                22,
+            ))),
+            Encoding::Zstd => Self::Zstd(zstd::stream::write::Encoder::new(Vec::new(), 3)?),
+            Encoding::Gzip => Self::Gzip(flate2::write::GzEncoder::new(
+                Vec::new(),
+                flate2::Compression::new(5),
+            )),
+        })
+    }
+
+    /// Compresses a chunk, returning the compressed bytes produced so far.
+    fn write(&mut self, chunk: &[u8]) -> io::Result<Bytes> {
+        let buffer = match self {
+            Self::Brotli(encoder) => {
+                encoder.write_all(chunk)?;
+                encoder.flush()?;
+                encoder.get_mut()
+            }
+            Self::Zstd(encoder) => {
+                encoder.write_all(chunk)?;
+                encoder.flush()?;
+                encoder.get_mut()
+            }
+            Self::Gzip(encoder) => {
+                encoder.write_all(chunk)?;
+                encoder.flush()?;
+                encoder.get_mut()
+            }
+        };
+
+        Ok(Bytes::from(std::mem::take(buffer)))
+    }
+
+    fn finish(self) -> io::Result<Bytes> {
+        let buffer = match self {
+            Self::Brotli(encoder) => encoder.into_inner(),
+            Self::Zstd(encoder) => encoder.finish()?,
+            Self::Gzip(encoder) => encoder.finish()?,
+        };
+
+        Ok(Bytes::from(buffer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::BodyExt;
+    use std::io::Read;
+
+    #[test]
+    fn negotiation() {
+        let negotiate = |accept: &'static str, available: &[Encoding]| {
+            negotiate(Some(&HeaderValue::from_static(accept)), available)
+        };
+
+        assert_eq!(
+            negotiate("gzip, br", &Encoding::ALL),
+            Some(Encoding::Brotli)
+        );
+        assert_eq!(
+            negotiate("gzip, br;q=0.5", &Encoding::ALL),
+            Some(Encoding::Gzip)
+        );
+        assert_eq!(
+            negotiate("gzip, br", &Encoding::PRECOMPRESSED[1..]),
+            Some(Encoding::Gzip)
+        );
+        assert_eq!(negotiate("*", &Encoding::ALL), Some(Encoding::Brotli));
+        assert_eq!(negotiate("*, br;q=0", &Encoding::ALL), Some(Encoding::Zstd));
+        assert_eq!(negotiate("identity", &Encoding::ALL), None);
+        assert_eq!(
+            negotiate("GZIP;q=0.1", &Encoding::ALL),
+            Some(Encoding::Gzip)
+        );
+        assert_eq!(negotiate("gzip;q=0", &Encoding::ALL), None);
+        assert_eq!(negotiate("gzip", &[]), None);
+        assert_eq!(None, super::negotiate(None, &Encoding::ALL));
+    }
+
+    #[test]
+    fn identity() {
+        let refuses =
+            |accept: &'static str| refuses_identity(Some(&HeaderValue::from_static(accept)));
+
+        assert!(refuses("gzip, identity;q=0"));
+        assert!(refuses("gzip, *;q=0"));
+        assert!(!refuses("gzip, identity, *;q=0"));
+        assert!(!refuses("gzip"));
+        assert!(!refuses_identity(None));
+    }
+
+    #[test]
+    fn compressible() {
+        assert!(is_compressible(&HeaderValue::from_static(
+            "text/html; charset=utf-8"
+        )));
+        assert!(is_compressible(&HeaderValue::from_static(
+            "application/ld+json"
+        )));
+        assert!(!is_compressible(&HeaderValue::from_static("image/png")));
+        assert!(!is_compressible(&HeaderValue::from_static(
+            "application/gzip"
+        )));
+    }
+
+    #[tokio::test]
+    async fn round_trip() {
+        let contents = "Hello, World! ".repeat(1000);
+
+        for encoding in Encoding::ALL {
+            let (sender, output) = Output::channel(2);
+            let encoded = encode(output, encoding).unwrap();
+
+            for chunk in contents.as_bytes().chunks(4096) {
+                sender
+                    .send(Ok(Bytes::copy_from_slice(chunk)))
+                    .await
+                    .unwrap();
+            }
+
+            drop(sender);
+
+            let compressed = encoded.collect().await.unwrap().to_bytes();
+            let mut decoded = String::new();
+
+            assert!(compressed.len() < contents.len() / 10, "{encoding:?}");
+
+            match encoding {
+                Encoding::Brotli => {
+                    brotli::Decompressor::new(compressed.as_ref(), 4096)
+                        .read_to_string(&mut decoded)
+                        .unwrap();
+                }
+                Encoding::Zstd => {
+                    zstd::stream::read::Decoder::new(compressed.as_ref())
+                        .unwrap()
+                        .read_to_string(&mut decoded)
+                        .unwrap();
+                }
+                Encoding::Gzip => {
+                    flate2::read::GzDecoder::new(compressed.as_ref())
+                        .read_to_string(&mut decoded)
+                        .unwrap();
+                }
+            }
+
+            assert_eq!(decoded, contents, "{encoding:?}");
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use crate::script::Output;
+use crate::server::response::ResponseBody;
+use bytes::{Bytes, BytesMut};
+use http_body_util::BodyExt;
+use std::io::{self, SeekFrom};
+use std::ops::RangeInclusive;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio::sync::mpsc;
+
+/// The size of the chunks static files are read and sent in.
+const CHUNK_BYTES: u64 = 1024 * 64;
+
+/// The chunks read ahead of a slow client, which bounds the memory of each response regardless of file size.
+const READ_AHEAD_CHUNKS: usize = 4;
+
+/// A piece of a static file response, such as the headers of a `multipart/byteranges` part.
+pub enum Part {
+    Bytes(Bytes),
+    Range(RangeInclusive<u64>),
+}
+
+/// Streams the parts from the file in fixed-size chunks, stopping early if the client goes away.
+pub fn stream(file: File, parts: Vec<Part>) -> ResponseBody {
+    let (sender, output) = Output::channel(READ_AHEAD_CHUNKS);
+
+    tokio::spawn(async move {
+        if let Err(e) = send(file, parts, &sender).await {
+            let _ = sender.send(Err(e)).await;
+        }
+    });
+
+    output.boxed()
+}
+
+async fn send(
+    mut file: File,
+    parts: Vec<Part>,
+    sender: &mpsc::Sender<io::Result<Bytes>>,
+) -> io::Result<()> {
+    for part in parts {
+        let range = match part {
+            Part::Bytes(bytes) => {
+                if sender.send(Ok(bytes)).await.is_err() {
+                    return Ok(());
+                }
+
+                continue;
+            }
+            Part::Range(range) => range,
+        };
+
+        file.seek(SeekFrom::Start(*range.start())).await?;
+
+        let mut remaining = range.end() - range.start() + 1;
+
+        while remaining > 0 {
+            let mut chunk = BytesMut::zeroed(remaining.min(CHUNK_BYTES) as usize);
+
+            // The length was already sent, so a file truncated while it is read fails the response.
+            file.read_exact(&mut chunk).await?;
+            remaining -= chunk.len() as u64;
+
+            if sender.send(Ok(chunk.freeze())).await.is_err() {
+                return Ok(());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn chunks() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("large.bin");
+        let contents: Vec<u8> = (0..CHUNK_BYTES * 3).map(|i| i as u8).collect();
+
+        std::fs::write(&path, &contents).unwrap();
+
+        let end = CHUNK_BYTES * 2 + 9;
+        let mut body = stream(
+            File::open(&path).await.unwrap(),
+            vec![
+                Part::Bytes(Bytes::from("--")),
+                Part::Range(10..=end),
+                Part::Bytes(Bytes::from("--")),
+            ],
+        );
+        let mut frames = Vec::new();
+
+        while let Some(frame) = body.frame().await {
+            frames.push(frame.unwrap().into_data().unwrap());
+        }
+
+        assert_eq!(
+            frames.iter().map(Bytes::len).collect::<Vec<_>>(),
+            vec![2, CHUNK_BYTES as usize, CHUNK_BYTES as usize, 2]
+        );
+        assert_eq!(frames[1..3].concat(), contents[10..=end as usize]);
+    }
+
+    #[tokio::test]
+    async fn truncated() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("short.txt");
+
+        std::fs::write(&path, "Hello").unwrap();
+
+        let body = stream(File::open(&path).await.unwrap(), vec![Part::Range(0..=9)]);
+
+        assert!(body.collect().await.is_err());
+    }
+}
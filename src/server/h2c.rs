@@ -0,0 +1,315 @@
+use crate::server::response::{self, ResponseBody};
+use crate::server::{serve_connection, Router};
+use crate::shutdown::ShutdownSignal;
+use bytes::{Bytes, BytesMut};
+use http::header::{CONNECTION, HOST, TE, UPGRADE};
+use http::{HeaderName, Method, Request, Response, StatusCode, Version};
+use hyper::body::{Body, Incoming};
+use hyper::service::Service;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf};
+use tokio::select;
+use tokio::sync::mpsc;
+
+/// The connection preface every HTTP/2 client starts with, including after an upgrade.
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+const FRAME_HEADER_BYTES: usize = 9;
+
+/// The largest frame either side may send before the other raises `SETTINGS_MAX_FRAME_SIZE`.
+const MAX_FRAME_BYTES: usize = 1024 * 16;
+
+const SETTINGS: u8 = 0x4;
+const HEADERS: u8 = 0x1;
+const END_STREAM: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+
+/// Serves the requests of a cleartext connection, upgrading it to HTTP/2 when an HTTP/1.1 request
+/// asks for `Upgrade: h2c` (RFC 7540 Section 3.2). Other requests are passed to the router as-is.
+#[derive(Clone)]
+pub struct H2c {
+    router: Router,
+    builder: auto::Builder<TokioExecutor>,
+    signal: ShutdownSignal,
+    /// Held until the upgraded connection closes, so the server waits for it when shutting down.
+    connection_guard: mpsc::Sender<()>,
+}
+
+impl H2c {
+    pub fn new(
+        router: Router,
+        builder: auto::Builder<TokioExecutor>,
+        signal: ShutdownSignal,
+        connection_guard: mpsc::Sender<()>,
+    ) -> Self {
+        Self {
+            router,
+            builder,
+            signal,
+            connection_guard,
+        }
+    }
+}
+
+impl Service<Request<Incoming>> for H2c {
+    type Response = Response<ResponseBody>;
+    type Error = http::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn call(&self, mut request: Request<Incoming>) -> Self::Future {
+        let Some(headers) = upgrade_headers(&request) else {
+            return self.router.call(request);
+        };
+
+        let upgrade = hyper::upgrade::on(&mut request);
+        let service = self.clone();
+
+        tokio::spawn(async move {
+            if let Ok(upgraded) = upgrade.await {
+                let _ = service.serve(TokioIo::new(upgraded), headers).await;
+            }
+        });
+
+        Box::pin(async {
+            Response::builder()
+                .status(StatusCode::SWITCHING_PROTOCOLS)
+                .header(CONNECTION, "upgrade")
+                .header(UPGRADE, "h2c")
+                .body(response::empty())
+        })
+    }
+}
+
+impl H2c {
+    /// Serves HTTP/2 on the upgraded connection, answering the upgrade request as stream 1.
+    /// The request is replayed as a `HEADERS` frame right after the client's preface and first `SETTINGS` frame,
+    /// since the client sent it over HTTP/1.1 and never sends it again.
+    async fn serve<I>(
+        self,
+        mut io: I,
+        headers: Bytes,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+    where
+        I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let _connection_guard = self.connection_guard;
+        let mut start = vec![0; PREFACE.len() + FRAME_HEADER_BYTES];
+
+        // Connections still waiting on the preface have no requests in flight to wait for.
+        select! {
+            read = io.read_exact(&mut start) => read?,
+            _ = self.signal.draining() => return Ok(()),
+        };
+
+        let frame = &start[PREFACE.len()..];
+        let length = u32::from_be_bytes([0, frame[0], frame[1], frame[2]]) as usize;
+
+        if !start.starts_with(PREFACE) || frame[3] != SETTINGS || length > MAX_FRAME_BYTES {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "The upgraded connection did not start with the HTTP/2 preface.",
+            )
+            .into());
+        }
+
+        start.resize(start.len() + length, 0);
+
+        select! {
+            read = io.read_exact(&mut start[PREFACE.len() + FRAME_HEADER_BYTES..]) => read?,
+            _ = self.signal.draining() => return Ok(()),
+        };
+
+        start.extend_from_slice(&headers);
+
+        let io = Rewind {
+            prefix: Bytes::from(start),
+            io,
+        };
+
+        serve_connection(&self.builder, TokioIo::new(io), self.router, &self.signal).await
+    }
+}
+
+/// The `HEADERS` frame replaying the request on stream 1, if the request asks to upgrade to HTTP/2 and can be upgraded.
+/// Requests with a body are answered over HTTP/1.1, since the body would have to be replayed as well.
+fn upgrade_headers<B: Body>(request: &Request<B>) -> Option<Bytes> {
+    let headers = request.headers();
+    let connection: Vec<_> = headers
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|option| option.trim().to_ascii_lowercase())
+        .collect();
+    let upgrade = headers
+        .get_all(UPGRADE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|protocol| protocol.trim().eq_ignore_ascii_case("h2c"));
+
+    if request.version() != Version::HTTP_11
+        || request.method() == Method::CONNECT
+        || !request.body().is_end_stream()
+        || !upgrade
+        || !connection.iter().any(|option| option == "upgrade")
+        || !connection.iter().any(|option| option == "http2-settings")
+        || headers.get_all("http2-settings").iter().count() != 1
+    {
+        return None;
+    }
+
+    let authority = headers
+        .get(HOST)
+        .map(|host| host.as_bytes())
+        .or_else(|| request.uri().authority().map(|a| a.as_str().as_bytes()))
+        .unwrap_or_default();
+    let path = request
+        .uri()
+        .path_and_query()
+        .map_or("/", |path| path.as_str());
+    let mut block = BytesMut::new();
+
+    literal(&mut block, b":method", request.method().as_str().as_bytes());
+    literal(&mut block, b":scheme", b"http");
+    literal(&mut block, b":authority", authority);
+    literal(&mut block, b":path", path.as_bytes());
+
+    // Connection-specific headers are not allowed in HTTP/2.
+    for (name, value) in headers {
+        let specific = matches!(
+            name.as_str(),
+            "connection" | "keep-alive" | "proxy-connection" | "transfer-encoding" | "upgrade"
+        ) || name == HOST
+            || name == HeaderName::from_static("http2-settings")
+            || (name == TE && value != "trailers")
+            || connection.iter().any(|option| option == name.as_str());
+
+        if !specific {
+            literal(&mut block, name.as_str().as_bytes(), value.as_bytes());
+        }
+    }
+
+    // Larger header blocks would need `CONTINUATION` frames.
+    if block.len() > MAX_FRAME_BYTES {
+        return None;
+    }
+
+    let mut frame = BytesMut::with_capacity(FRAME_HEADER_BYTES + block.len());
+
+    frame.extend_from_slice(&(block.len() as u32).to_be_bytes()[1..]);
+    frame.extend_from_slice(&[HEADERS, END_STREAM | END_HEADERS]);
+    frame.extend_from_slice(&1u32.to_be_bytes());
+    frame.extend_from_slice(&block);
+
+    Some(frame.freeze())
+}
+
+/// Encodes a header field as an HPACK literal without indexing, with a new name (RFC 7541 Section 6.2.2).
+fn literal(block: &mut BytesMut, name: &[u8], value: &[u8]) {
+    block.extend_from_slice(&[0]);
+
+    for string in [name, value] {
+        integer(block, string.len());
+        block.extend_from_slice(string);
+    }
+}
+
+/// Encodes the length of a string literal without Huffman coding, using a 7-bit prefix (RFC 7541 Section 5.1).
+fn integer(block: &mut BytesMut, mut value: usize) {
+    const PREFIX_MAX: usize = 0x7f;
+
+    if value < PREFIX_MAX {
+        block.extend_from_slice(&[value as u8]);
+        return;
+    }
+
+    block.extend_from_slice(&[PREFIX_MAX as u8]);
+    value -= PREFIX_MAX;
+
+    while value >= 0x80 {
+        block.extend_from_slice(&[(value % 0x80) as u8 | 0x80]);
+        value /= 0x80;
+    }
+
+    block.extend_from_slice(&[value as u8]);
+}
+
+/// A connection whose reads start with bytes that were already read from it, or that replace them.
+struct Rewind<I> {
+    prefix: Bytes,
+    io: I,
+}
+
+impl<I: AsyncRead + Unpin> AsyncRead for Rewind<I> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        if self.prefix.is_empty() {
+            return Pin::new(&mut self.io).poll_read(cx, buf);
+        }
+
+        let length = self.prefix.len().min(buf.remaining());
+
+        buf.put_slice(&self.prefix.split_to(length));
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<I: AsyncWrite + Unpin> AsyncWrite for Rewind<I> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.io).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.io).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.io.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn integers() {
+        let encode = |value| {
+            let mut block = BytesMut::new();
+
+            integer(&mut block, value);
+            block
+        };
+
+        assert_eq!(&encode(10)[..], [10]);
+        assert_eq!(&encode(127)[..], [127, 0]);
+        // RFC 7541 Appendix C.1.2, with a 7-bit instead of a 5-bit prefix.
+        assert_eq!(&encode(1337)[..], [127, 186, 9]);
+    }
+}
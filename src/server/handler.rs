@@ -1,13 +1,15 @@
 use crate::context::{ClientContext, RequestContext, ServerContext};
-use crate::server::response::CgiResponse;
+use crate::script::{Input, Output};
+use crate::server::response::{CgiResponse, HttpResponse};
 use crate::Script;
-use bytes::Bytes;
-use http::StatusCode;
-use http_body_util::Full;
+use bytes::{Bytes, BytesMut};
 use hyper::{Request, Response};
 use std::io;
 use std::sync::Arc;
 
+/// The largest header block a CGI script may send before its response body.
+const MAX_HEADER_BYTES: usize = 1024 * 64;
+
 pub struct CgiHandler {
     server: Arc<ServerContext>,
     client: Arc<ClientContext>,
@@ -18,36 +20,106 @@ impl CgiHandler {
         Self { server, client }
     }
 
-    pub async fn serve(&self, request: Request<Bytes>) -> io::Result<Response<Full<Bytes>>> {
-        let context = RequestContext::new(self.server.clone(), self.client.clone(), &request);
-        let body = request.into_body();
-
-        let extension = context.script()?.extension();
-        let output = if extension == Some("wcgi".as_ref()) {
-            let script = self.server.script_mappings().wasm();
-            script.invoke(context, body).await
-        } else if extension == Some("cgi".as_ref()) {
-            let script = self.server.script_mappings().process();
-            script.invoke(context, body).await
+    pub async fn serve(&self, request: Request<Input>) -> io::Result<Response<Output>> {
+        let mut context = RequestContext::new(self.server.clone(), self.client.clone(), &request)?;
+        let mappings = self.server.script_mappings();
+
+        let custom = match mappings.by_prefix(request.uri().path()) {
+            Some((_, script)) => Some(script),
+            None => context
+                .script()?
+                .extension()
+                .and_then(|e| mappings.by_extension(e)),
+        };
+        let extension = context.script().ok().and_then(|script| script.extension());
+        let component = custom.is_none() && extension == Some("whttp".as_ref());
+        let wasm = component || (custom.is_none() && extension == Some("wcgi".as_ref()));
+        let process = custom.is_none() && extension == Some("cgi".as_ref());
+        let mut input = request.into_body();
+
+        if input.content_length().is_none() {
+            // WebAssembly scripts read their input from memory, so the body is buffered regardless.
+            if wasm {
+                input.buffer().await?;
+            } else if self.server.requires_content_length(context.script_name()) {
+                input.spool().await?;
+            }
+
+            if let Some(length) = input.content_length() {
+                context.set_content_length(length);
+            }
+        }
+
+        let script_name = context.script_name().to_string();
+        let output = if let Some(script) = custom {
+            script.invoke_boxed(context, input).await
+        } else if wasm {
+            mappings.wasm().invoke(context, input).await
+        } else if process {
+            mappings.process().invoke(context, input).await
         } else {
             Err(io::Error::new(
                 io::ErrorKind::Unsupported,
-                "Invalid file extension; must be either cgi or wcgi.",
+                "Invalid file extension; must be one of cgi, wcgi or whttp.",
             ))
-        }?;
+        };
 
-        let mut response = Response::new(Full::from(output.clone()));
-
-        if output.is_empty() {
-            *response.status_mut() = StatusCode::BAD_GATEWAY;
+        // Requests for scripts that do not exist are not counted, so clients cannot create arbitrary labels.
+        match &output {
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::NotFound | io::ErrorKind::Unsupported
+                ) => {}
+            _ => self.server.metrics().invocation(&script_name),
         }
 
-        let offset = response.parse_headers(&output)?;
+        let mut output = output?;
+
+        let mut response = Response::new(Output::from(Bytes::new()));
+        let mut buffer = BytesMut::new();
 
-        if offset != 0 {
-            *response.body_mut() = Full::from(output.slice(offset..));
+        let offset = loop {
+            if let Some(offset) = response.parse_headers(&buffer)? {
+                break offset;
+            }
+
+            if buffer.len() > MAX_HEADER_BYTES {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Received too many response headers from the CGI script.",
+                ));
+            }
+
+            match output.chunk().await {
+                Some(chunk) => buffer.extend_from_slice(&chunk?),
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Received partial response headers from the CGI script.",
+                    ))
+                }
+            }
+        };
+
+        output.unread(buffer.split_off(offset).freeze());
+
+        if component {
+            response.extensions_mut().insert(HttpResponse);
+            *response.body_mut() = output;
+
+            return Ok(response);
+        }
+
+        // Redirects must not have a document, so wait to find out whether the script sends one.
+        if response.headers().contains_key(http::header::LOCATION) {
+            if let Some(chunk) = output.chunk().await {
+                output.unread(chunk?);
+            }
         }
 
+        *response.body_mut() = output;
+
         Ok(response)
     }
 }
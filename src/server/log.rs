@@ -0,0 +1,208 @@
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The destination of a log, shared by every connection.
+pub enum LogWriter {
+    Stdout,
+    Stderr,
+    File(Mutex<RotatingFile>),
+}
+
+impl LogWriter {
+    pub fn file(path: PathBuf, max_bytes: u64, max_files: usize) -> io::Result<Self> {
+        let file = RotatingFile::open(path, max_bytes, max_files)?;
+
+        Ok(Self::File(Mutex::new(file)))
+    }
+
+    /// Writes a complete line, so lines from concurrent requests are never interleaved.
+    pub fn write(&self, line: &[u8]) -> io::Result<()> {
+        match self {
+            Self::Stdout => io::stdout().lock().write_all(line),
+            Self::Stderr => io::stderr().lock().write_all(line),
+            Self::File(file) => file.lock().unwrap_or_else(|e| e.into_inner()).write(line),
+        }
+    }
+}
+
+/// Diagnostics from the server and its scripts, tagged with the request they relate to.
+pub struct ErrorLog {
+    writer: LogWriter,
+}
+
+impl ErrorLog {
+    pub fn new(writer: LogWriter) -> Self {
+        Self { writer }
+    }
+
+    /// Writes a message, prefixed with the time, request ID and script path (if any).
+    pub fn write(
+        &self,
+        request_id: Option<&str>,
+        script: Option<&str>,
+        message: impl fmt::Display,
+    ) {
+        let mut line = format!("[{}]", Rfc3339(SystemTime::now()));
+
+        if let Some(request_id) = request_id {
+            line.push_str(&format!(" [request {request_id}]"));
+        }
+
+        if let Some(script) = script {
+            line.push_str(&format!(" [{script}]"));
+        }
+
+        line.push_str(&format!(" {message}\n"));
+
+        if let Err(e) = self.writer.write(line.as_bytes()) {
+            eprintln!("Unable to write to the error log: {e}");
+        }
+    }
+}
+
+/// Writes to standard error, as the server does when no `error_log` is configured.
+impl Default for ErrorLog {
+    fn default() -> Self {
+        Self::new(LogWriter::Stderr)
+    }
+}
+
+/// A UTC timestamp in the RFC 3339 format (e.g., `2000-10-10T13:55:36Z`).
+pub struct Rfc3339(pub SystemTime);
+
+impl fmt::Display for Rfc3339 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (year, month, day, hour, minute, second) = civil(self.0);
+
+        write!(
+            f,
+            "{year:04}-{month:02}-{day:02}T{hour:02}:{minute:02}:{second:02}Z"
+        )
+    }
+}
+
+/// Splits the time into its UTC year, month, day, hour, minute and second.
+/// See <https://howardhinnant.github.io/date_algorithms.html#civil_from_days>.
+pub fn civil(time: SystemTime) -> (i64, u32, u32, u32, u32, u32) {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64;
+    let (days, remainder) = (seconds.div_euclid(86_400), seconds.rem_euclid(86_400));
+
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    (
+        year,
+        month,
+        day,
+        (remainder / 3_600) as u32,
+        (remainder % 3_600 / 60) as u32,
+        (remainder % 60) as u32,
+    )
+}
+
+/// A log file that is renamed with a numeric suffix (e.g., `access.log.1`) once it grows too large.
+pub struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    written: u64,
+    max_bytes: u64,
+    max_files: usize,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, max_bytes: u64, max_files: usize) -> io::Result<Self> {
+        let file = Self::append(&path)?;
+        let written = file.metadata()?.len();
+
+        Ok(Self {
+            path,
+            file,
+            written,
+            max_bytes,
+            max_files,
+        })
+    }
+
+    fn append(path: &Path) -> io::Result<File> {
+        OpenOptions::new().create(true).append(true).open(path)
+    }
+
+    fn rotated(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+
+        path.push(format!(".{index}"));
+        path.into()
+    }
+
+    fn write(&mut self, line: &[u8]) -> io::Result<()> {
+        if self.written > 0 && self.written + line.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+
+        self.file.write_all(line)?;
+        self.written += line.len() as u64;
+
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.max_files == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            for index in (1..self.max_files).rev() {
+                let from = self.rotated(index);
+
+                if from.exists() {
+                    fs::rename(from, self.rotated(index + 1))?;
+                }
+            }
+
+            fs::rename(&self.path, self.rotated(1))?;
+        }
+
+        self.file = Self::append(&self.path)?;
+        self.written = 0;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotation() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("access.log");
+        let mut file = RotatingFile::open(path.clone(), 10, 2).unwrap();
+
+        for line in ["first\n", "second\n", "third\n", "fourth\n"] {
+            file.write(line.as_bytes()).unwrap();
+        }
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fourth\n");
+        assert_eq!(fs::read_to_string(file.rotated(1)).unwrap(), "third\n");
+        assert_eq!(fs::read_to_string(file.rotated(2)).unwrap(), "second\n");
+        assert!(!file.rotated(3).exists());
+    }
+}
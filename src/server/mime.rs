@@ -0,0 +1,170 @@
+use http::HeaderValue;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+/// The media type of files with an unknown extension.
+const DEFAULT_TYPE: &str = "application/octet-stream";
+
+/// The media types of common static assets by file extension.
+const BUILT_IN: &[(&str, &str)] = &[
+    ("aac", "audio/aac"),
+    ("avif", "image/avif"),
+    ("bmp", "image/bmp"),
+    ("css", "text/css"),
+    ("csv", "text/csv"),
+    ("gif", "image/gif"),
+    ("gz", "application/gzip"),
+    ("htm", "text/html"),
+    ("html", "text/html"),
+    ("ico", "image/vnd.microsoft.icon"),
+    ("ics", "text/calendar"),
+    ("jpeg", "image/jpeg"),
+    ("jpg", "image/jpeg"),
+    ("js", "text/javascript"),
+    ("json", "application/json"),
+    ("jsonld", "application/ld+json"),
+    ("map", "application/json"),
+    ("md", "text/markdown"),
+    ("mjs", "text/javascript"),
+    ("mp3", "audio/mpeg"),
+    ("mp4", "video/mp4"),
+    ("oga", "audio/ogg"),
+    ("ogg", "audio/ogg"),
+    ("ogv", "video/ogg"),
+    ("otf", "font/otf"),
+    ("pdf", "application/pdf"),
+    ("png", "image/png"),
+    ("svg", "image/svg+xml"),
+    ("tar", "application/x-tar"),
+    ("tif", "image/tiff"),
+    ("tiff", "image/tiff"),
+    ("toml", "application/toml"),
+    ("ttf", "font/ttf"),
+    ("txt", "text/plain"),
+    ("wasm", "application/wasm"),
+    ("wav", "audio/wav"),
+    ("weba", "audio/webm"),
+    ("webm", "video/webm"),
+    ("webmanifest", "application/manifest+json"),
+    ("webp", "image/webp"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("xhtml", "application/xhtml+xml"),
+    ("xml", "application/xml"),
+    ("yaml", "application/yaml"),
+    ("yml", "application/yaml"),
+    ("zip", "application/zip"),
+];
+
+/// Maps the extensions of static files to the `Content-Type` they are served with.
+pub struct MimeTypes {
+    types: HashMap<String, HeaderValue>,
+    default: HeaderValue,
+}
+
+impl MimeTypes {
+    /// Combines the built-in table with the configured overrides, which take precedence.
+    /// Extensions are matched case-insensitively. Media types that are not valid header values are skipped,
+    /// since [`Options::validate`](crate::server::Options::validate) rejects them.
+    pub fn new(overrides: &BTreeMap<String, String>) -> Self {
+        let types = BUILT_IN
+            .iter()
+            .map(|&(extension, media_type)| (extension.to_string(), media_type.to_string()))
+            .chain(
+                overrides
+                    .iter()
+                    .map(|(extension, media_type)| (extension.to_lowercase(), media_type.clone())),
+            )
+            .filter_map(|(extension, media_type)| Some((extension, header_value(&media_type)?)))
+            .collect();
+
+        Self {
+            types,
+            default: HeaderValue::from_static(DEFAULT_TYPE),
+        }
+    }
+
+    /// The `Content-Type` of the file at the given path.
+    pub fn content_type(&self, path: &Path) -> HeaderValue {
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(|extension| self.types.get(&extension.to_lowercase()))
+            .unwrap_or(&self.default)
+            .clone()
+    }
+}
+
+/// The header value for a media type, declaring UTF-8 for text types without an explicit charset.
+pub fn header_value(media_type: &str) -> Option<HeaderValue> {
+    let media_type = media_type.trim();
+
+    if media_type.is_empty() {
+        return None;
+    }
+
+    if is_text(media_type) && !media_type.contains(';') {
+        HeaderValue::try_from(format!("{media_type}; charset=utf-8")).ok()
+    } else {
+        HeaderValue::try_from(media_type).ok()
+    }
+}
+
+/// Whether files of the media type are text, so browsers need to know their character encoding.
+fn is_text(media_type: &str) -> bool {
+    let essence = media_type.to_ascii_lowercase();
+
+    essence.starts_with("text/")
+        || essence.ends_with("+json")
+        || essence.ends_with("+xml")
+        || matches!(
+            essence.as_str(),
+            "application/json" | "application/xml" | "application/toml" | "application/yaml"
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn built_in() {
+        let types = MimeTypes::new(&BTreeMap::new());
+
+        assert_eq!(
+            types.content_type(Path::new("/srv/style.css")),
+            "text/css; charset=utf-8"
+        );
+        assert_eq!(
+            types.content_type(Path::new("/srv/app.wasm")),
+            "application/wasm"
+        );
+        assert_eq!(types.content_type(Path::new("/srv/LOGO.PNG")), "image/png");
+        assert_eq!(types.content_type(Path::new("/srv/README")), DEFAULT_TYPE);
+    }
+
+    #[test]
+    fn overrides() {
+        let overrides = BTreeMap::from([
+            ("md".to_string(), "text/plain".to_string()),
+            (
+                "csv".to_string(),
+                "text/csv; charset=iso-8859-1".to_string(),
+            ),
+            ("dat".to_string(), "application/x-data".to_string()),
+        ]);
+        let types = MimeTypes::new(&overrides);
+
+        assert_eq!(
+            types.content_type(Path::new("notes.md")),
+            "text/plain; charset=utf-8"
+        );
+        assert_eq!(
+            types.content_type(Path::new("report.csv")),
+            "text/csv; charset=iso-8859-1"
+        );
+        assert_eq!(
+            types.content_type(Path::new("table.dat")),
+            "application/x-data"
+        );
+    }
+}
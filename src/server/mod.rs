@@ -1,21 +1,57 @@
-use hyper::server::conn::http1;
-use hyper_util::rt::TokioIo;
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::body::Incoming;
+use hyper::header::CONTENT_TYPE;
+use hyper::rt::{Read, Write};
+use hyper::service::{service_fn, Service};
+use hyper::{Request, Response, StatusCode};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
 use std::io;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::net::TcpListener;
-
+use tokio::select;
+use tokio::sync::mpsc;
+
+mod access;
+mod autoindex;
+mod builder;
+mod conditional;
+pub(crate) mod confine;
+mod encoding;
+mod file;
+mod h2c;
 mod handler;
+mod log;
+mod mime;
 mod options;
+mod range;
 mod request;
 mod response;
 mod router;
 
-use crate::context::{ClientContext, ScriptMapping, ServerContext};
-use crate::{script, wasm::ModuleLoader};
-pub use options::Options;
-use router::Router;
+use crate::context::{ClientContext, ServerContext};
+use crate::shutdown::{ShutdownHandle, ShutdownSignal};
+use crate::tls::{self, CertificateLoader};
+use crate::wasm::ModuleLoader;
+pub use access::{AccessLog, LogFormat};
+pub use builder::ServerBuilder;
+pub(crate) use encoding::Encoding;
+use h2c::H2c;
+pub use log::ErrorLog;
+pub(crate) use mime::MimeTypes;
+pub use options::{
+    DirectoryAccess, Options, SymlinkPolicy, WasmDirectory, WasmLimits, WasmPooling,
+};
+pub use router::Router;
+
+/// How long connections are given to close once remaining scripts are terminated after the grace period.
+const TERMINATION_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long clients are given to complete the TLS handshake, so idle sockets do not hold connections open.
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
 
 ///    The server acts as an application gateway.  It receives the request
 ///    from the client, selects a CGI script to handle the request, converts
@@ -38,87 +74,288 @@ use router::Router;
 ///    controls.
 pub struct Server {
     preload_wasm: bool,
+    scan_interval: Duration,
     context: Arc<ServerContext>,
     listener: TcpListener,
+    metrics_listener: Option<TcpListener>,
     loader: ModuleLoader,
+    tls: Option<CertificateLoader>,
+    shutdown: ShutdownHandle,
+    grace_period: Duration,
 }
 
 impl Server {
-    pub async fn bind(mut options: Options) -> io::Result<Self> {
-        options.document_root = options.document_root.canonicalize()?;
-
-        if options.cgi_bin.is_relative() {
-            options.cgi_bin = options
-                .document_root
-                .join(&options.cgi_bin)
-                .canonicalize()?;
-        }
-
-        let loader = ModuleLoader::new(options.cgi_bin.clone(), options.wasm_cache)
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-
-        let mut addresses =
-            tokio::net::lookup_host(format!("{}:{}", options.hostname, options.port)).await?;
-        let address = addresses.next().ok_or_else(|| {
-            io::Error::new(
-                io::ErrorKind::AddrNotAvailable,
-                "unable to resolve interface to a local address",
-            )
-        })?;
-
-        let listener = TcpListener::bind(address).await?;
-        let address = listener.local_addr()?;
-        let process = script::Process::new();
-        let wasm = script::Wasm::new(loader.clone());
-        let scripts = ScriptMapping::new(process, wasm);
-
-        Ok(Self {
-            preload_wasm: options.preload_wasm,
-            context: Arc::new(ServerContext::new(address, options, scripts)),
-            listener,
-            loader,
-        })
+    pub async fn bind(options: Options) -> io::Result<Self> {
+        ServerBuilder::new(options).build().await
     }
 
     pub fn address(&self) -> io::Result<SocketAddr> {
         self.listener.local_addr()
     }
 
+    /// The address of the listener serving Prometheus metrics, if one is configured.
+    pub fn metrics_address(&self) -> Option<io::Result<SocketAddr>> {
+        self.metrics_listener.as_ref().map(TcpListener::local_addr)
+    }
+
+    /// A handle to shut down the server once it is serving.
+    /// Serving returns after in-flight requests finish or the shutdown grace period ends.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
     pub async fn serve(self) -> io::Result<()> {
         if self.preload_wasm {
             self.loader.scan(self.preload_wasm).await?;
         }
 
+        // Held until the server stops, since dropping the watcher stops watching.
+        let _watcher = self
+            .loader
+            .watch(self.preload_wasm)
+            .unwrap_or_else(|e| {
+                self.context.error_log().write(
+                    None,
+                    None,
+                    format_args!("Unable to watch the CGI bin directory for changes, relying on periodic scans instead: {e}"),
+                );
+                None
+            });
         let loader = self.loader.clone();
+        let context = self.context.clone();
 
         tokio::spawn(async move {
-            let mut interval = tokio::time::interval(Duration::from_secs(300));
+            let mut interval = tokio::time::interval(self.scan_interval);
             loop {
                 interval.tick().await;
 
                 if let Err(e) = loader.scan(self.preload_wasm).await {
-                    eprintln!("Encountered an error scanning the CGI bin directory structure: {e}")
+                    context.error_log().write(
+                        None,
+                        None,
+                        format_args!(
+                            "Encountered an error scanning the CGI bin directory structure: {e}"
+                        ),
+                    );
                 }
             }
         });
 
+        #[cfg(unix)]
+        if let Some(tls) = self.tls.clone() {
+            use tokio::signal::unix::{signal, SignalKind};
+
+            let mut hangup = signal(SignalKind::hangup())?;
+            let context = self.context.clone();
+
+            tokio::spawn(async move {
+                while hangup.recv().await.is_some() {
+                    let log = context.error_log();
+
+                    match tls.reload() {
+                        Ok(()) => log.write(None, None, "Reloaded the TLS certificate."),
+                        Err(e) => log.write(
+                            None,
+                            None,
+                            format_args!("Unable to reload the TLS certificate: {e}"),
+                        ),
+                    }
+                }
+            });
+        }
+
+        // Speaks HTTP/1.1 or HTTP/2 depending on the connection preface sent by the client.
+        // Cleartext HTTP/1.1 connections may also be upgraded to HTTP/2 with `Upgrade: h2c`.
+        let builder = auto::Builder::new(TokioExecutor::new());
+        let signal = self.shutdown.signal();
+
+        if let Some(listener) = self.metrics_listener {
+            tokio::spawn(serve_metrics(
+                listener,
+                builder.clone(),
+                self.context.clone(),
+                signal.clone(),
+            ));
+        }
+
+        // Every connection holds a sender, so the receiver closes once all of them are done.
+        let (connections, mut drained) = mpsc::channel::<()>(1);
+
         loop {
-            let (stream, remote_address) = self.listener.accept().await?;
+            let (stream, remote_address) = select! {
+                accepted = self.listener.accept() => accepted?,
+                _ = signal.draining() => break,
+            };
+            let context = self.context.clone();
+            let builder = builder.clone();
+            let signal = signal.clone();
+            let connection_guard = connections.clone();
+
+            let Some(tls) = self.tls.as_ref() else {
+                let client = Arc::new(ClientContext::new(remote_address));
+
+                tokio::spawn(async move {
+                    let router = Router::new(context, client);
+                    let service =
+                        H2c::new(router, builder.clone(), signal.clone(), connection_guard);
+
+                    serve_connection(&builder, TokioIo::new(stream), service, &signal).await
+                });
+                continue;
+            };
+
+            // Connections keep the certificate they were accepted with, even if it is reloaded later.
+            let acceptor = tls.acceptor();
+
+            tokio::spawn(async move {
+                let _connection_guard = connection_guard;
+                let handshake =
+                    tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream));
+                let stream = select! {
+                    accepted = handshake => match accepted {
+                        Ok(Ok(stream)) => stream,
+                        Ok(Err(e)) => {
+                            context.error_log().write(
+                                None,
+                                None,
+                                format_args!("TLS handshake with {remote_address} failed: {e}"),
+                            );
+                            return Ok(());
+                        }
+                        Err(_) => {
+                            context.error_log().write(
+                                None,
+                                None,
+                                format_args!("TLS handshake with {remote_address} timed out."),
+                            );
+                            return Ok(());
+                        }
+                    },
+                    // Connections still in their handshake have no requests in flight to wait for.
+                    _ = signal.draining() => return Ok(()),
+                };
+
+                let (_, connection) = stream.get_ref();
+                let client = ClientContext::new(remote_address)
+                    .with_tls(tls::protocol(connection), tls::cipher(connection));
+
+                let router = Router::new(context, Arc::new(client));
+
+                serve_connection(&builder, TokioIo::new(stream), router, &signal).await
+            });
+        }
+
+        drop(self.listener);
+        drop(connections);
 
-            let client = Arc::new(ClientContext::new(remote_address));
-            let handler = http1::Builder::new().serve_connection(
-                TokioIo::new(stream),
-                Router::new(self.context.clone(), client),
+        if tokio::time::timeout(self.grace_period, drained.recv())
+            .await
+            .is_err()
+        {
+            self.context.error_log().write(
+                None,
+                None,
+                "Terminating the CGI scripts still running after the shutdown grace period.",
             );
 
-            tokio::spawn(handler);
+            self.shutdown.terminate();
+
+            let _ = tokio::time::timeout(TERMINATION_TIMEOUT, drained.recv()).await;
         }
+
+        Ok(())
+    }
+}
+
+/// Serves the Prometheus metrics at `/metrics` until the server begins to shut down.
+async fn serve_metrics(
+    listener: TcpListener,
+    builder: auto::Builder<TokioExecutor>,
+    context: Arc<ServerContext>,
+    signal: ShutdownSignal,
+) {
+    loop {
+        let stream = select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    context.error_log().write(
+                        None,
+                        None,
+                        format_args!("Unable to accept a connection to the metrics listener: {e}"),
+                    );
+                    continue;
+                }
+            },
+            _ = signal.draining() => break,
+        };
+        let context = context.clone();
+        let builder = builder.clone();
+
+        tokio::spawn(async move {
+            let log = context.clone();
+            let service = service_fn(move |request: Request<Incoming>| {
+                let context = context.clone();
+
+                async move {
+                    let response = if request.uri().path() == "/metrics" {
+                        Response::builder()
+                            .header(CONTENT_TYPE, "text/plain; version=0.0.4")
+                            .body(Full::new(Bytes::from(context.metrics().render())))
+                    } else {
+                        Response::builder()
+                            .status(StatusCode::NOT_FOUND)
+                            .body(Full::default())
+                    };
+
+                    response.map_err(io::Error::other)
+                }
+            });
+
+            if let Err(e) = builder
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                log.error_log().write(
+                    None,
+                    None,
+                    format_args!("Unable to serve a connection to the metrics listener: {e}"),
+                );
+            }
+        });
     }
 }
 
+/// Serves requests on the connection until the client closes it or the server shuts down.
+/// Once the server begins to shut down, keep-alive connections are closed after their in-flight requests.
+async fn serve_connection<I, S>(
+    builder: &auto::Builder<TokioExecutor>,
+    io: I,
+    service: S,
+    signal: &ShutdownSignal,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+where
+    I: Read + Write + Unpin + Send + 'static,
+    S: Service<Request<Incoming>, Response = Response<response::ResponseBody>, Error = http::Error>
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+{
+    let connection = builder.serve_connection_with_upgrades(io, service);
+    tokio::pin!(connection);
+
+    select! {
+        result = connection.as_mut() => return result,
+        _ = signal.draining() => connection.as_mut().graceful_shutdown(),
+    }
+
+    connection.await
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::{BTreeMap, BTreeSet};
     use std::path::Component::CurDir;
     use tokio::io::{AsyncReadExt, AsyncWriteExt};
     use tokio::net::TcpStream;
@@ -128,11 +365,9 @@ mod tests {
         let mut client = connect_to_server().await;
 
         for _ in 0..10 {
-            let mut output = vec![0; 1024];
-
             let response_start =
-                "HTTP/1.1 200 OK\r\ncontent-type: text/html\r\ncontent-length: 14\r\ndate: ";
-            let response_end = " GMT\r\n\r\nHello, World!\n";
+                "HTTP/1.1 200 OK\r\ncontent-type: text/html\r\ntransfer-encoding: chunked\r\ndate: ";
+            let response_end = " GMT\r\n\r\nE\r\nHello, World!\n\r\n0\r\n\r\n";
 
             client
                 .write_all(
@@ -140,10 +375,8 @@ mod tests {
                 ).await
                 .unwrap();
 
-            assert_ne!(client.read(&mut output).await.unwrap(), 0);
-
-            let response = String::from_utf8_lossy(output.as_slice());
-            let end = response.find('\0').unwrap_or_else(|| response.len());
+            let response = read_response(&mut client, response_end).await;
+            let end = response.len();
 
             assert_eq!(&response[(end - response_end.len())..end], response_end);
             assert_eq!(&response[..response_start.len()], response_start);
@@ -178,11 +411,9 @@ mod tests {
     async fn local_redirect() {
         let mut client = connect_to_server().await;
 
-        let mut output = vec![0; 1024];
-
         let response_start =
-            "HTTP/1.1 200 OK\r\ncontent-type: text/html\r\ncontent-length: 14\r\ndate: ";
-        let response_end = " GMT\r\n\r\nHello, World!\n";
+            "HTTP/1.1 200 OK\r\ncontent-type: text/html\r\ntransfer-encoding: chunked\r\ndate: ";
+        let response_end = " GMT\r\n\r\nE\r\nHello, World!\n\r\n0\r\n\r\n";
 
         client
             .write_all(
@@ -191,10 +422,8 @@ mod tests {
             .await
             .unwrap();
 
-        assert_ne!(client.read(&mut output).await.unwrap(), 0);
-
-        let response = String::from_utf8_lossy(output.as_slice());
-        let end = response.find('\0').unwrap_or_else(|| response.len());
+        let response = read_response(&mut client, response_end).await;
+        let end = response.len();
 
         assert_eq!(&response[(end - response_end.len())..end], response_end);
         assert_eq!(&response[..response_start.len()], response_start);
@@ -255,11 +484,9 @@ mod tests {
     async fn client_redirect_with_document() {
         let mut client = connect_to_server().await;
 
-        let mut output = vec![0; 1024];
-
         let response_start =
-            "HTTP/1.1 302 Found\r\nlocation: http://localhost:3000/\r\ncontent-type: text/html\r\ncontent-length: 5\r\ndate: ";
-        let response_end = " GMT\r\n\r\ntest\n";
+            "HTTP/1.1 302 Found\r\nlocation: http://localhost:3000/\r\ncontent-type: text/html\r\ntransfer-encoding: chunked\r\ndate: ";
+        let response_end = " GMT\r\n\r\n5\r\ntest\n\r\n0\r\n\r\n";
 
         client
             .write_all(
@@ -268,10 +495,8 @@ mod tests {
             .await
             .unwrap();
 
-        assert_ne!(client.read(&mut output).await.unwrap(), 0);
-
-        let response = String::from_utf8_lossy(output.as_slice());
-        let end = response.find('\0').unwrap_or_else(|| response.len());
+        let response = read_response(&mut client, response_end).await;
+        let end = response.len();
 
         assert_eq!(&response[(end - response_end.len())..end], response_end);
         assert_eq!(&response[..response_start.len()], response_start);
@@ -305,11 +530,10 @@ mod tests {
     #[tokio::test]
     async fn validate() {
         let mut client = connect_to_server().await;
-        let mut output = vec![0; 1024];
 
         let response_start =
-            "HTTP/1.1 200 OK\r\ncontent-type: application/octet-stream\r\ncontent-length: 6\r\ndate: ";
-        let response_end = " GMT\r\n\r\nfoobar";
+            "HTTP/1.1 200 OK\r\ncontent-type: application/octet-stream\r\ntransfer-encoding: chunked\r\ndate: ";
+        let response_end = " GMT\r\n\r\n6\r\nfoobar\r\n0\r\n\r\n";
 
         client
             .write_all(
@@ -317,94 +541,115 @@ mod tests {
             ).await
             .unwrap();
 
-        assert_ne!(client.read(&mut output).await.unwrap(), 0);
-
-        let response = String::from_utf8_lossy(output.as_slice());
-        let end = response.find('\0').unwrap_or_else(|| response.len());
+        let response = read_response(&mut client, response_end).await;
+        let end = response.len();
 
         assert_eq!(&response[(end - response_end.len())..end], response_end);
         assert_eq!(&response[..response_start.len()], response_start);
     }
 
     #[tokio::test]
-    async fn status() {
+    async fn chunked_body() {
         let mut client = connect_to_server().await;
-        let mut output = vec![0; 1024];
 
         let response_start =
-            "HTTP/1.1 404 Not Found\r\ncontent-type: text/html\r\ncontent-length: 0\r\ndate: ";
-        let response_end = " GMT\r\n\r\n";
+            "HTTP/1.1 200 OK\r\ncontent-type: text/plain\r\ninput-length: unknown\r\ntransfer-encoding: chunked\r\ndate: ";
+        let response_end = "\r\n0\r\n\r\n";
 
         client
             .write_all(
-                b"GET /cgi-bin/status.cgi HTTP/1.1\r\nHost: localhost\r\nStatus: 404\r\n\r\n\r\n",
+                b"POST /cgi-bin/cat.cgi HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nfoo\r\n3\r\nbar\r\n0\r\n\r\n",
             )
             .await
             .unwrap();
 
-        assert_ne!(client.read(&mut output).await.unwrap(), 0);
+        let response = read_response(&mut client, response_end).await;
 
-        let response = String::from_utf8_lossy(output.as_slice());
-        let end = response.find('\0').unwrap_or_else(|| response.len());
+        assert!(response.ends_with(response_end));
+        assert!(response.contains("foo"));
+        assert!(response.contains("bar"));
+        assert_eq!(&response[..response_start.len()], response_start);
+    }
 
-        assert_eq!(&response[(end - response_end.len())..end], response_end);
+    #[tokio::test]
+    async fn spooled_body() {
+        let mut client = connect_with_options(Options {
+            document_root: "./examples".into(),
+            require_content_length: BTreeSet::from(["/cgi-bin/cat.cgi".to_string()]),
+            ..Default::default()
+        })
+        .await;
+
+        let response_start =
+            "HTTP/1.1 200 OK\r\ncontent-type: text/plain\r\ninput-length: 6\r\ntransfer-encoding: chunked\r\ndate: ";
+        let response_end = "\r\n6\r\nfoobar\r\n0\r\n\r\n";
+
+        client
+            .write_all(
+                b"POST /cgi-bin/cat.cgi HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nfoo\r\n3\r\nbar\r\n0\r\n\r\n",
+            )
+            .await
+            .unwrap();
+
+        let response = read_response(&mut client, response_end).await;
+
+        assert!(response.ends_with(response_end));
         assert_eq!(&response[..response_start.len()], response_start);
     }
 
     #[tokio::test]
-    async fn not_found() {
+    async fn body_too_large() {
         let mut client = connect_to_server().await;
         let mut output = vec![0; 1024];
 
-        let response_start = "HTTP/1.1 404 Not Found\r\ncontent-length: 0\r\ndate: ";
-        let response_end = " GMT\r\n\r\n";
+        let response_start = "HTTP/1.1 413 Payload Too Large\r\n";
 
         client
-            .write_all(b"GET /fake/news HTTP/1.1\r\nHost: localhost\r\n\r\n\r\n")
+            .write_all(
+                b"POST /cgi-bin/cat.cgi HTTP/1.1\r\nHost: localhost\r\nContent-Length: 1048576\r\n\r\n",
+            )
             .await
             .unwrap();
 
         assert_ne!(client.read(&mut output).await.unwrap(), 0);
 
         let response = String::from_utf8_lossy(output.as_slice());
-        let end = response.find('\0').unwrap_or_else(|| response.len());
 
-        assert_eq!(&response[(end - response_end.len())..end], response_end);
         assert_eq!(&response[..response_start.len()], response_start);
     }
 
     #[tokio::test]
-    async fn wcgi_not_found() {
+    async fn status() {
         let mut client = connect_to_server().await;
-        let mut output = vec![0; 1024];
 
-        let response_start = "HTTP/1.1 404 Not Found\r\ncontent-length: 0\r\ndate: ";
-        let response_end = " GMT\r\n\r\n";
+        let response_start =
+            "HTTP/1.1 404 Not Found\r\ncontent-type: text/html\r\ntransfer-encoding: chunked\r\ndate: ";
+        let response_end = " GMT\r\n\r\n0\r\n\r\n";
 
         client
-            .write_all(b"GET /cgi-bin/fake.wcgi HTTP/1.1\r\nHost: localhost\r\n\r\n\r\n")
+            .write_all(
+                b"GET /cgi-bin/status.cgi HTTP/1.1\r\nHost: localhost\r\nStatus: 404\r\n\r\n\r\n",
+            )
             .await
             .unwrap();
 
-        assert_ne!(client.read(&mut output).await.unwrap(), 0);
-
-        let response = String::from_utf8_lossy(output.as_slice());
-        let end = response.find('\0').unwrap_or_else(|| response.len());
+        let response = read_response(&mut client, response_end).await;
+        let end = response.len();
 
         assert_eq!(&response[(end - response_end.len())..end], response_end);
         assert_eq!(&response[..response_start.len()], response_start);
     }
 
     #[tokio::test]
-    async fn cgi_not_found() {
+    async fn timeout() {
         let mut client = connect_to_server().await;
         let mut output = vec![0; 1024];
 
-        let response_start = "HTTP/1.1 404 Not Found\r\ncontent-length: 0\r\ndate: ";
-        let response_end = " GMT\r\n\r\n";
+        let response_start = "HTTP/1.1 504 Gateway Timeout\r\ncontent-length: 40\r\ndate: ";
+        let response_end = " GMT\r\n\r\nTimed out waiting for the child process.";
 
         client
-            .write_all(b"GET /cgi-bin/fake.cgi HTTP/1.1\r\nHost: localhost\r\n\r\n\r\n")
+            .write_all(b"GET /cgi-bin/sleep.cgi HTTP/1.1\r\nHost: localhost\r\n\r\n")
             .await
             .unwrap();
 
@@ -418,89 +663,469 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn static_asset() {
-        let mut client = connect_to_server().await;
-        let mut output = vec![0; 1024];
+    async fn slow_client() {
+        use std::os::unix::fs::PermissionsExt;
 
-        let response_start = "HTTP/1.1 200 OK\r\ncontent-length: 26\r\ndate: ";
-        let response_end = " GMT\r\n\r\nHello, World from tortuga!";
+        let directory = tempfile::tempdir().unwrap();
+        let script = directory.path().join("large.cgi");
+        let length = 1024 * 1024 * 16;
+
+        std::fs::write(
+            &script,
+            format!("#!/usr/bin/env sh\n\necho \"Content-Type: application/octet-stream\"\necho\nhead -c {length} /dev/zero\n"),
+        )
+        .unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut client = connect_with_options(Options {
+            document_root: directory.path().into(),
+            cgi_bin: CurDir.as_os_str().into(),
+            ..Default::default()
+        })
+        .await;
+        let started = std::time::Instant::now();
+        let mut buffer = vec![0; 1024 * 64];
+        let mut received = 0;
 
         client
-            .write_all(
-                b"GET / HTTP/1.1\r\nHost: localhost\r\nUser-Agent: test\r\nAccept: */*\r\n\r\n",
-            )
+            .write_all(b"GET /cgi-bin/large.cgi HTTP/1.0\r\nHost: localhost\r\n\r\n")
             .await
             .unwrap();
 
-        assert_ne!(client.read(&mut output).await.unwrap(), 0);
+        // Reads slowly enough that downloading the response takes longer than the script timeout.
+        loop {
+            let read = client.read(&mut buffer).await.unwrap();
 
-        let response = String::from_utf8_lossy(output.as_slice());
-        let end = response.find('\0').unwrap_or_else(|| response.len());
+            if read == 0 {
+                break;
+            }
 
-        assert_eq!(&response[(end - response_end.len())..end], response_end);
-        assert_eq!(&response[..response_start.len()], response_start);
+            received += read;
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+
+        assert!(started.elapsed() > Options::default().cgi_timeout);
+        assert!(received > length, "{received}");
     }
 
     #[tokio::test]
-    async fn static_asset_post() {
-        let mut client = connect_to_server().await;
-        let mut output = vec![0; 1024];
+    async fn wasm_slow_client() {
+        let directory = tempfile::tempdir().unwrap();
+        let length = 1024 * 1024 * 16;
+        // Writes the output 64 KiB at a time, then creates `done` in the document root.
+        let module = format!(
+            r#"(module
+                (import "wasi_snapshot_preview1" "path_open"
+                    (func $open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "fd_write" (func $write (param i32 i32 i32 i32) (result i32)))
+                (memory (export "memory") 2)
+                (data (i32.const 0) "Content-Type: application/octet-stream\r\n\r\n")
+                (data (i32.const 64) "done")
+                (func (export "_start") (local $written i32)
+                    (i32.store (i32.const 100) (i32.const 0))
+                    (i32.store (i32.const 104) (i32.const 42))
+                    (drop (call $write (i32.const 1) (i32.const 100) (i32.const 1) (i32.const 200)))
+                    (i32.store (i32.const 100) (i32.const 65536))
+                    (i32.store (i32.const 104) (i32.const 65536))
+                    (loop $output
+                        (if (call $write (i32.const 1) (i32.const 100) (i32.const 1) (i32.const 200))
+                            (then unreachable))
+                        (local.set $written (i32.add (local.get $written) (i32.load (i32.const 200))))
+                        (br_if $output (i32.lt_u (local.get $written) (i32.const {length}))))
+                    (if (call $open (i32.const 3) (i32.const 0) (i32.const 64) (i32.const 4)
+                            (i32.const 1) (i64.const 66) (i64.const 0) (i32.const 0) (i32.const 300))
+                        (then unreachable))))"#
+        );
+
+        std::fs::write(directory.path().join("large.wcgi"), module).unwrap();
+
+        let mut client = connect_with_options(Options {
+            document_root: directory.path().into(),
+            cgi_bin: CurDir.as_os_str().into(),
+            wasm_document_root: DirectoryAccess::ReadWrite,
+            ..Default::default()
+        })
+        .await;
+        let done = directory.path().join("done");
+        let started = std::time::Instant::now();
+        let mut buffer = vec![0; 1024 * 64];
+        let mut received = 0;
 
-        let response_start = "HTTP/1.1 405 Method Not Allowed\r\ncontent-length: 0\r\ndate: ";
-        let response_end = " GMT\r\n\r\n";
+        client
+            .write_all(b"GET /cgi-bin/large.wcgi HTTP/1.0\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+
+        // Reads slowly enough that downloading the response takes longer than the script timeout.
+        loop {
+            let read = client.read(&mut buffer).await.unwrap();
+
+            if read == 0 {
+                break;
+            }
+
+            received += read;
+
+            // The module waits on the client instead of running ahead of it.
+            if received < length / 2 {
+                assert!(!done.exists());
+            }
+
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+
+        assert!(started.elapsed() > Options::default().cgi_timeout);
+        assert!(received > length, "{received}");
+        assert!(done.exists());
+    }
+
+    #[tokio::test]
+    async fn slow_upload() {
+        let mut client = connect_to_server().await;
+        let body = b"Hello, World!";
 
         client
             .write_all(
-                b"POST / HTTP/1.1\r\nHost: localhost\r\nUser-Agent: test\r\nAccept: */*\r\n\r\n",
+                format!("POST /cgi-bin/cat.cgi HTTP/1.0\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n", body.len())
+                    .as_bytes(),
             )
             .await
             .unwrap();
 
-        assert_ne!(client.read(&mut output).await.unwrap(), 0);
+        // Sends the body slowly enough that uploading it takes longer than the script timeout.
+        for byte in body {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            client.write_all(&[*byte]).await.unwrap();
+        }
 
-        let response = String::from_utf8_lossy(output.as_slice());
-        let end = response.find('\0').unwrap_or_else(|| response.len());
+        let mut response = String::new();
 
-        assert_eq!(&response[(end - response_end.len())..end], response_end);
-        assert_eq!(&response[..response_start.len()], response_start);
+        client.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.0 200 OK\r\n"), "{response}");
+        assert!(response.ends_with("\r\n\r\nHello, World!"), "{response}");
     }
 
     #[tokio::test]
-    async fn static_asset_head() {
-        let mut client = connect_to_server().await;
-        let mut output = vec![0; 1024];
+    async fn wasm_limits() {
+        let directory = tempfile::tempdir().unwrap();
+        let spin = "(module (func (export \"_start\") (loop br 0)))";
+        let grow =
+            "(module (memory 1) (func (export \"_start\") (drop (memory.grow (i32.const 16)))))";
+
+        std::fs::write(directory.path().join("fuel.wcgi"), spin).unwrap();
+        std::fs::write(directory.path().join("deadline.wcgi"), spin).unwrap();
+        std::fs::write(directory.path().join("memory.wcgi"), grow).unwrap();
+
+        let options = Options {
+            document_root: directory.path().into(),
+            cgi_bin: CurDir.as_os_str().into(),
+            wasm_fuel: 10_000,
+            wasm_max_memory_bytes: Some(1024 * 256),
+            wasm_limits: BTreeMap::from([(
+                "/cgi-bin/deadline.wcgi".to_string(),
+                WasmLimits {
+                    fuel: Some(u64::MAX),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+        let server = Server::bind(options).await.unwrap();
+        let address = server.address().unwrap();
 
-        let response_start = "HTTP/1.1 200 OK\r\ncontent-length: 26\r\ndate: ";
-        let response_end = " GMT\r\n\r\n";
+        tokio::spawn(server.serve());
+
+        for (script, status) in [
+            ("fuel", "503 Service Unavailable"),
+            ("deadline", "504 Gateway Timeout"),
+            ("memory", "500 Internal Server Error"),
+        ] {
+            let mut client = TcpStream::connect(&address).await.unwrap();
+
+            client
+                .write_all(format!("GET /cgi-bin/{script}.wcgi HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").as_bytes())
+                .await
+                .unwrap();
+
+            let mut response = String::new();
+
+            client.read_to_string(&mut response).await.unwrap();
+
+            assert!(
+                response.starts_with(&format!("HTTP/1.1 {status}\r\n")),
+                "{response}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn wasm_deadline() {
+        let directory = tempfile::tempdir().unwrap();
+        let spin = "(module (func (export \"_start\") (loop br 0)))";
+
+        std::fs::write(directory.path().join("spin.wcgi"), spin).unwrap();
+
+        let options = Options {
+            document_root: directory.path().into(),
+            cgi_bin: CurDir.as_os_str().into(),
+            cgi_timeout: Duration::from_secs(60),
+            wasm_limits: BTreeMap::from([(
+                "/cgi-bin/spin.wcgi".to_string(),
+                WasmLimits {
+                    fuel: Some(u64::MAX),
+                    deadline: Some(Duration::from_secs(1)),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+        let server = Server::bind(options).await.unwrap();
+        let address = server.address().unwrap();
+
+        tokio::spawn(server.serve());
+
+        let mut client = TcpStream::connect(&address).await.unwrap();
+        let mut response = String::new();
 
         client
             .write_all(
-                b"HEAD /index.html HTTP/1.1\r\nHost: localhost\r\nUser-Agent: test\r\nAccept: */*\r\n\r\n",
+                b"GET /cgi-bin/spin.wcgi HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
             )
             .await
             .unwrap();
 
-        assert_ne!(client.read(&mut output).await.unwrap(), 0);
+        // The script's deadline applies instead of the much longer CGI script timeout.
+        tokio::time::timeout(
+            Duration::from_secs(10),
+            client.read_to_string(&mut response),
+        )
+        .await
+        .unwrap()
+        .unwrap();
 
-        let response = String::from_utf8_lossy(output.as_slice());
-        let end = response.find('\0').unwrap_or_else(|| response.len());
+        assert!(
+            response.starts_with("HTTP/1.1 504 Gateway Timeout\r\n"),
+            "{response}"
+        );
+    }
 
-        assert_eq!(&response[(end - response_end.len())..end], response_end);
-        assert_eq!(&response[..response_start.len()], response_start);
+    #[tokio::test]
+    async fn wasm_exit() {
+        let directory = tempfile::tempdir().unwrap();
+        // Writes its output, unless told not to, then exits with the code or traps.
+        // Output is followed by a 100ms sleep, so the response head is sent before any later failure.
+        let exit = |write: bool, end: &str| {
+            let write = if write {
+                "(drop (call $write (i32.const 1) (i32.const 100) (i32.const 1) (i32.const 200)))
+                (i32.store (i32.const 272) (i32.const 1))
+                (i64.store (i32.const 280) (i64.const 100000000))
+                (drop (call $poll (i32.const 256) (i32.const 320) (i32.const 1) (i32.const 400)))"
+            } else {
+                ""
+            };
+
+            format!(
+                r#"(module
+                    (import "wasi_snapshot_preview1" "fd_write" (func $write (param i32 i32 i32 i32) (result i32)))
+                    (import "wasi_snapshot_preview1" "poll_oneoff" (func $poll (param i32 i32 i32 i32) (result i32)))
+                    (import "wasi_snapshot_preview1" "proc_exit" (func $exit (param i32)))
+                    (memory (export "memory") 1)
+                    (data (i32.const 0) "Content-Type: text/plain\r\n\r\nExited")
+                    (func $fail unreachable)
+                    (func (export "_start")
+                        (i32.store (i32.const 100) (i32.const 0))
+                        (i32.store (i32.const 104) (i32.const 34))
+                        {write}
+                        {end}))"#
+            )
+        };
+
+        std::fs::write(
+            directory.path().join("success.wcgi"),
+            exit(true, "(call $exit (i32.const 0))"),
+        )
+        .unwrap();
+        std::fs::write(
+            directory.path().join("failure.wcgi"),
+            exit(false, "(call $exit (i32.const 3))"),
+        )
+        .unwrap();
+        std::fs::write(
+            directory.path().join("trap.wcgi"),
+            exit(false, "(call $fail)"),
+        )
+        .unwrap();
+        std::fs::write(
+            directory.path().join("late-failure.wcgi"),
+            exit(true, "(call $exit (i32.const 3))"),
+        )
+        .unwrap();
+        std::fs::write(
+            directory.path().join("invalid.wcgi"),
+            "(module (func (export \"_start\") i32.add))",
+        )
+        .unwrap();
+        std::fs::write(
+            directory.path().join("late-trap.wcgi"),
+            exit(true, "(call $fail)"),
+        )
+        .unwrap();
+
+        let error_log = directory.path().join("error.log");
+        let options = Options {
+            document_root: directory.path().into(),
+            cgi_bin: CurDir.as_os_str().into(),
+            error_log: Some(error_log.clone()),
+            ..Default::default()
+        };
+        let server = Server::bind(options).await.unwrap();
+        let address = server.address().unwrap();
+
+        tokio::spawn(server.serve());
+
+        for (script, status, body) in [
+            ("success", "200 OK", "Exited"),
+            (
+                "failure",
+                "502 Bad Gateway",
+                "The script exited with code 3.",
+            ),
+            (
+                "trap",
+                "500 Internal Server Error",
+                "The script failed with a wasm trap: wasm `unreachable` instruction executed.",
+            ),
+            (
+                "invalid",
+                "500 Internal Server Error",
+                "The WebAssembly script could not be compiled.",
+            ),
+            // The status cannot change once the output is streamed, so the chunked body is cut off instead.
+            ("late-failure", "200 OK", "Exited"),
+            ("late-trap", "200 OK", "Exited"),
+        ] {
+            let mut client = TcpStream::connect(&address).await.unwrap();
+
+            client
+                .write_all(format!("GET /cgi-bin/{script}.wcgi HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").as_bytes())
+                .await
+                .unwrap();
+
+            let mut response = String::new();
+
+            client.read_to_string(&mut response).await.unwrap();
+
+            assert!(
+                response.starts_with(&format!("HTTP/1.1 {status}\r\n")),
+                "{response}"
+            );
+            assert!(response.contains(body), "{response}");
+
+            if script.starts_with("late") {
+                assert!(!response.ends_with("\r\n0\r\n\r\n"), "{response}");
+            }
+        }
+
+        let log = std::fs::read_to_string(error_log).unwrap();
+
+        assert!(log.contains("The script exited with code 3."), "{log}");
+        assert!(log.contains("!fail"), "{log}");
+        assert!(
+            log.contains("Unable to compile the WebAssembly script"),
+            "{log}"
+        );
+        assert!(log.contains("invalid.wcgi"), "{log}");
     }
 
     #[tokio::test]
-    async fn static_cgi() {
+    async fn wasm_preopens() {
+        let directory = tempfile::tempdir().unwrap();
+        // Writes the header block and sleeps for 100ms, so the response head is sent before opening `data.txt`
+        // in the given preopened directory fails. Then copies up to 64 bytes of the file to stdout.
+        let open = |fd: i32, oflags: i32, rights: i64| {
+            format!(
+                r#"(module
+                    (import "wasi_snapshot_preview1" "path_open"
+                        (func $open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+                    (import "wasi_snapshot_preview1" "fd_read" (func $read (param i32 i32 i32 i32) (result i32)))
+                    (import "wasi_snapshot_preview1" "fd_write" (func $write (param i32 i32 i32 i32) (result i32)))
+                    (import "wasi_snapshot_preview1" "poll_oneoff" (func $poll (param i32 i32 i32 i32) (result i32)))
+                    (memory (export "memory") 1)
+                    (data (i32.const 0) "Content-Type: text/plain\r\n\r\n")
+                    (data (i32.const 64) "data.txt")
+                    (func (export "_start")
+                        (i32.store (i32.const 100) (i32.const 0))
+                        (i32.store (i32.const 104) (i32.const 28))
+                        (drop (call $write (i32.const 1) (i32.const 100) (i32.const 1) (i32.const 304)))
+                        (i32.store (i32.const 512) (i32.const 1))
+                        (i64.store (i32.const 520) (i64.const 100000000))
+                        (drop (call $poll (i32.const 496) (i32.const 560) (i32.const 1) (i32.const 640)))
+                        (if (call $open (i32.const {fd}) (i32.const 0) (i32.const 64) (i32.const 8)
+                                (i32.const {oflags}) (i64.const {rights}) (i64.const 0) (i32.const 0) (i32.const 300))
+                            (then unreachable))
+                        (i32.store (i32.const 100) (i32.const 200))
+                        (i32.store (i32.const 104) (i32.const 64))
+                        (drop (call $read (i32.load (i32.const 300)) (i32.const 100) (i32.const 1) (i32.const 304)))
+                        (i32.store (i32.const 104) (i32.load (i32.const 304)))
+                        (drop (call $write (i32.const 1) (i32.const 100) (i32.const 1) (i32.const 304)))))"#
+            )
+        };
+
+        std::fs::write(directory.path().join("data.txt"), "Hello, world!").unwrap();
+        std::fs::write(directory.path().join("read.wcgi"), open(3, 0, 2)).unwrap();
+        std::fs::write(directory.path().join("write.wcgi"), open(3, 1, 66)).unwrap();
+        std::fs::write(directory.path().join("scratch.wcgi"), open(4, 1, 66)).unwrap();
+
+        let options = Options {
+            document_root: directory.path().into(),
+            cgi_bin: CurDir.as_os_str().into(),
+            wasm_scratch_directory: true,
+            ..Default::default()
+        };
+        let server = Server::bind(options).await.unwrap();
+        let address = server.address().unwrap();
+
+        tokio::spawn(server.serve());
+
+        // The status cannot change once the header block is streamed, so a denied write cuts off the chunked body.
+        for (script, body, complete) in [
+            ("read", "Hello, world!", true),
+            ("write", "", false),
+            ("scratch", "", true),
+        ] {
+            let mut client = TcpStream::connect(&address).await.unwrap();
+
+            client
+                .write_all(format!("GET /cgi-bin/{script}.wcgi HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").as_bytes())
+                .await
+                .unwrap();
+
+            let mut response = String::new();
+
+            client.read_to_string(&mut response).await.unwrap();
+
+            assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+            assert!(response.contains(body), "{response}");
+            assert_eq!(response.ends_with("\r\n0\r\n\r\n"), complete, "{response}");
+        }
+
+        assert_eq!(
+            std::fs::read_to_string(directory.path().join("data.txt")).unwrap(),
+            "Hello, world!"
+        );
+    }
+
+    #[tokio::test]
+    async fn not_found() {
         let mut client = connect_to_server().await;
         let mut output = vec![0; 1024];
 
-        let response_start = "HTTP/1.1 403 Forbidden\r\ncontent-length: 0\r\ndate: ";
+        let response_start = "HTTP/1.1 404 Not Found\r\ncontent-length: 0\r\ndate: ";
         let response_end = " GMT\r\n\r\n";
 
         client
-            .write_all(
-                b"GET /hello.cgi HTTP/1.1\r\nHost: localhost\r\nUser-Agent: test\r\nAccept: */*\r\n\r\n",
-            )
+            .write_all(b"GET /fake/news HTTP/1.1\r\nHost: localhost\r\n\r\n\r\n")
             .await
             .unwrap();
 
@@ -513,17 +1138,1166 @@ mod tests {
         assert_eq!(&response[..response_start.len()], response_start);
     }
 
-    async fn connect_to_server() -> TcpStream {
-        let server = Server::bind(Options {
-            document_root: "./examples".into(),
-            cgi_bin: CurDir.as_os_str().into(),
-            hostname: "localhost".to_string(),
-            port: 0,
-            wasm_cache: true,
-            preload_wasm: false,
-        })
-        .await
-        .unwrap();
+    #[tokio::test]
+    async fn whttp() {
+        let mut client = connect_to_server().await;
+
+        client
+            .write_all(b"POST /cgi-bin/echo.whttp/extra?query=1 HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: 5\r\n\r\nHello")
+            .await
+            .unwrap();
+
+        let mut response = String::new();
+
+        client.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+        assert!(
+            response.contains("content-type: text/plain\r\n"),
+            "{response}"
+        );
+        assert!(
+            response.contains("POST /cgi-bin/echo.whttp/extra?query=1\n"),
+            "{response}"
+        );
+        assert!(response.contains("\r\nHello\r\n"), "{response}");
+    }
+
+    #[tokio::test]
+    async fn wcgi_not_found() {
+        let mut client = connect_to_server().await;
+        let mut output = vec![0; 1024];
+
+        let response_start = "HTTP/1.1 404 Not Found\r\ncontent-length: 0\r\ndate: ";
+        let response_end = " GMT\r\n\r\n";
+
+        client
+            .write_all(b"GET /cgi-bin/fake.wcgi HTTP/1.1\r\nHost: localhost\r\n\r\n\r\n")
+            .await
+            .unwrap();
+
+        assert_ne!(client.read(&mut output).await.unwrap(), 0);
+
+        let response = String::from_utf8_lossy(output.as_slice());
+        let end = response.find('\0').unwrap_or_else(|| response.len());
+
+        assert_eq!(&response[(end - response_end.len())..end], response_end);
+        assert_eq!(&response[..response_start.len()], response_start);
+    }
+
+    #[tokio::test]
+    async fn cgi_not_found() {
+        let mut client = connect_to_server().await;
+        let mut output = vec![0; 1024];
+
+        let response_start = "HTTP/1.1 404 Not Found\r\ncontent-length: 0\r\ndate: ";
+        let response_end = " GMT\r\n\r\n";
+
+        client
+            .write_all(b"GET /cgi-bin/fake.cgi HTTP/1.1\r\nHost: localhost\r\n\r\n\r\n")
+            .await
+            .unwrap();
+
+        assert_ne!(client.read(&mut output).await.unwrap(), 0);
+
+        let response = String::from_utf8_lossy(output.as_slice());
+        let end = response.find('\0').unwrap_or_else(|| response.len());
+
+        assert_eq!(&response[(end - response_end.len())..end], response_end);
+        assert_eq!(&response[..response_start.len()], response_start);
+    }
+
+    #[tokio::test]
+    async fn static_asset() {
+        let mut client = connect_to_server().await;
+
+        let response_start = "HTTP/1.1 200 OK\r\netag: \"1a-";
+        let response_end = " GMT\r\n\r\nHello, World from tortuga!";
+
+        client
+            .write_all(
+                b"GET / HTTP/1.1\r\nHost: localhost\r\nUser-Agent: test\r\nAccept: */*\r\n\r\n",
+            )
+            .await
+            .unwrap();
+
+        // The file is streamed, so its contents may arrive after the headers.
+        let response = read_response(&mut client, response_end).await;
+
+        assert!(response.ends_with(response_end), "{response}");
+        assert!(response.starts_with(response_start));
+        assert!(response.contains(
+            "\r\naccept-ranges: bytes\r\ncontent-length: 26\r\ncontent-type: text/html; charset=utf-8\r\n"
+        ));
+    }
+
+    #[tokio::test]
+    async fn static_asset_post() {
+        let mut client = connect_to_server().await;
+        let mut output = vec![0; 1024];
+
+        let response_start = "HTTP/1.1 405 Method Not Allowed\r\ncontent-length: 0\r\ndate: ";
+        let response_end = " GMT\r\n\r\n";
+
+        client
+            .write_all(
+                b"POST / HTTP/1.1\r\nHost: localhost\r\nUser-Agent: test\r\nAccept: */*\r\n\r\n",
+            )
+            .await
+            .unwrap();
+
+        assert_ne!(client.read(&mut output).await.unwrap(), 0);
+
+        let response = String::from_utf8_lossy(output.as_slice());
+        let end = response.find('\0').unwrap_or_else(|| response.len());
+
+        assert_eq!(&response[(end - response_end.len())..end], response_end);
+        assert_eq!(&response[..response_start.len()], response_start);
+    }
+
+    #[tokio::test]
+    async fn static_asset_head() {
+        let mut client = connect_to_server().await;
+        let mut output = vec![0; 1024];
+
+        let response_start = "HTTP/1.1 200 OK\r\netag: \"1a-";
+        let response_end = " GMT\r\n\r\n";
+
+        client
+            .write_all(
+                b"HEAD /index.html HTTP/1.1\r\nHost: localhost\r\nUser-Agent: test\r\nAccept: */*\r\n\r\n",
+            )
+            .await
+            .unwrap();
+
+        assert_ne!(client.read(&mut output).await.unwrap(), 0);
+
+        let response = String::from_utf8_lossy(output.as_slice());
+        let end = response.find('\0').unwrap_or_else(|| response.len());
+
+        assert_eq!(&response[(end - response_end.len())..end], response_end);
+        assert_eq!(&response[..response_start.len()], response_start);
+        assert!(response.contains(
+            "\r\naccept-ranges: bytes\r\ncontent-length: 26\r\ncontent-type: text/html; charset=utf-8\r\n"
+        ));
+    }
+
+    #[tokio::test]
+    async fn static_content_type() {
+        let mut client = connect_with_options(Options {
+            document_root: "./examples".into(),
+            mime_types: BTreeMap::from([("txt".to_string(), "text/x-input".to_string())]),
+            nosniff: true,
+            ..Default::default()
+        })
+        .await;
+
+        client
+            .write_all(b"GET /input.txt HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+
+        let response = read_response(&mut client, "Hello, CGI!\"").await;
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("\r\ncontent-type: text/x-input; charset=utf-8\r\n"));
+        assert!(response.contains("\r\nx-content-type-options: nosniff\r\n"));
+    }
+
+    #[tokio::test]
+    async fn static_ranges() {
+        let directory = tempfile::tempdir().unwrap();
+
+        std::fs::write(directory.path().join("digits.txt"), "0123456789").unwrap();
+
+        let server = Server::bind(Options {
+            document_root: directory.path().into(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let address = server.address().unwrap();
+
+        tokio::spawn(server.serve());
+
+        let request = |headers: String| async move {
+            let mut client = TcpStream::connect(&address).await.unwrap();
+
+            client
+                .write_all(
+                    format!("GET /digits.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n{headers}\r\n")
+                        .as_bytes(),
+                )
+                .await
+                .unwrap();
+
+            let mut response = String::new();
+
+            client.read_to_string(&mut response).await.unwrap();
+            response
+        };
+        let response = request(String::new()).await;
+        let header = |response: &str, name: &str| {
+            response
+                .lines()
+                .find_map(|line| line.strip_prefix(&format!("{name}: ")))
+                .unwrap()
+                .to_string()
+        };
+        let etag = header(&response, "etag");
+        let last_modified = header(&response, "last-modified");
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\n0123456789"));
+
+        let response = request("Range: bytes=2-4\r\n".to_string()).await;
+
+        assert!(
+            response.starts_with("HTTP/1.1 206 Partial Content\r\n"),
+            "{response}"
+        );
+        assert!(response.contains("\r\ncontent-range: bytes 2-4/10\r\n"));
+        assert!(response.ends_with("\r\n\r\n234"));
+
+        let response = request("Range: bytes=0-1,-2\r\n".to_string()).await;
+        let content_type = header(&response, "content-type");
+        let boundary = content_type
+            .strip_prefix("multipart/byteranges; boundary=")
+            .unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 206 Partial Content\r\n"));
+        assert!(response.ends_with(&format!(
+            "\r\n\r\n\r\n--{boundary}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Range: bytes 0-1/10\r\n\r\n01\
+             \r\n--{boundary}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Range: bytes 8-9/10\r\n\r\n89\
+             \r\n--{boundary}--\r\n"
+        )));
+
+        let response = request("Range: bytes=10-\r\n".to_string()).await;
+
+        assert!(response.starts_with("HTTP/1.1 416 Range Not Satisfiable\r\n"));
+        assert!(response.contains("\r\ncontent-range: bytes */10\r\n"));
+
+        // A range of a different version of the file is ignored in favor of the whole file.
+        let response = request("Range: bytes=2-4\r\nIf-Range: \"other\"\r\n".to_string()).await;
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+
+        let response = request(format!("Range: bytes=2-4\r\nIf-Range: {etag}\r\n")).await;
+
+        assert!(response.starts_with("HTTP/1.1 206 Partial Content\r\n"));
+
+        for conditional in [
+            format!("If-None-Match: {etag}\r\n"),
+            format!("If-Modified-Since: {last_modified}\r\n"),
+        ] {
+            let response = request(conditional).await;
+
+            assert!(
+                response.starts_with("HTTP/1.1 304 Not Modified\r\n"),
+                "{response}"
+            );
+            assert!(response.contains(&format!("\r\netag: {etag}\r\n")));
+            assert!(response.ends_with("\r\n\r\n"));
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn directories() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let directory = tempfile::tempdir().unwrap();
+        let root = directory.path();
+        let script = root.join("app/index.cgi");
+
+        for subdirectory in ["docs", "app", "empty", "listed/nested"] {
+            std::fs::create_dir_all(root.join(subdirectory)).unwrap();
+        }
+
+        std::fs::write(root.join("docs/index.htm"), "Docs").unwrap();
+        std::fs::write(root.join("listed/a.txt"), "Hello").unwrap();
+        std::fs::write(
+            &script,
+            "#!/usr/bin/env sh\n\necho \"Content-Type: text/plain\"\necho\necho \"Script $SCRIPT_NAME\"\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let server = Server::bind(Options {
+            document_root: root.into(),
+            cgi_bin: CurDir.as_os_str().into(),
+            index_files: vec![
+                "index.html".to_string(),
+                "index.htm".to_string(),
+                "index.cgi".to_string(),
+            ],
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let address = server.address().unwrap();
+
+        tokio::spawn(server.serve());
+
+        let request = |path: &'static str, accept: &'static str| async move {
+            let mut client = TcpStream::connect(&address).await.unwrap();
+
+            client
+                .write_all(
+                    format!("GET {path} HTTP/1.1\r\nHost: localhost\r\nAccept: {accept}\r\nConnection: close\r\n\r\n")
+                        .as_bytes(),
+                )
+                .await
+                .unwrap();
+
+            let mut response = String::new();
+
+            client.read_to_string(&mut response).await.unwrap();
+            response
+        };
+
+        let response = request("/docs?page=2", "*/*").await;
+
+        assert!(
+            response.starts_with("HTTP/1.1 301 Moved Permanently\r\n"),
+            "{response}"
+        );
+        assert!(response.contains("\r\nlocation: /docs/?page=2\r\n"));
+
+        let response = request("/docs/", "*/*").await;
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nDocs"));
+
+        let response = request("/app/", "*/*").await;
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+        assert!(response.contains("Script /app/index.cgi\n"), "{response}");
+
+        // Without an autoindex, directories without an index file are not listed.
+        let response = request("/listed/", "*/*").await;
+
+        assert!(response.starts_with("HTTP/1.1 403 Forbidden\r\n"));
+    }
+
+    #[tokio::test]
+    async fn compression() {
+        use flate2::read::GzDecoder;
+        use flate2::write::GzEncoder;
+        use std::io::{Read, Write};
+        use std::os::unix::fs::PermissionsExt;
+
+        let directory = tempfile::tempdir().unwrap();
+        let root = directory.path();
+        let text = "Hello, World!\n".repeat(100);
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+
+        encoder.write_all(text.as_bytes()).unwrap();
+        std::fs::write(root.join("app.js"), &text).unwrap();
+        std::fs::write(root.join("app.js.gz"), encoder.finish().unwrap()).unwrap();
+
+        for (name, headers, repeat) in [
+            ("large.cgi", "Content-Type: text/plain", 100),
+            ("small.cgi", "Content-Type: text/plain", 1),
+            ("image.cgi", "Content-Type: image/png", 100),
+            (
+                "encoded.cgi",
+                "Content-Type: text/plain\nContent-Encoding: identity",
+                100,
+            ),
+        ] {
+            let script = root.join(name);
+            let headers: String = headers
+                .lines()
+                .map(|header| format!("echo \"{header}\"\n"))
+                .collect();
+
+            std::fs::write(
+                &script,
+                format!("#!/usr/bin/env sh\n\n{headers}echo\nfor i in $(seq {repeat}); do echo \"Hello, World!\"; done\n"),
+            )
+            .unwrap();
+            std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+
+        let slow = root.join("slow.cgi");
+
+        std::fs::write(
+            &slow,
+            "#!/usr/bin/env sh\n\necho \"Content-Type: text/plain\"\necho\necho \"Hello, World!\"\nsleep 2\necho \"Goodbye, World!\"\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&slow, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let server = Server::bind(Options {
+            document_root: root.into(),
+            cgi_bin: CurDir.as_os_str().into(),
+            cgi_timeout: Duration::from_secs(10),
+            compression: true,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let address = server.address().unwrap();
+
+        tokio::spawn(server.serve());
+
+        // HTTP/1.0 responses are not chunked, so their bodies can be decoded as they are.
+        let request = |path: &'static str, accept_encoding: &'static str| async move {
+            let mut client = TcpStream::connect(&address).await.unwrap();
+
+            client
+                .write_all(
+                    format!("GET {path} HTTP/1.0\r\nHost: localhost\r\nAccept-Encoding: {accept_encoding}\r\n\r\n")
+                        .as_bytes(),
+                )
+                .await
+                .unwrap();
+
+            let mut response = Vec::new();
+
+            client.read_to_end(&mut response).await.unwrap();
+
+            let end = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+            let body = response.split_off(end + 4);
+
+            (String::from_utf8(response).unwrap(), body)
+        };
+        let gunzip = |body: Vec<u8>| {
+            let mut decoded = String::new();
+
+            GzDecoder::new(body.as_slice())
+                .read_to_string(&mut decoded)
+                .unwrap();
+            decoded
+        };
+
+        let (head, body) = request("/app.js", "gzip, br").await;
+
+        assert!(head.starts_with("HTTP/1.0 200 OK\r\n"), "{head}");
+        assert!(head.contains("\r\ncontent-encoding: gzip\r\n"), "{head}");
+        assert!(head.contains("\r\nvary: accept-encoding\r\n"), "{head}");
+        assert!(head.contains("\r\ncontent-type: text/javascript"), "{head}");
+        assert_eq!(gunzip(body), text);
+
+        let (head, body) = request("/app.js", "identity").await;
+
+        assert!(!head.contains("content-encoding"), "{head}");
+        assert!(head.contains("\r\nvary: accept-encoding\r\n"), "{head}");
+        assert_eq!(body, text.as_bytes());
+
+        let (head, body) = request("/cgi-bin/large.cgi", "gzip").await;
+
+        assert!(head.contains("\r\ncontent-encoding: gzip\r\n"), "{head}");
+        assert!(head.contains("\r\nvary: accept-encoding\r\n"), "{head}");
+        assert_eq!(gunzip(body), text);
+
+        // Responses below the minimum size, of types that are already compressed or encoded by the script are sent as they are.
+        for path in [
+            "/cgi-bin/small.cgi",
+            "/cgi-bin/image.cgi",
+            "/cgi-bin/encoded.cgi",
+        ] {
+            let (head, body) = request(path, "gzip").await;
+
+            assert!(!head.contains("content-encoding: gzip"), "{path}: {head}");
+            assert!(body.starts_with(b"Hello, World!\n"), "{path}");
+        }
+
+        // Small responses are compressed for clients that refuse uncompressed ones.
+        let (head, body) = request("/cgi-bin/small.cgi", "gzip, identity;q=0").await;
+
+        assert!(head.contains("\r\ncontent-encoding: gzip\r\n"), "{head}");
+        assert_eq!(gunzip(body), "Hello, World!\n");
+
+        // The headers of slow responses without a length are not held back until the minimum size is reached.
+        let mut client = TcpStream::connect(&address).await.unwrap();
+        let mut head = Vec::new();
+
+        client
+            .write_all(b"GET /cgi-bin/slow.cgi HTTP/1.0\r\nHost: localhost\r\nAccept-Encoding: gzip\r\n\r\n")
+            .await
+            .unwrap();
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while !head.ends_with(b"\r\n\r\n") {
+                head.push(client.read_u8().await.unwrap());
+            }
+        })
+        .await
+        .unwrap();
+
+        assert!(String::from_utf8_lossy(&head).contains("\r\ncontent-encoding: gzip\r\n"));
+
+        let mut body = Vec::new();
+
+        client.read_to_end(&mut body).await.unwrap();
+
+        assert_eq!(gunzip(body), "Hello, World!\nGoodbye, World!\n");
+    }
+
+    #[tokio::test]
+    async fn autoindex() {
+        let directory = tempfile::tempdir().unwrap();
+
+        std::fs::create_dir(directory.path().join("nested")).unwrap();
+        std::fs::write(directory.path().join("a.txt"), "Hello").unwrap();
+
+        let server = Server::bind(Options {
+            document_root: directory.path().into(),
+            autoindex: true,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let address = server.address().unwrap();
+
+        tokio::spawn(server.serve());
+
+        for (accept, content_type, listing) in [
+            (
+                "text/html",
+                "text/html; charset=utf-8",
+                "<a href=\"a.txt\">a.txt</a></td><td>5</td>",
+            ),
+            (
+                "application/json",
+                "application/json",
+                "{\"name\":\"a.txt\",\"type\":\"file\",\"size\":5,",
+            ),
+        ] {
+            let mut client = TcpStream::connect(&address).await.unwrap();
+
+            client
+                .write_all(
+                    format!("GET / HTTP/1.1\r\nHost: localhost\r\nAccept: {accept}\r\nConnection: close\r\n\r\n")
+                        .as_bytes(),
+                )
+                .await
+                .unwrap();
+
+            let mut response = String::new();
+
+            client.read_to_string(&mut response).await.unwrap();
+
+            assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+            assert!(response.contains(&format!("\r\ncontent-type: {content_type}\r\n")));
+            assert!(response.contains(listing), "{response}");
+            assert!(response.contains("nested"));
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn path_traversal() {
+        let directory = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        let error_log = outside.path().join("error.log");
+
+        std::fs::write(directory.path().join("index.html"), "Hello").unwrap();
+        std::fs::write(outside.path().join("secret.txt"), "Secret").unwrap();
+        std::os::unix::fs::symlink(
+            directory.path().join("index.html"),
+            directory.path().join("link.html"),
+        )
+        .unwrap();
+        std::os::unix::fs::symlink(outside.path(), directory.path().join("outside")).unwrap();
+
+        let server = Server::bind(Options {
+            document_root: directory.path().into(),
+            cgi_bin: CurDir.as_os_str().into(),
+            error_log: Some(error_log.clone()),
+            symlinks: SymlinkPolicy::Deny,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let address = server.address().unwrap();
+
+        tokio::spawn(server.serve());
+
+        for (path, status) in [
+            ("/index.html", "200 OK"),
+            ("/missing.html", "404 Not Found"),
+            ("/../secret.txt", "403 Forbidden"),
+            ("/outside/secret.txt", "403 Forbidden"),
+            ("/link.html", "403 Forbidden"),
+            ("/cgi-bin/..", "403 Forbidden"),
+            ("/cgi-bin/env.cgi/..%2F..%2Fetc%2Fpasswd", "403 Forbidden"),
+        ] {
+            let mut client = TcpStream::connect(&address).await.unwrap();
+
+            client
+                .write_all(
+                    format!("GET {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+                        .as_bytes(),
+                )
+                .await
+                .unwrap();
+
+            let mut response = String::new();
+
+            client.read_to_string(&mut response).await.unwrap();
+
+            assert!(
+                response.starts_with(&format!("HTTP/1.1 {status}\r\n")),
+                "{path}: {response}"
+            );
+            assert!(!response.contains("Secret"));
+        }
+
+        let log = std::fs::read_to_string(&error_log).unwrap();
+
+        assert!(
+            log.contains("Denied access to /link.html: The path has a symbolic link, which is not allowed.\n"),
+            "{log}"
+        );
+        assert!(log.contains("Denied access to /../secret.txt: "));
+    }
+
+    #[tokio::test]
+    async fn static_cgi() {
+        let mut client = connect_to_server().await;
+        let mut output = vec![0; 1024];
+
+        let response_start = "HTTP/1.1 403 Forbidden\r\ncontent-length: 0\r\ndate: ";
+        let response_end = " GMT\r\n\r\n";
+
+        client
+            .write_all(
+                b"GET /hello.cgi HTTP/1.1\r\nHost: localhost\r\nUser-Agent: test\r\nAccept: */*\r\n\r\n",
+            )
+            .await
+            .unwrap();
+
+        assert_ne!(client.read(&mut output).await.unwrap(), 0);
+
+        let response = String::from_utf8_lossy(output.as_slice());
+        let end = response.find('\0').unwrap_or_else(|| response.len());
+
+        assert_eq!(&response[(end - response_end.len())..end], response_end);
+        assert_eq!(&response[..response_start.len()], response_start);
+    }
+
+    #[tokio::test]
+    async fn graceful_shutdown() {
+        let server = Server::bind(Options {
+            document_root: "./examples".into(),
+            cgi_bin: CurDir.as_os_str().into(),
+            cgi_timeout: Duration::from_secs(5),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let address = server.address().unwrap();
+        let shutdown = server.shutdown_handle();
+        let serving = tokio::spawn(server.serve());
+
+        let mut client = TcpStream::connect(&address).await.unwrap();
+
+        client
+            .write_all(b"GET /cgi-bin/slow.cgi HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        shutdown.shutdown();
+
+        let mut output = Vec::new();
+        tokio::time::timeout(Duration::from_secs(5), client.read_to_end(&mut output))
+            .await
+            .unwrap()
+            .unwrap();
+
+        let response = String::from_utf8_lossy(output.as_slice());
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+        assert!(response.contains("connection: close\r\n"));
+        assert!(response.ends_with("Done\n\r\n0\r\n\r\n"));
+
+        tokio::time::timeout(Duration::from_secs(1), serving)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert!(TcpStream::connect(&address).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn shutdown_grace_period() {
+        let server = Server::bind(Options {
+            document_root: "./examples".into(),
+            cgi_bin: CurDir.as_os_str().into(),
+            cgi_timeout: Duration::from_secs(30),
+            shutdown_grace_period: Duration::from_millis(100),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let address = server.address().unwrap();
+        let shutdown = server.shutdown_handle();
+        let serving = tokio::spawn(server.serve());
+
+        let mut client = TcpStream::connect(&address).await.unwrap();
+
+        client
+            .write_all(b"GET /cgi-bin/sleep.cgi HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        shutdown.shutdown();
+
+        let response = read_response(
+            &mut client,
+            "The server shut down before the script finished.",
+        )
+        .await;
+
+        assert!(
+            response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"),
+            "{response}"
+        );
+
+        tokio::time::timeout(Duration::from_secs(2), serving)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+    }
+
+    /// Echoes the script name and path info the script was invoked with.
+    struct Mounted;
+
+    impl crate::Script for Mounted {
+        async fn invoke(
+            &self,
+            context: crate::RequestContext,
+            _: crate::Input,
+        ) -> io::Result<crate::Output> {
+            let variables: std::collections::HashMap<_, _> = context.variables().collect();
+            let body = format!(
+                "Content-Type: text/plain\n\n{} {}",
+                variables["SCRIPT_NAME"],
+                variables.get("PATH_INFO").unwrap_or(&"")
+            );
+
+            Ok(crate::Output::from(bytes::Bytes::from(body)))
+        }
+    }
+
+    #[tokio::test]
+    async fn custom_scripts() {
+        let listener = TcpListener::bind("localhost:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = ServerBuilder::new(Options {
+            document_root: "./examples".into(),
+            cgi_bin: CurDir.as_os_str().into(),
+            ..Default::default()
+        })
+        .listener(listener)
+        .prefix("/api/", Mounted)
+        .extension("cgi", Mounted)
+        .build()
+        .await
+        .unwrap();
+
+        tokio::spawn(server.serve());
+
+        let mut client = TcpStream::connect(&address).await.unwrap();
+
+        client
+            .write_all(b"GET /api/users/42 HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+
+        let response = read_response(&mut client, "/api /users/42").await;
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+
+        client
+            .write_all(b"GET /cgi-bin/hello.cgi/foo HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+
+        let response = read_response(&mut client, "/cgi-bin/hello.cgi /foo").await;
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+    }
+
+    #[tokio::test]
+    async fn router_service() {
+        use http_body_util::{BodyExt, Full};
+        use hyper::service::Service;
+
+        let router = ServerBuilder::new(Options {
+            document_root: "./examples".into(),
+            cgi_bin: CurDir.as_os_str().into(),
+            ..Default::default()
+        })
+        .build_router()
+        .unwrap()
+        .for_client("127.0.0.1:4000".parse().unwrap());
+
+        let request = http::Request::get("/cgi-bin/variables.cgi")
+            .body(Full::new(bytes::Bytes::new()))
+            .unwrap();
+        let response = router.call(request).await.unwrap();
+
+        assert_eq!(response.status(), http::StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let body = String::from_utf8_lossy(&body);
+
+        assert!(body.contains("REMOTE_ADDR=127.0.0.1\n"));
+        assert!(body.contains("REMOTE_PORT=4000\n"));
+    }
+
+    #[tokio::test]
+    async fn router_tower_layer() {
+        use http_body_util::{BodyExt, Full};
+        use tower::{Service, ServiceBuilder, ServiceExt};
+
+        let router = ServerBuilder::new(Options {
+            document_root: "./examples".into(),
+            cgi_bin: CurDir.as_os_str().into(),
+            ..Default::default()
+        })
+        .build_router()
+        .unwrap()
+        .for_client("127.0.0.1:4000".parse().unwrap());
+        let mut service = ServiceBuilder::new()
+            .map_request(|mut request: http::Request<Full<bytes::Bytes>>| {
+                *request.uri_mut() = http::Uri::from_static("/cgi-bin/variables.cgi");
+                request
+            })
+            .map_response(|mut response: http::Response<_>| {
+                response
+                    .headers_mut()
+                    .insert("X-Layer", http::HeaderValue::from_static("tower"));
+                response
+            })
+            .service(router);
+
+        let request = http::Request::get("/rewritten")
+            .body(Full::new(bytes::Bytes::new()))
+            .unwrap();
+        let response = service.ready().await.unwrap().call(request).await.unwrap();
+
+        assert_eq!(response.status(), http::StatusCode::OK);
+        assert_eq!(response.headers()["X-Layer"], "tower");
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+
+        assert!(String::from_utf8_lossy(&body).contains("REMOTE_PORT=4000\n"));
+    }
+
+    #[tokio::test]
+    async fn access_log() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("access.log");
+        let mut client = connect_with_options(Options {
+            document_root: "./examples".into(),
+            cgi_bin: CurDir.as_os_str().into(),
+            access_log_format: Some(LogFormat::Json),
+            access_log: Some(path.clone()),
+            ..Default::default()
+        })
+        .await;
+
+        client
+            .write_all(b"GET /cgi-bin/hello.cgi?foo HTTP/1.1\r\nHost: localhost\r\nUser-Agent: test\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        client.read_to_end(&mut Vec::new()).await.unwrap();
+
+        let mut log = String::new();
+
+        for _ in 0..50 {
+            log = std::fs::read_to_string(&path).unwrap();
+
+            if !log.is_empty() {
+                break;
+            }
+
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        assert!(log.contains("\"uri\":\"/cgi-bin/hello.cgi?foo\""), "{log}");
+        assert!(log.contains("\"status\":200"));
+        assert!(log.contains("\"bytes\":14"));
+        assert!(log.contains("\"user_agent\":\"test\""));
+        assert!(log.contains("hello.cgi\",\"duration_ms\":"));
+    }
+
+    #[tokio::test]
+    async fn metrics() {
+        let server = Server::bind(Options {
+            document_root: "./examples".into(),
+            cgi_bin: CurDir.as_os_str().into(),
+            metrics_address: Some(SocketAddr::from(([127, 0, 0, 1], 0))),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let address = server.address().unwrap();
+        let metrics_address = server.metrics_address().unwrap().unwrap();
+
+        tokio::spawn(server.serve());
+
+        let mut client = TcpStream::connect(&address).await.unwrap();
+
+        client
+            .write_all(
+                b"GET /cgi-bin/hello.cgi HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        client.read_to_end(&mut Vec::new()).await.unwrap();
+
+        let mut client = TcpStream::connect(&metrics_address).await.unwrap();
+
+        client
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+
+        let mut response = String::new();
+
+        client.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+        assert!(response.contains("tortuga_requests_total{route=\"cgi\",status=\"200\"} 1\n"));
+        assert!(response.contains("tortuga_request_duration_seconds_count{route=\"cgi\"} 1\n"));
+        assert!(response
+            .contains("tortuga_script_invocations_total{script=\"/cgi-bin/hello.cgi\"} 1\n"));
+    }
+
+    #[tokio::test]
+    async fn script_stderr() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("error.log");
+        let mut client = connect_with_options(Options {
+            document_root: "./examples".into(),
+            cgi_bin: CurDir.as_os_str().into(),
+            error_log: Some(path.clone()),
+            development: true,
+            ..Default::default()
+        })
+        .await;
+
+        client
+            .write_all(
+                b"GET /cgi-bin/fail.cgi HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            )
+            .await
+            .unwrap();
+
+        let mut output = Vec::new();
+        client.read_to_end(&mut output).await.unwrap();
+
+        let response = String::from_utf8_lossy(output.as_slice());
+
+        assert!(response.starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+        assert!(
+            response.ends_with("exit status: 1.\n\nStandard error:\nSomething went wrong\n"),
+            "{response}"
+        );
+
+        let log = std::fs::read_to_string(&path).unwrap();
+
+        assert!(log.contains("] [request "), "{log}");
+        assert!(log.contains("fail.cgi] Something went wrong\n"));
+    }
+
+    #[tokio::test]
+    async fn h2c_upgrade() {
+        let mut client = connect_to_server().await;
+
+        client
+            .write_all(b"GET /cgi-bin/variables.cgi HTTP/1.1\r\nHost: localhost\r\nConnection: Upgrade, HTTP2-Settings\r\nUpgrade: h2c\r\nHTTP2-Settings: \r\n\r\n")
+            .await
+            .unwrap();
+
+        let response = read_response(&mut client, "\r\n\r\n").await;
+
+        assert!(
+            response.starts_with("HTTP/1.1 101 Switching Protocols\r\n"),
+            "{response}"
+        );
+        assert!(response.contains("upgrade: h2c\r\n"), "{response}");
+
+        // The connection preface, followed by an empty SETTINGS frame.
+        client
+            .write_all(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n\0\0\0\x04\0\0\0\0\0")
+            .await
+            .unwrap();
+
+        let mut status = None;
+        let mut body = Vec::new();
+
+        // The upgrade request is answered on stream 1.
+        loop {
+            let mut header = [0; 9];
+
+            client.read_exact(&mut header).await.unwrap();
+
+            let length = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+            let stream = u32::from_be_bytes([header[5], header[6], header[7], header[8]]);
+            let mut payload = vec![0; length];
+
+            client.read_exact(&mut payload).await.unwrap();
+
+            match (header[3], stream) {
+                (0x1, 1) => status = payload.first().copied(),
+                (0x0, 1) => {
+                    body.extend_from_slice(&payload);
+
+                    if header[4] & 0x1 != 0 {
+                        break;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // `:status: 200`, indexed in the HPACK static table.
+        assert_eq!(status, Some(0x88));
+        assert!(String::from_utf8_lossy(&body).contains("SERVER_PROTOCOL=HTTP/2.0\n"));
+    }
+
+    #[tokio::test]
+    async fn http2_prior_knowledge() {
+        use http_body_util::{BodyExt, Empty};
+
+        let client = connect_to_server().await;
+        let (mut sender, connection) =
+            hyper::client::conn::http2::handshake(TokioExecutor::new(), TokioIo::new(client))
+                .await
+                .unwrap();
+
+        tokio::spawn(connection);
+
+        let request = http::Request::get("http://localhost/cgi-bin/variables.cgi")
+            .body(Empty::<bytes::Bytes>::new())
+            .unwrap();
+        let response = sender.send_request(request).await.unwrap();
+
+        assert_eq!(response.status(), http::StatusCode::OK);
+        assert_eq!(response.version(), http::Version::HTTP_2);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let body = String::from_utf8_lossy(&body);
+
+        assert!(body.contains("SERVER_PROTOCOL=HTTP/2.0\n"));
+        assert!(body.contains("HTTP_HOST=localhost\n"));
+    }
+
+    #[tokio::test]
+    async fn tls() {
+        use tokio_rustls::rustls::pki_types::ServerName;
+        use tokio_rustls::rustls::{crypto, ClientConfig, RootCertStore};
+        use tokio_rustls::TlsConnector;
+
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let mut certificate = tempfile::NamedTempFile::new().unwrap();
+        let mut key = tempfile::NamedTempFile::new().unwrap();
+
+        std::io::Write::write_all(&mut certificate, certified.cert.pem().as_bytes()).unwrap();
+        std::io::Write::write_all(&mut key, certified.key_pair.serialize_pem().as_bytes()).unwrap();
+
+        let server = Server::bind(Options {
+            document_root: "./examples".into(),
+            cgi_bin: CurDir.as_os_str().into(),
+            tls_cert: Some(certificate.path().to_path_buf()),
+            tls_key: Some(key.path().to_path_buf()),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let address = server.address().unwrap();
+        let shutdown = server.shutdown_handle();
+        let serving = tokio::spawn(server.serve());
+
+        let mut roots = RootCertStore::empty();
+        roots.add(certified.cert.der().clone()).unwrap();
+
+        let config =
+            ClientConfig::builder_with_provider(Arc::new(crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_root_certificates(roots)
+                .with_no_client_auth();
+        let stream = TcpStream::connect(&address).await.unwrap();
+        let mut client = TlsConnector::from(Arc::new(config))
+            .connect(ServerName::try_from("localhost").unwrap(), stream)
+            .await
+            .unwrap();
+
+        client
+            .write_all(
+                b"GET /cgi-bin/variables.cgi HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            )
+            .await
+            .unwrap();
+
+        let mut output = Vec::new();
+        client.read_to_end(&mut output).await.unwrap();
+
+        let response = String::from_utf8_lossy(output.as_slice());
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+        assert!(response.contains("HTTPS=on\n"));
+        assert!(response.contains("SCRIPT_URI=https://"));
+        assert!(response.contains("SSL_PROTOCOL=TLSv1.3\n"));
+
+        // A client that never sends its ClientHello does not hold up the shutdown.
+        let _idle = TcpStream::connect(&address).await.unwrap();
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        shutdown.shutdown();
+
+        tokio::time::timeout(Duration::from_secs(2), serving)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+    }
+
+    /// Reads from the client until the response ends as expected, since streamed responses may span multiple reads.
+    async fn read_response(client: &mut TcpStream, response_end: &str) -> String {
+        let mut response = Vec::new();
+        let mut output = vec![0; 1024];
+
+        while !response.ends_with(response_end.as_bytes()) {
+            let read = tokio::time::timeout(Duration::from_secs(5), client.read(&mut output))
+                .await
+                .unwrap_or(Ok(0))
+                .unwrap();
+
+            if read == 0 {
+                break;
+            }
+
+            response.extend_from_slice(&output[..read]);
+        }
+
+        String::from_utf8_lossy(response.as_slice()).to_string()
+    }
+
+    async fn connect_to_server() -> TcpStream {
+        connect_with_options(Options {
+            document_root: "./examples".into(),
+            cgi_bin: CurDir.as_os_str().into(),
+            hostname: "localhost".to_string(),
+            port: 0,
+            wasm_cache: true,
+            ..Default::default()
+        })
+        .await
+    }
+
+    async fn connect_with_options(options: Options) -> TcpStream {
+        let server = Server::bind(options).await.unwrap();
         let address = server.address().unwrap();
 
         tokio::spawn(server.serve());
@@ -1,6 +1,18 @@
-use std::path::PathBuf;
+use crate::server::{mime, LogFormat};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::net::SocketAddr;
+use std::path::Component::CurDir;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
 
-#[derive(Clone)]
+/// The size of a WebAssembly linear memory page.
+const WASM_PAGE_BYTES: u64 = 1024 * 64;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Options {
     /// The document root path to load CGI scripts and other assets from.
     pub document_root: PathBuf,
@@ -20,4 +32,767 @@ pub struct Options {
 
     /// Pre-load compiled WebAssembly modules into the in-memory cache.
     pub preload_wasm: bool,
+
+    /// The maximum number of seconds a CGI or WebAssembly script may run before it is terminated.
+    #[serde(with = "seconds")]
+    pub cgi_timeout: Duration,
+
+    /// A directory to store compiled WebAssembly modules in, so they are not recompiled after a restart.
+    /// Modules are loaded from the directory as native code, so it must only be writable by trusted users.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wasm_disk_cache: Option<PathBuf>,
+
+    /// A directory to load precompiled `.cwasm` artifacts from, mirroring the CGI bin directory,
+    /// as written by `tortuga compile --output`. Artifacts are loaded from next to their scripts when unset.
+    /// Artifacts are loaded as native code, so the directory must only be writable by trusted users.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wasm_artifacts: Option<PathBuf>,
+
+    /// The amount of fuel each WebAssembly script invocation is given to run with.
+    pub wasm_fuel: u64,
+
+    /// The maximum number of seconds a WebAssembly script may run before it is stopped.
+    /// Falls back to the CGI script timeout (`cgi_timeouts`, then `cgi_timeout`) when unset.
+    #[serde(with = "optional_seconds", skip_serializing_if = "Option::is_none")]
+    pub wasm_deadline: Option<Duration>,
+
+    /// The most linear memory, in bytes, each WebAssembly script invocation may grow to.
+    /// Set to `"unlimited"` in the configuration file to remove the limit.
+    #[serde(with = "limit")]
+    pub wasm_max_memory_bytes: Option<u64>,
+
+    /// The most elements each table of a WebAssembly script invocation may grow to.
+    /// Set to `"unlimited"` in the configuration file to remove the limit.
+    #[serde(with = "limit")]
+    pub wasm_max_table_elements: Option<u64>,
+
+    /// Overrides the WebAssembly limits for specific script names (e.g., `/cgi-bin/echo.wcgi`).
+    pub wasm_limits: BTreeMap<String, WasmLimits>,
+
+    /// How WebAssembly scripts may access the document root, which is preopened at its path on the host
+    /// so `PATH_TRANSLATED` resolves inside the script: `read-only` (the default), `read-write` or `none`.
+    pub wasm_document_root: DirectoryAccess,
+
+    /// Give each WebAssembly script invocation an empty, writable directory at `/tmp`, removed once it finishes.
+    pub wasm_scratch_directory: bool,
+
+    /// Additional host directories available to WebAssembly scripts, keyed by their path in the guest (e.g., `/data`).
+    pub wasm_directories: BTreeMap<String, WasmDirectory>,
+
+    /// Allocate WebAssembly instances from pre-allocated pools instead of on demand.
+    /// Pooling reduces the cost of instantiating a script on every request, at the cost of reserving memory up front.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wasm_pooling: Option<WasmPooling>,
+
+    /// The largest request body, in bytes, the server will pass to a CGI script.
+    /// Set to `"unlimited"` in the configuration file to allow bodies of any size.
+    #[serde(with = "limit")]
+    pub max_body_bytes: Option<u64>,
+
+    /// Script names (e.g., `/cgi-bin/upload.cgi`) that require a `CONTENT_LENGTH`.
+    /// Request bodies without a known length are spooled to a temporary file before invoking these scripts.
+    pub require_content_length: BTreeSet<String>,
+
+    /// The number of seconds between scans of the CGI bin directory for WebAssembly modules.
+    #[serde(with = "seconds")]
+    pub scan_interval: Duration,
+
+    /// Overrides the CGI script timeout for specific script names (e.g., `/cgi-bin/git.cgi`).
+    #[serde(with = "seconds_map")]
+    pub cgi_timeouts: BTreeMap<String, Duration>,
+
+    /// The number of seconds in-flight requests are given to finish once the server begins to shut down.
+    /// Any CGI scripts still running afterwards are terminated.
+    #[serde(with = "seconds")]
+    pub shutdown_grace_period: Duration,
+
+    /// The format of the access log entries written for each request.
+    /// Access logging is disabled unless a format is set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub access_log_format: Option<LogFormat>,
+
+    /// The path of a file to append access log entries to, instead of standard output.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub access_log: Option<PathBuf>,
+
+    /// The size in bytes the access log file may grow to before it is rotated.
+    pub access_log_max_bytes: u64,
+
+    /// The number of rotated access log files to keep (e.g., `access.log.1`).
+    pub access_log_max_files: usize,
+
+    /// The path of a file to append diagnostics to, such as the standard error of CGI scripts, instead of standard error.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_log: Option<PathBuf>,
+
+    /// The most standard error output, in bytes, captured from each script invocation.
+    pub stderr_max_bytes: usize,
+
+    /// Include the standard error of failed scripts in `500 Internal Server Error` responses.
+    /// Intended for local development only, since it may expose sensitive details to clients.
+    pub development: bool,
+
+    /// The files served (or run, for scripts such as `index.cgi`) for requests of a directory, in order of preference.
+    pub index_files: Vec<String>,
+
+    /// List the contents of directories without an index file, as HTML or, for clients that accept it, JSON.
+    pub autoindex: bool,
+
+    /// Whether symbolic links are followed when serving static files and scripts: `follow` (the default),
+    /// `follow-if-owner-matches` or `deny`. Links are never followed outside of the document root or CGI bin directory.
+    pub symlinks: SymlinkPolicy,
+
+    /// Overrides or extends the built-in `Content-Type` of static files by extension (e.g., `md = "text/markdown"`).
+    /// Text types are served with `charset=utf-8` unless they name a charset.
+    pub mime_types: BTreeMap<String, String>,
+
+    /// Send `X-Content-Type-Options: nosniff` with static files, so browsers trust their `Content-Type`.
+    pub nosniff: bool,
+
+    /// Serve a precompressed sibling of a static file (e.g., `app.js.br` or `app.js.gz`) to clients that accept its encoding.
+    pub precompressed: bool,
+
+    /// Compress the responses of scripts with gzip, brotli or zstd, as negotiated with `Accept-Encoding`.
+    /// Only textual content types are compressed, and never responses that set their own `Content-Encoding`.
+    pub compression: bool,
+
+    /// The size in bytes a script response must reach before it is compressed, since small responses gain little.
+    pub compression_min_bytes: u64,
+
+    /// The address of a separate listener serving Prometheus metrics at `/metrics` (e.g., `127.0.0.1:9090`).
+    /// Metrics are not exposed unless an address is set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metrics_address: Option<SocketAddr>,
+
+    /// The path to a PEM-encoded certificate chain to terminate TLS connections with.
+    /// Requires `tls_key` to be set as well.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// The path to the PEM-encoded private key for the TLS certificate.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls_key: Option<PathBuf>,
+}
+
+/// Limits for a specific WebAssembly script. Limits that are not set fall back to the server's.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WasmLimits {
+    /// The amount of fuel each invocation of the script is given to run with.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fuel: Option<u64>,
+
+    /// The most linear memory, in bytes, each invocation of the script may grow to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_memory_bytes: Option<u64>,
+
+    /// The most elements each table of the script may grow to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_table_elements: Option<u64>,
+
+    /// The maximum number of seconds each invocation of the script may run before it is stopped.
+    #[serde(with = "optional_seconds", skip_serializing_if = "Option::is_none")]
+    pub deadline: Option<Duration>,
+}
+
+/// How a WebAssembly script may access a preopened directory.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DirectoryAccess {
+    /// The directory is not available to scripts.
+    None,
+    /// Files may be read, but not created, written, renamed or removed.
+    #[default]
+    ReadOnly,
+    ReadWrite,
+}
+
+impl FromStr for DirectoryAccess {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "none" => Ok(Self::None),
+            "read-only" => Ok(Self::ReadOnly),
+            "read-write" => Ok(Self::ReadWrite),
+            _ => Err(format!(
+                "expected one of `none`, `read-only` or `read-write`, found `{value}`"
+            )),
+        }
+    }
+}
+
+/// Whether request paths may resolve through symbolic links.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SymlinkPolicy {
+    #[default]
+    Follow,
+    /// Links are only followed when the link and its target are owned by the same user.
+    FollowIfOwnerMatches,
+    Deny,
+}
+
+impl FromStr for SymlinkPolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "follow" => Ok(Self::Follow),
+            "follow-if-owner-matches" => Ok(Self::FollowIfOwnerMatches),
+            "deny" => Ok(Self::Deny),
+            _ => Err(format!(
+                "expected one of `follow`, `follow-if-owner-matches` or `deny`, found `{value}`"
+            )),
+        }
+    }
+}
+
+/// A host directory available to WebAssembly scripts.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct WasmDirectory {
+    /// The path of the directory on the host.
+    pub path: PathBuf,
+
+    /// How scripts may access the directory.
+    #[serde(default)]
+    pub access: DirectoryAccess,
+}
+
+/// The sizes of the pools WebAssembly instances are allocated from.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WasmPooling {
+    /// The most WebAssembly scripts that may run concurrently.
+    pub instances: u32,
+
+    /// The most core instances a `.whttp` component may contain, since every module inside it is instantiated separately.
+    /// The core instance pool holds this many for each concurrent script.
+    pub core_instances_per_component: u32,
+
+    /// The most linear memories a `.whttp` component may contain, which sizes the memory pool like the core instances.
+    pub memories_per_component: u32,
+
+    /// The most tables a `.whttp` component may contain, which sizes the table pool like the core instances.
+    pub tables_per_component: u32,
+
+    /// The size in bytes of each slot in the memory pool, which bounds the linear memory of every script.
+    pub memory_bytes: u64,
+
+    /// The number of elements in each slot in the table pool, which bounds the tables of every script.
+    pub table_elements: u32,
+
+    /// Initialize linear memories by mapping the module's data copy-on-write, instead of copying it into every instance.
+    pub copy_on_write: bool,
+}
+
+impl Default for WasmPooling {
+    fn default() -> Self {
+        Self {
+            instances: 1000,
+            core_instances_per_component: 8,
+            memories_per_component: 2,
+            tables_per_component: 4,
+            memory_bytes: 1024 * 1024 * 128,
+            table_elements: 10_000,
+            copy_on_write: true,
+        }
+    }
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            document_root: PathBuf::from(CurDir.as_os_str()),
+            cgi_bin: PathBuf::from(CurDir.as_os_str()),
+            hostname: "localhost".to_string(),
+            port: 0,
+            wasm_cache: false,
+            preload_wasm: false,
+            wasm_disk_cache: None,
+            wasm_artifacts: None,
+            cgi_timeout: Duration::from_secs(1),
+            wasm_fuel: 1_000_000,
+            wasm_deadline: None,
+            wasm_max_memory_bytes: Some(1024 * 1024 * 128),
+            wasm_max_table_elements: Some(10_000),
+            wasm_limits: BTreeMap::new(),
+            wasm_pooling: None,
+            wasm_document_root: DirectoryAccess::ReadOnly,
+            wasm_scratch_directory: false,
+            wasm_directories: BTreeMap::new(),
+            max_body_bytes: Some(1024 * 64),
+            require_content_length: BTreeSet::new(),
+            scan_interval: Duration::from_secs(300),
+            cgi_timeouts: BTreeMap::new(),
+            shutdown_grace_period: Duration::from_secs(30),
+            access_log_format: None,
+            access_log: None,
+            access_log_max_bytes: 1024 * 1024 * 10,
+            access_log_max_files: 5,
+            error_log: None,
+            stderr_max_bytes: 1024 * 64,
+            development: false,
+            index_files: vec!["index.html".to_string(), "index.htm".to_string()],
+            autoindex: false,
+            symlinks: SymlinkPolicy::Follow,
+            mime_types: BTreeMap::new(),
+            nosniff: false,
+            precompressed: true,
+            compression: false,
+            compression_min_bytes: 1024,
+            metrics_address: None,
+            tls_cert: None,
+            tls_key: None,
+        }
+    }
+}
+
+impl Options {
+    /// Reads the options from a TOML configuration file.
+    /// Keys missing from the file are set to their default values.
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)?;
+
+        Self::from_toml(&contents)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", path.display())))
+    }
+
+    /// Parses and validates the options from the contents of a TOML configuration file.
+    pub fn from_toml(contents: &str) -> io::Result<Self> {
+        let options: Self = toml::from_str(contents)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+
+        options.validate()?;
+
+        Ok(options)
+    }
+
+    /// Validates the options and makes the document root and CGI bin paths absolute.
+    pub(crate) fn resolve_paths(&mut self) -> io::Result<()> {
+        self.validate()?;
+
+        self.document_root = self.document_root.canonicalize()?;
+
+        // An absolute CGI bin path replaces the document root when joined, but is still canonicalized,
+        // since scripts are checked against it.
+        self.cgi_bin = self.document_root.join(&self.cgi_bin).canonicalize()?;
+
+        Ok(())
+    }
+
+    /// Renders the options as a TOML configuration file.
+    pub fn to_toml(&self) -> io::Result<String> {
+        toml::to_string_pretty(self).map_err(io::Error::other)
+    }
+
+    /// Verifies the options are consistent with each other and within supported bounds.
+    pub fn validate(&self) -> io::Result<()> {
+        if self.preload_wasm && !self.wasm_cache {
+            return Err(invalid_key(
+                "preload_wasm",
+                "requires `wasm_cache` to be enabled",
+            ));
+        }
+
+        if self.cgi_timeout.is_zero() {
+            return Err(invalid_key("cgi_timeout", "must be greater than zero"));
+        }
+
+        for (script_name, timeout) in &self.cgi_timeouts {
+            let key = format!("cgi_timeouts.\"{script_name}\"");
+
+            if !script_name.starts_with("/cgi-bin/") {
+                return Err(invalid_key(&key, "script name must start with `/cgi-bin/`"));
+            }
+
+            if timeout.is_zero() {
+                return Err(invalid_key(&key, "must be greater than zero"));
+            }
+        }
+
+        if let Some(pooling) = &self.wasm_pooling {
+            for (key, count) in [
+                ("wasm_pooling.instances", pooling.instances),
+                (
+                    "wasm_pooling.core_instances_per_component",
+                    pooling.core_instances_per_component,
+                ),
+                (
+                    "wasm_pooling.memories_per_component",
+                    pooling.memories_per_component,
+                ),
+                (
+                    "wasm_pooling.tables_per_component",
+                    pooling.tables_per_component,
+                ),
+            ] {
+                if count == 0 {
+                    return Err(invalid_key(key, "must be greater than zero"));
+                }
+            }
+
+            if pooling.memory_bytes % WASM_PAGE_BYTES != 0 {
+                return Err(invalid_key(
+                    "wasm_pooling.memory_bytes",
+                    "must be a multiple of the 64 KiB WebAssembly page size",
+                ));
+            }
+        }
+
+        for guest in self.wasm_directories.keys() {
+            if !guest.starts_with('/') {
+                return Err(invalid_key(
+                    &format!("wasm_directories.\"{guest}\""),
+                    "guest path must be absolute",
+                ));
+            }
+        }
+
+        if self
+            .wasm_deadline
+            .is_some_and(|deadline| deadline.is_zero())
+        {
+            return Err(invalid_key("wasm_deadline", "must be greater than zero"));
+        }
+
+        for (script_name, limits) in &self.wasm_limits {
+            let key = format!("wasm_limits.\"{script_name}\"");
+
+            if !script_name.starts_with("/cgi-bin/") {
+                return Err(invalid_key(&key, "script name must start with `/cgi-bin/`"));
+            }
+
+            if limits.deadline.is_some_and(|deadline| deadline.is_zero()) {
+                return Err(invalid_key(
+                    &format!("{key}.deadline"),
+                    "must be greater than zero",
+                ));
+            }
+        }
+
+        for script_name in &self.require_content_length {
+            if !script_name.starts_with("/cgi-bin/") {
+                return Err(invalid_key(
+                    "require_content_length",
+                    "script names must start with `/cgi-bin/`",
+                ));
+            }
+        }
+
+        for index_file in &self.index_files {
+            if index_file.is_empty() || index_file.contains('/') || index_file.starts_with('.') {
+                return Err(invalid_key(
+                    "index_files",
+                    "file names must be non-empty, without a `/` and not start with a `.`",
+                ));
+            }
+        }
+
+        for (extension, media_type) in &self.mime_types {
+            let key = format!("mime_types.\"{extension}\"");
+
+            if extension.is_empty() || extension.contains('.') {
+                return Err(invalid_key(
+                    &key,
+                    "extension must be non-empty and without a `.`",
+                ));
+            }
+
+            if mime::header_value(media_type).is_none() {
+                return Err(invalid_key(&key, "must be a valid media type"));
+            }
+        }
+
+        if self.wasm_fuel == 0 {
+            return Err(invalid_key("wasm_fuel", "must be greater than zero"));
+        }
+
+        if self.scan_interval.is_zero() {
+            return Err(invalid_key("scan_interval", "must be greater than zero"));
+        }
+
+        if self.access_log.is_some() && self.access_log_format.is_none() {
+            return Err(invalid_key(
+                "access_log",
+                "requires `access_log_format` to be set",
+            ));
+        }
+
+        if self.access_log_max_bytes == 0 {
+            return Err(invalid_key(
+                "access_log_max_bytes",
+                "must be greater than zero",
+            ));
+        }
+
+        match (&self.tls_cert, &self.tls_key) {
+            (Some(_), None) => return Err(invalid_key("tls_cert", "requires `tls_key` to be set")),
+            (None, Some(_)) => return Err(invalid_key("tls_key", "requires `tls_cert` to be set")),
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+fn invalid_key(key: &str, reason: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("invalid value for key `{key}`: {reason}"),
+    )
+}
+
+mod seconds {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(duration.as_secs())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        u64::deserialize(deserializer).map(Duration::from_secs)
+    }
+}
+
+mod optional_seconds {
+    use serde::{Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(
+        duration: &Option<Duration>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match duration {
+            Some(duration) => super::seconds::serialize(duration, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Duration>, D::Error> {
+        super::seconds::deserialize(deserializer).map(Some)
+    }
+}
+
+mod limit {
+    use serde::de::{self, Visitor};
+    use serde::{Deserializer, Serializer};
+    use std::fmt;
+
+    const UNLIMITED: &str = "unlimited";
+
+    pub fn serialize<S: Serializer>(limit: &Option<u64>, serializer: S) -> Result<S::Ok, S::Error> {
+        match limit {
+            Some(limit) => serializer.serialize_u64(*limit),
+            None => serializer.serialize_str(UNLIMITED),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<u64>, D::Error> {
+        deserializer.deserialize_any(LimitVisitor)
+    }
+
+    struct LimitVisitor;
+
+    impl<'de> Visitor<'de> for LimitVisitor {
+        type Value = Option<u64>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            write!(formatter, "a non-negative integer or \"{UNLIMITED}\"")
+        }
+
+        fn visit_i64<E: de::Error>(self, value: i64) -> Result<Self::Value, E> {
+            u64::try_from(value)
+                .map(Some)
+                .map_err(|_| E::invalid_value(de::Unexpected::Signed(value), &self))
+        }
+
+        fn visit_u64<E: de::Error>(self, value: u64) -> Result<Self::Value, E> {
+            Ok(Some(value))
+        }
+
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+            if value == UNLIMITED {
+                Ok(None)
+            } else {
+                Err(E::invalid_value(de::Unexpected::Str(value), &self))
+            }
+        }
+    }
+}
+
+mod seconds_map {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::collections::BTreeMap;
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(
+        durations: &BTreeMap<String, Duration>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(durations.iter().map(|(k, v)| (k, v.as_secs())))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<BTreeMap<String, Duration>, D::Error> {
+        let seconds = BTreeMap::<String, u64>::deserialize(deserializer)?;
+
+        Ok(seconds
+            .into_iter()
+            .map(|(k, v)| (k, Duration::from_secs(v)))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_keys_use_defaults() {
+        let options = Options::from_toml("port = 8080\ncgi_timeout = 30").unwrap();
+
+        assert_eq!(options.port, 8080);
+        assert_eq!(options.cgi_timeout, Duration::from_secs(30));
+        assert_eq!(options.hostname, Options::default().hostname);
+        assert_eq!(options.wasm_fuel, Options::default().wasm_fuel);
+    }
+
+    #[test]
+    fn unknown_key() {
+        let error = Options::from_toml("prot = 8080").unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("prot"));
+    }
+
+    #[test]
+    fn invalid_type() {
+        let error = Options::from_toml("wasm_fuel = \"lots\"").unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("wasm_fuel"));
+    }
+
+    #[test]
+    fn invalid_value() {
+        let error = Options::from_toml("cgi_timeout = 0").unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(error.to_string().contains("`cgi_timeout`"));
+    }
+
+    #[test]
+    fn script_timeouts() {
+        let options = Options::from_toml("[cgi_timeouts]\n\"/cgi-bin/git.cgi\" = 300").unwrap();
+
+        assert_eq!(
+            options.cgi_timeouts.get("/cgi-bin/git.cgi"),
+            Some(&Duration::from_secs(300))
+        );
+
+        let error = Options::from_toml("[cgi_timeouts]\n\"git.cgi\" = 300").unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(error.to_string().contains("`cgi_timeouts.\"git.cgi\"`"));
+    }
+
+    #[test]
+    fn unlimited_body() {
+        let options = Options::from_toml("max_body_bytes = \"unlimited\"").unwrap();
+
+        assert_eq!(options.max_body_bytes, None);
+        assert_eq!(
+            Options::from_toml("max_body_bytes = 42")
+                .unwrap()
+                .max_body_bytes,
+            Some(42)
+        );
+
+        let error = Options::from_toml("max_body_bytes = \"lots\"").unwrap_err();
+
+        assert!(error.to_string().contains("max_body_bytes"));
+    }
+
+    #[test]
+    fn tls_requires_key() {
+        let error = Options::from_toml("tls_cert = \"cert.pem\"").unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(error.to_string().contains("`tls_cert`"));
+        assert!(Options::from_toml("tls_cert = \"cert.pem\"\ntls_key = \"key.pem\"").is_ok());
+    }
+
+    #[test]
+    fn index_files() {
+        let options = Options::from_toml("index_files = [\"index.cgi\"]").unwrap();
+
+        assert_eq!(options.index_files, vec!["index.cgi".to_string()]);
+        assert_eq!(
+            Options::default().index_files,
+            vec!["index.html".to_string(), "index.htm".to_string()]
+        );
+
+        let error = Options::from_toml("index_files = [\"../index.html\"]").unwrap_err();
+
+        assert!(error.to_string().contains("`index_files`"));
+    }
+
+    #[test]
+    fn mime_types() {
+        let options = Options::from_toml("[mime_types]\nmd = \"text/markdown\"").unwrap();
+
+        assert_eq!(
+            options.mime_types.get("md").map(String::as_str),
+            Some("text/markdown")
+        );
+
+        let error = Options::from_toml("[mime_types]\n\".md\" = \"text/markdown\"").unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(error.to_string().contains("`mime_types.\".md\"`"));
+
+        let error = Options::from_toml("[mime_types]\nmd = \"\"").unwrap_err();
+
+        assert!(error.to_string().contains("`mime_types.\"md\"`"));
+    }
+
+    #[test]
+    fn round_trip() {
+        let options = Options {
+            port: 3000,
+            wasm_cache: true,
+            preload_wasm: true,
+            max_body_bytes: None,
+            cgi_timeouts: BTreeMap::from([(
+                "/cgi-bin/git.cgi".to_string(),
+                Duration::from_secs(60),
+            )]),
+            wasm_limits: BTreeMap::from([(
+                "/cgi-bin/report.wcgi".to_string(),
+                WasmLimits {
+                    deadline: Some(Duration::from_secs(120)),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+        let parsed = Options::from_toml(&options.to_toml().unwrap()).unwrap();
+
+        assert_eq!(parsed.port, options.port);
+        assert_eq!(parsed.preload_wasm, options.preload_wasm);
+        assert_eq!(parsed.scan_interval, options.scan_interval);
+        assert_eq!(parsed.max_body_bytes, options.max_body_bytes);
+        assert_eq!(parsed.cgi_timeouts, options.cgi_timeouts);
+        assert_eq!(parsed.wasm_deadline, None);
+        assert_eq!(parsed.wasm_limits, options.wasm_limits);
+    }
 }
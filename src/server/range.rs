@@ -0,0 +1,112 @@
+use std::ops::RangeInclusive;
+
+/// The most ranges served from a single request, so clients cannot ask for a file one byte at a time.
+const MAX_RANGES: usize = 16;
+
+/// The parts of a file a `Range` header asks for.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Ranges {
+    /// The whole file, since the header is missing, malformed or asks for too many ranges.
+    Full,
+    /// None of the ranges overlap the file.
+    Unsatisfiable,
+    /// The satisfiable ranges, in the order they were requested.
+    Partial(Vec<RangeInclusive<u64>>),
+}
+
+/// Parses a `Range` header of byte ranges (e.g., `bytes=0-499, -500`) for a file of the given length.
+pub fn parse(header: &[u8], length: u64) -> Ranges {
+    let Some(ranges) = std::str::from_utf8(header)
+        .ok()
+        .and_then(|header| header.trim().strip_prefix("bytes="))
+    else {
+        return Ranges::Full;
+    };
+
+    let mut satisfiable = Vec::new();
+    let mut count = 0;
+
+    for range in ranges.split(',').map(str::trim).filter(|r| !r.is_empty()) {
+        count += 1;
+
+        if count > MAX_RANGES {
+            return Ranges::Full;
+        }
+
+        let Some((first, last)) = range.split_once('-') else {
+            return Ranges::Full;
+        };
+        let bounds = match (first.trim(), last.trim()) {
+            ("", suffix) => match suffix.parse::<u64>() {
+                Ok(0) => None,
+                Ok(suffix) => Some(length.saturating_sub(suffix)..=length.saturating_sub(1)),
+                Err(_) => return Ranges::Full,
+            },
+            (first, "") => match first.parse::<u64>() {
+                Ok(first) => Some(first..=length.saturating_sub(1)),
+                Err(_) => return Ranges::Full,
+            },
+            (first, last) => match (first.parse::<u64>(), last.parse::<u64>()) {
+                (Ok(first), Ok(last)) if first <= last => {
+                    Some(first..=last.min(length.saturating_sub(1)))
+                }
+                _ => return Ranges::Full,
+            },
+        };
+
+        if let Some(bounds) = bounds.filter(|bounds| *bounds.start() < length) {
+            satisfiable.push(bounds);
+        }
+    }
+
+    match (count, satisfiable.is_empty()) {
+        (0, _) => Ranges::Full,
+        (_, true) => Ranges::Unsatisfiable,
+        (_, false) => Ranges::Partial(satisfiable),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single() {
+        assert_eq!(parse(b"bytes=0-499", 1000), Ranges::Partial(vec![0..=499]));
+        assert_eq!(parse(b"bytes=500-", 1000), Ranges::Partial(vec![500..=999]));
+        assert_eq!(parse(b"bytes=-200", 1000), Ranges::Partial(vec![800..=999]));
+        assert_eq!(parse(b"bytes=-2000", 1000), Ranges::Partial(vec![0..=999]));
+        assert_eq!(
+            parse(b"bytes=900-2000", 1000),
+            Ranges::Partial(vec![900..=999])
+        );
+    }
+
+    #[test]
+    fn multiple() {
+        assert_eq!(
+            parse(b"bytes=0-0, 2000-3000, -1", 1000),
+            Ranges::Partial(vec![0..=0, 999..=999])
+        );
+        assert_eq!(parse(b"bytes=", 1000), Ranges::Full);
+
+        let many = format!("bytes={}", vec!["0-0"; MAX_RANGES + 1].join(","));
+
+        assert_eq!(parse(many.as_bytes(), 1000), Ranges::Full);
+    }
+
+    #[test]
+    fn unsatisfiable() {
+        assert_eq!(parse(b"bytes=1000-", 1000), Ranges::Unsatisfiable);
+        assert_eq!(parse(b"bytes=-0", 1000), Ranges::Unsatisfiable);
+        assert_eq!(parse(b"bytes=0-", 0), Ranges::Unsatisfiable);
+    }
+
+    #[test]
+    fn malformed() {
+        assert_eq!(parse(b"items=0-1", 1000), Ranges::Full);
+        assert_eq!(parse(b"bytes=5-1", 1000), Ranges::Full);
+        assert_eq!(parse(b"bytes=a-b", 1000), Ranges::Full);
+        assert_eq!(parse(b"bytes=1", 1000), Ranges::Full);
+    }
+}
@@ -1,36 +1,29 @@
+use crate::script::Input;
 use bytes::Bytes;
-use http::{Request, Response, StatusCode};
-use http_body_util::{BodyExt, Collected, Full};
-use hyper::body::{Body, Incoming};
-
-const MAX_BODY_BYTES: u64 = 1024 * 64;
+use http::Request;
+use hyper::body::Body;
+use std::error::Error;
+use std::io;
 
 pub trait CgiRequest {
-    async fn buffer(self) -> Result<Request<Bytes>, Response<Full<Bytes>>>;
+    fn into_input(self, max_body_bytes: Option<u64>) -> io::Result<Request<Input>>;
 }
 
-impl CgiRequest for Request<Incoming> {
-    async fn buffer(self) -> Result<Request<Bytes>, Response<Full<Bytes>>> {
-        let upper = self.body().size_hint().upper().unwrap_or(u64::MAX);
-        if upper > MAX_BODY_BYTES {
-            let mut response = Response::new(Full::from(format!(
-                "Body size of {upper} bytes is too large. The largest supported body is {MAX_BODY_BYTES}"
-            )));
-            *response.status_mut() = StatusCode::PAYLOAD_TOO_LARGE;
-            return Err(response);
-        }
+impl<B> CgiRequest for Request<B>
+where
+    B: Body<Data = Bytes> + Send + 'static,
+    B::Error: Into<Box<dyn Error + Send + Sync>>,
+{
+    fn into_input(self, max_body_bytes: Option<u64>) -> io::Result<Request<Input>> {
+        let lower = self.body().size_hint().lower();
 
-        let (parts, body) = self.into_parts();
-        let body = match body.collect().await.map(Collected::to_bytes) {
-            Ok(b) => b,
-            Err(_) => {
-                let mut response =
-                    Response::new(Full::from("Unable to read the full request body."));
-                *response.status_mut() = StatusCode::UNPROCESSABLE_ENTITY;
-                return Err(response);
-            }
-        };
+        if let Some(max_body_bytes) = max_body_bytes.filter(|max| lower > *max) {
+            return Err(io::Error::new(
+                io::ErrorKind::FileTooLarge,
+                format!("Body size of {lower} bytes is too large. The largest supported body is {max_body_bytes} bytes."),
+            ));
+        }
 
-        Ok(Request::from_parts(parts, body))
+        Ok(self.map(|body| Input::new(body, max_body_bytes)))
     }
 }
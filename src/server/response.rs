@@ -1,22 +1,51 @@
+use crate::script::Output;
 use bytes::Bytes;
 use http::{HeaderName, HeaderValue, Response, StatusCode};
-use http_body_util::Full;
+use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt, Empty, Full};
 use httparse::Status;
 use hyper::body::Body;
 use std::io;
 use std::str::FromStr;
 
+/// The body of every response sent by the server.
+pub type ResponseBody = BoxBody<Bytes, io::Error>;
+
+/// A response body with the given contents.
+pub fn full(contents: impl Into<Bytes>) -> ResponseBody {
+    Full::new(contents.into())
+        .map_err(|never| match never {})
+        .boxed()
+}
+
+/// A response body without any contents.
+pub fn empty() -> ResponseBody {
+    Empty::new().map_err(|never| match never {}).boxed()
+}
+
+/// Marks a response from a component that handles the HTTP request itself, which is sent as-is
+/// instead of being interpreted as a CGI document or redirect.
+#[derive(Clone, Copy, Debug)]
+pub struct HttpResponse;
+
 pub trait CgiResponse {
+    fn is_http(&self) -> bool;
     fn is_document(&self) -> bool;
     fn is_redirect(&self) -> bool;
     fn is_local_redirect(&self) -> bool;
     fn is_client_redirect(&self) -> bool;
     fn is_client_redirect_with_document(&self) -> bool;
 
-    fn parse_headers(&mut self, output: &Bytes) -> io::Result<usize>;
+    /// Parses the header block at the start of the script's output.
+    /// Returns the offset of the body, or `None` if the header block is incomplete.
+    fn parse_headers(&mut self, output: &[u8]) -> io::Result<Option<usize>>;
 }
 
-impl CgiResponse for Response<Full<Bytes>> {
+impl CgiResponse for Response<Output> {
+    fn is_http(&self) -> bool {
+        self.extensions().get::<HttpResponse>().is_some()
+    }
+
     fn is_document(&self) -> bool {
         (self.status().is_success() || self.status().is_client_error())
             && self.headers().contains_key(http::header::CONTENT_TYPE)
@@ -59,7 +88,7 @@ impl CgiResponse for Response<Full<Bytes>> {
             && self.headers().contains_key(http::header::CONTENT_TYPE)
     }
 
-    fn parse_headers(&mut self, output: &Bytes) -> io::Result<usize> {
+    fn parse_headers(&mut self, output: &[u8]) -> io::Result<Option<usize>> {
         let mut headers = [httparse::EMPTY_HEADER; 256];
 
         match httparse::parse_headers(output, &mut headers) {
@@ -80,7 +109,7 @@ impl CgiResponse for Response<Full<Bytes>> {
                             ) {
                                 (Ok(name), Ok(value)) => {
                                     if !name.as_str().starts_with("x-cgi-") {
-                                        self.headers_mut().insert(name, value);
+                                        self.headers_mut().append(name, value);
                                     }
                                 }
                                 _ => {
@@ -94,12 +123,9 @@ impl CgiResponse for Response<Full<Bytes>> {
                     }
                 }
 
-                Ok(offset)
+                Ok(Some(offset))
             }
-            Ok(Status::Partial) => Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "Received partial response headers from the CGI script.",
-            )),
+            Ok(Status::Partial) => Ok(None),
             Err(_) => Err(io::Error::new(
                 io::ErrorKind::InvalidData,
                 "Received invalid response headers from the CGI script.",
@@ -1,18 +1,39 @@
-use crate::context::{ClientContext, ServerContext};
-use crate::server::{self, request::CgiRequest, response::CgiResponse};
+use crate::context::{self, ClientContext, IndexScript, RequestId, ServerContext};
+use crate::metrics::RouteKind;
+use crate::script::{Input, Output};
+use crate::server::access::{Entry, Logged};
+use crate::server::autoindex;
+use crate::server::conditional::Validators;
+use crate::server::encoding::{self, Encoding};
+use crate::server::file::{self, Part};
+use crate::server::range::{self, Ranges};
+use crate::server::response::{self, CgiResponse, ResponseBody};
+use crate::server::{self, confine, request::CgiRequest};
+use bytes::{Bytes, BytesMut};
+use http::request::Parts;
 use http::uri::PathAndQuery;
 use http::{HeaderValue, Method, Request, Response, StatusCode};
-use http_body_util::Full;
-use hyper::body::{Bytes, Incoming};
+use http_body_util::BodyExt;
+use hyper::body::Body;
 use hyper::service::Service;
 use server::handler::CgiHandler;
+use std::collections::hash_map::RandomState;
+use std::error::Error;
 use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
 use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::ops::RangeInclusive;
+use std::path::Path;
 use std::pin::Pin;
 use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime};
 use tokio::fs::File;
-use tokio::io::AsyncReadExt;
 
+/// Routes requests to CGI scripts or static files in the document root.
+/// Routers are cheap to clone and can be mounted as a [`Service`] inside a larger application,
+/// either directly with hyper or wrapped in `tower` layers.
 #[derive(Clone)]
 pub struct Router {
     server: Arc<ServerContext>,
@@ -20,52 +41,200 @@ pub struct Router {
 }
 
 impl Router {
-    pub fn new(server: Arc<ServerContext>, client: Arc<ClientContext>) -> Self {
+    pub(crate) fn new(server: Arc<ServerContext>, client: Arc<ClientContext>) -> Self {
         Self { server, client }
     }
 
-    pub async fn route(
+    /// Creates a router for a server context without a known client.
+    /// The `REMOTE_ADDR` of requests is the unspecified address until set with [`Router::for_client`].
+    pub(crate) fn detached(server: Arc<ServerContext>) -> Self {
+        let client = ClientContext::new(SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)));
+
+        Self::new(server, Arc::new(client))
+    }
+
+    /// A copy of this router for requests from the client at the given address.
+    pub fn for_client(&self, remote_address: SocketAddr) -> Self {
+        Self::new(
+            self.server.clone(),
+            Arc::new(ClientContext::new(remote_address)),
+        )
+    }
+
+    pub async fn route<B>(
         self,
-        request: Request<Incoming>,
-    ) -> Result<Response<Full<Bytes>>, http::Error> {
-        let request = match request.buffer().await {
-            Ok(value) => value,
-            Err(value) => return Ok(value),
+        mut request: Request<B>,
+    ) -> Result<Response<ResponseBody>, http::Error>
+    where
+        B: Body<Data = Bytes> + Send + 'static,
+        B::Error: Into<Box<dyn Error + Send + Sync>>,
+    {
+        request
+            .extensions_mut()
+            .insert(self.server.next_request_id());
+
+        let started = Instant::now();
+        let route = if self.server.is_script_path(request.uri().path()) {
+            RouteKind::Cgi
+        } else {
+            RouteKind::Static
+        };
+        let server = self.server.clone();
+        let Some(log) = self.server.access_log().cloned() else {
+            let response = self.respond(request).await?;
+
+            server
+                .metrics()
+                .request(route, response.status().as_u16(), started.elapsed());
+
+            return Ok(response);
+        };
+
+        let mut entry = self.log_entry(&request);
+        let response = self.respond(request).await?;
+
+        entry.status = response.status().as_u16();
+        server
+            .metrics()
+            .request(route, entry.status, started.elapsed());
+
+        Ok(response.map(|body| Logged::new(body, entry, started, log).boxed()))
+    }
+
+    fn log_entry<B>(&self, request: &Request<B>) -> Entry {
+        let header = |name| {
+            request
+                .headers()
+                .get(name)
+                .map(|value: &HeaderValue| String::from_utf8_lossy(value.as_bytes()).to_string())
         };
+        let path = request.uri().path();
 
+        Entry {
+            time: SystemTime::now(),
+            request_id: request
+                .extensions()
+                .get::<RequestId>()
+                .map(ToString::to_string)
+                .unwrap_or_default(),
+            remote_address: self.client.remote_ip_address().to_string(),
+            method: request.method().to_string(),
+            uri: request
+                .uri()
+                .path_and_query()
+                .map(PathAndQuery::as_str)
+                .unwrap_or(path)
+                .to_string(),
+            protocol: context::protocol(request.version()),
+            status: 0,
+            bytes: 0,
+            referer: header(http::header::REFERER),
+            user_agent: header(http::header::USER_AGENT),
+            script: self
+                .server
+                .is_script_path(path)
+                .then(|| self.server.script_path(path))
+                .flatten(),
+            duration: Duration::ZERO,
+        }
+    }
+
+    async fn respond<B>(self, request: Request<B>) -> Result<Response<ResponseBody>, http::Error>
+    where
+        B: Body<Data = Bytes> + Send + 'static,
+        B::Error: Into<Box<dyn Error + Send + Sync>>,
+    {
         let ignore_body = request.method() == Method::HEAD;
-        let result = match (request.method(), request.uri().path()) {
-            (_, path) if path.starts_with("/cgi-bin/") => self.invoke_cgi(request).await,
-            (method, path) => self.load_file(method, path).await,
+        let request_id = request.extensions().get::<RequestId>().cloned();
+        let path = request.uri().path().to_string();
+        let result = match request.into_input(self.server.max_body_bytes()) {
+            Ok(request) if self.server.is_script_path(request.uri().path()) => {
+                self.run_script(request).await
+            }
+            Ok(request) => self.serve_static(request).await,
+            Err(e) => Err(e),
         };
 
         match result {
             Ok(mut response) => {
                 if ignore_body {
-                    *response.body_mut() = Full::default();
+                    *response.body_mut() = response::empty();
                 }
 
                 Ok(response)
             }
+            Err(e) if confine::is_violation(&e) => {
+                self.server.error_log().write(
+                    request_id.as_ref().map(|id| id.0.as_str()),
+                    None,
+                    format_args!("Denied access to {path}: {e}"),
+                );
+
+                Response::builder()
+                    .status(StatusCode::FORBIDDEN)
+                    .body(response::empty())
+            }
             Err(e) if e.kind() == io::ErrorKind::NotFound => Response::builder()
                 .status(StatusCode::NOT_FOUND)
-                .body(Full::default()),
+                .body(response::empty()),
+            Err(e) if e.kind() == io::ErrorKind::FileTooLarge => Response::builder()
+                .status(StatusCode::PAYLOAD_TOO_LARGE)
+                .body(response::full(e.to_string())),
+            // A script that exits with a non-zero code failed the way an upstream server would.
+            Err(e) if e.kind() == io::ErrorKind::ConnectionAborted => Response::builder()
+                .status(StatusCode::BAD_GATEWAY)
+                .body(response::full(e.to_string())),
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => Response::builder()
+                .status(StatusCode::GATEWAY_TIMEOUT)
+                .body(response::full(e.to_string())),
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::Interrupted | io::ErrorKind::QuotaExceeded
+                ) =>
+            {
+                Response::builder()
+                    .status(StatusCode::SERVICE_UNAVAILABLE)
+                    .body(response::full(e.to_string()))
+            }
             Err(e) => Response::builder()
                 .status(StatusCode::INTERNAL_SERVER_ERROR)
-                .body(Full::from(e.to_string())),
+                .body(response::full(e.to_string())),
         }
     }
 
-    async fn invoke_cgi(&self, mut request: Request<Bytes>) -> io::Result<Response<Full<Bytes>>> {
+    /// Runs the script for a request, compressing its response if the client accepts it.
+    async fn run_script(&self, request: Request<Input>) -> io::Result<Response<ResponseBody>> {
+        let accept_encoding = request
+            .headers()
+            .get(http::header::ACCEPT_ENCODING)
+            .cloned();
+        let response = self.invoke_cgi(request).await?;
+
+        match self.server.compression_min_bytes() {
+            Some(min_bytes) => compress(response, accept_encoding.as_ref(), min_bytes).await,
+            None => Ok(response.map(BodyExt::boxed)),
+        }
+    }
+
+    async fn invoke_cgi(&self, request: Request<Input>) -> io::Result<Response<Output>> {
+        let (mut parts, input) = request.into_parts();
+        let mut input = Some(input);
+
         for _ in 0..10 {
             let handler = CgiHandler::new(self.server.clone(), self.client.clone());
 
-            let mut response = handler.serve(request.clone()).await?;
+            // The body can only be read once, so local redirects are sent without one.
+            let request = Request::from_parts(parts.clone(), input.take().unwrap_or_default());
+            let mut response = handler.serve(request).await?;
 
-            if response.is_document() || response.is_client_redirect_with_document() {
+            if response.is_http()
+                || response.is_document()
+                || response.is_client_redirect_with_document()
+            {
                 return Ok(response);
             } else if response.is_local_redirect() {
-                let mut parts = request.uri().clone().into_parts();
+                let mut uri = parts.uri.clone().into_parts();
 
                 let location = response
                     .headers()
@@ -81,9 +250,10 @@ impl Router {
                     )
                 })?;
 
-                parts.path_and_query = Some(path_and_query);
+                uri.path_and_query = Some(path_and_query);
+                parts.extensions.remove::<IndexScript>();
 
-                *request.uri_mut() = http::Uri::from_parts(parts).map_err(|_| {
+                parts.uri = http::Uri::from_parts(uri).map_err(|_| {
                     io::Error::new(io::ErrorKind::InvalidInput, "Invalid URI parts.")
                 })?;
 
@@ -102,67 +272,382 @@ impl Router {
         ))
     }
 
-    async fn load_file(&self, method: &Method, path: &str) -> io::Result<Response<Full<Bytes>>> {
-        let file_path = self.server.resolve_path(path);
+    /// Serves a file from the document root, or the index of a directory.
+    async fn serve_static(
+        &self,
+        mut request: Request<Input>,
+    ) -> io::Result<Response<ResponseBody>> {
+        let path = self.server.resolve_path(request.uri().path())?;
+
+        if !tokio::fs::metadata(&path).await?.is_dir() {
+            return self.load_file(&request.into_parts().0, &path).await;
+        }
+
+        // Relative links in an index only resolve within the directory when its path ends with a `/`.
+        if !request.uri().path().ends_with('/') {
+            let location = match request.uri().query() {
+                Some(query) => format!("{}/?{query}", request.uri().path()),
+                None => format!("{}/", request.uri().path()),
+            };
+
+            return Response::builder()
+                .status(StatusCode::MOVED_PERMANENTLY)
+                .header(http::header::LOCATION, location)
+                .body(response::empty())
+                .map_err(io::Error::other);
+        }
+
+        match self.server.index_file(&path)? {
+            Some(index) if self.server.is_script_file(&index) => {
+                let script_name = format!(
+                    "{}{}",
+                    request.uri().path(),
+                    index.file_name().unwrap_or_default().to_string_lossy()
+                );
+
+                request.extensions_mut().insert(IndexScript {
+                    file: index,
+                    script_name,
+                });
+
+                self.run_script(request).await
+            }
+            Some(index) => self.load_file(&request.into_parts().0, &index).await,
+            None if self.server.is_autoindex() => {
+                self.list_directory(&request.into_parts().0, &path).await
+            }
+            None => Response::builder()
+                .status(StatusCode::FORBIDDEN)
+                .body(response::empty())
+                .map_err(io::Error::other),
+        }
+    }
+
+    /// Lists a directory without an index file, as JSON for clients that accept it and HTML otherwise.
+    async fn list_directory(
+        &self,
+        request: &Parts,
+        directory: &Path,
+    ) -> io::Result<Response<ResponseBody>> {
+        if request.method != Method::GET && request.method != Method::HEAD {
+            let mut response = Response::new(response::empty());
+            *response.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
+            return Ok(response);
+        }
+
+        let entries = autoindex::entries(directory).await?;
+        let json = request
+            .headers
+            .get(http::header::ACCEPT)
+            .is_some_and(|accept| {
+                String::from_utf8_lossy(accept.as_bytes()).contains("application/json")
+            });
+        let (listing, content_type) = if json {
+            (autoindex::json(&entries)?, "application/json")
+        } else {
+            (
+                autoindex::html(request.uri.path(), &entries),
+                "text/html; charset=utf-8",
+            )
+        };
+        let mut response = Response::new(response::empty());
+
+        self.file_headers(
+            &mut response,
+            HeaderValue::from_static(content_type),
+            listing.len() as u64,
+        );
+        *response.body_mut() = response::full(listing);
+
+        Ok(response)
+    }
+
+    async fn load_file(
+        &self,
+        request: &Parts,
+        file_path: &Path,
+    ) -> io::Result<Response<ResponseBody>> {
+        let method = &request.method;
 
         if file_path.extension() == Some("cgi".as_ref()) {
-            let mut response = Response::new(Full::default());
+            let mut response = Response::new(response::empty());
             *response.status_mut() = StatusCode::FORBIDDEN;
             return Ok(response);
         }
 
-        match *method {
-            Method::HEAD => {
-                let file = File::open(file_path).await?;
-                let metadata = file.metadata().await?;
-                let mut response = Response::new(Full::default());
+        if method != Method::GET && method != Method::HEAD {
+            let mut response = Response::new(response::empty());
+            *response.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
+            return Ok(response);
+        }
+
+        let headers = &request.headers;
+        let content_type = self.server.content_type(file_path);
+
+        // A precompressed sibling is served as the file itself, with its own length and validators.
+        let precompressed = self.server.precompressed_files(file_path)?;
+        let available: Vec<_> = precompressed
+            .iter()
+            .map(|(encoding, _)| *encoding)
+            .collect();
+        let negotiated =
+            encoding::negotiate(headers.get(http::header::ACCEPT_ENCODING), &available);
+        let (file_path, encoding) = match precompressed
+            .into_iter()
+            .find(|(encoding, _)| Some(*encoding) == negotiated)
+        {
+            Some((encoding, sibling)) => (sibling, Some(encoding)),
+            None => (file_path.to_path_buf(), None),
+        };
+
+        let file = File::open(&file_path).await?;
+        let metadata = file.metadata().await?;
+        let length = metadata.len();
+        let validators = Validators::new(&metadata);
+        let mut response = Response::new(response::empty());
+
+        validators.insert(response.headers_mut());
+        response.headers_mut().insert(
+            http::header::ACCEPT_RANGES,
+            HeaderValue::from_static("bytes"),
+        );
+
+        if !available.is_empty() {
+            response.headers_mut().insert(
+                http::header::VARY,
+                HeaderValue::from_static("accept-encoding"),
+            );
+        }
+
+        if let Some(encoding) = encoding {
+            response
+                .headers_mut()
+                .insert(http::header::CONTENT_ENCODING, encoding.header_value());
+        }
 
+        if validators.is_not_modified(headers) {
+            *response.status_mut() = StatusCode::NOT_MODIFIED;
+            return Ok(response);
+        }
+
+        // Ranges only apply to GET requests, and only for the version of the file the client already has part of.
+        let ranges = match headers.get(http::header::RANGE) {
+            Some(range) if method == Method::GET && validators.is_range_current(headers) => {
+                range::parse(range.as_bytes(), length)
+            }
+            _ => Ranges::Full,
+        };
+
+        match ranges {
+            Ranges::Full => {
+                self.file_headers(&mut response, content_type, length);
+
+                if method == Method::GET && length > 0 {
+                    *response.body_mut() = file::stream(file, vec![Part::Range(0..=length - 1)]);
+                }
+            }
+            Ranges::Unsatisfiable => {
+                *response.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
                 response.headers_mut().insert(
-                    http::header::CONTENT_LENGTH,
-                    HeaderValue::from(metadata.len()),
+                    http::header::CONTENT_RANGE,
+                    HeaderValue::try_from(format!("bytes */{length}")).map_err(io::Error::other)?,
                 );
+            }
+            Ranges::Partial(mut ranges) if ranges.len() == 1 => {
+                let range = ranges.remove(0);
 
-                Ok(response)
+                *response.status_mut() = StatusCode::PARTIAL_CONTENT;
+                self.file_headers(&mut response, content_type, range.end() - range.start() + 1);
+                response.headers_mut().insert(
+                    http::header::CONTENT_RANGE,
+                    HeaderValue::try_from(content_range(&range, length))
+                        .map_err(io::Error::other)?,
+                );
+                *response.body_mut() = file::stream(file, vec![Part::Range(range)]);
             }
-            Method::GET => {
-                let mut file = File::open(file_path).await?;
+            Ranges::Partial(ranges) => {
+                let boundary = format!("{:016x}", RandomState::new().build_hasher().finish());
+                let part_type = String::from_utf8_lossy(content_type.as_bytes()).to_string();
+                let mut parts = Vec::with_capacity(ranges.len() * 2 + 1);
+                let mut body_length = 0;
 
-                let metadata = file.metadata().await?;
-                let length = usize::try_from(metadata.len()).map_err(|_| {
-                    io::Error::new(
-                        io::ErrorKind::OutOfMemory,
-                        "Unable to buffer file contents.",
-                    )
-                })?;
+                for range in ranges {
+                    let head = format!(
+                        "\r\n--{boundary}\r\nContent-Type: {part_type}\r\nContent-Range: {}\r\n\r\n",
+                        content_range(&range, length)
+                    );
 
-                let mut buffer = Vec::with_capacity(length);
+                    body_length += head.len() as u64 + range.end() - range.start() + 1;
+                    parts.push(Part::Bytes(Bytes::from(head)));
+                    parts.push(Part::Range(range));
+                }
 
-                file.read_to_end(&mut buffer).await?;
+                let tail = format!("\r\n--{boundary}--\r\n");
 
-                let mut response = Response::new(Full::from(buffer));
+                body_length += tail.len() as u64;
+                parts.push(Part::Bytes(Bytes::from(tail)));
 
-                response.headers_mut().insert(
-                    http::header::CONTENT_LENGTH,
-                    HeaderValue::from(metadata.len()),
-                );
+                let content_type =
+                    HeaderValue::try_from(format!("multipart/byteranges; boundary={boundary}"))
+                        .map_err(io::Error::other)?;
 
-                Ok(response)
+                *response.status_mut() = StatusCode::PARTIAL_CONTENT;
+                self.file_headers(&mut response, content_type, body_length);
+                *response.body_mut() = file::stream(file, parts);
             }
-            _ => {
-                let mut response = Response::new(Full::default());
-                *response.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
-                Ok(response)
+        }
+
+        Ok(response)
+    }
+
+    /// Describes a static file's contents, so clients do not have to guess its type.
+    fn file_headers(
+        &self,
+        response: &mut Response<ResponseBody>,
+        content_type: HeaderValue,
+        length: u64,
+    ) {
+        let headers = response.headers_mut();
+
+        headers.insert(http::header::CONTENT_LENGTH, HeaderValue::from(length));
+        headers.insert(http::header::CONTENT_TYPE, content_type);
+
+        if self.server.is_nosniff() {
+            headers.insert(
+                http::header::X_CONTENT_TYPE_OPTIONS,
+                HeaderValue::from_static("nosniff"),
+            );
+        }
+    }
+}
+
+/// How long a response without a `Content-Length` is buffered to learn whether it reaches the minimum size for compression.
+const COMPRESSION_WAIT: Duration = Duration::from_millis(50);
+
+/// Compresses a script's response once it reaches the minimum size, unless the script encoded it already
+/// or its content type is not worth compressing. Eligible responses vary by `Accept-Encoding` either way.
+async fn compress(
+    response: Response<Output>,
+    accept_encoding: Option<&HeaderValue>,
+    min_bytes: u64,
+) -> io::Result<Response<ResponseBody>> {
+    let headers = response.headers();
+    let no_transform = headers
+        .get_all(http::header::CACHE_CONTROL)
+        .iter()
+        .any(|value| {
+            String::from_utf8_lossy(value.as_bytes())
+                .to_ascii_lowercase()
+                .contains("no-transform")
+        });
+    let eligible = !no_transform
+        && !headers.contains_key(http::header::CONTENT_ENCODING)
+        && !headers.contains_key(http::header::CONTENT_RANGE)
+        && !matches!(
+            response.status(),
+            StatusCode::PARTIAL_CONTENT | StatusCode::NO_CONTENT | StatusCode::NOT_MODIFIED
+        )
+        && headers
+            .get(http::header::CONTENT_TYPE)
+            .is_some_and(encoding::is_compressible);
+
+    if !eligible {
+        return Ok(response.map(BodyExt::boxed));
+    }
+
+    let (mut parts, mut output) = response.into_parts();
+
+    parts.headers.append(
+        http::header::VARY,
+        HeaderValue::from_static("accept-encoding"),
+    );
+
+    let Some(encoding) = encoding::negotiate(accept_encoding, &Encoding::ALL) else {
+        return Ok(Response::from_parts(parts, output.boxed()));
+    };
+
+    let length = parts
+        .headers
+        .get(http::header::CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok()?.parse::<u64>().ok());
+    let mut buffered = BytesMut::new();
+    let mut small = length.is_some_and(|length| length < min_bytes);
+    let deadline = tokio::time::Instant::now() + COMPRESSION_WAIT;
+
+    // Without a length, the output is read until it is known to reach the minimum size.
+    // Slow scripts are compressed once the wait is over, so their headers are not held back.
+    while length.is_none() && (buffered.len() as u64) < min_bytes {
+        match tokio::time::timeout_at(deadline, output.chunk()).await {
+            Ok(Some(chunk)) => buffered.extend_from_slice(&chunk?),
+            Ok(None) => {
+                small = true;
+                break;
+            }
+            Err(_) => break,
+        }
+    }
+
+    output.unread(buffered.freeze());
+
+    // Clients that refuse uncompressed responses get small ones compressed as well.
+    if small && !encoding::refuses_identity(accept_encoding) {
+        return Ok(Response::from_parts(parts, output.boxed()));
+    }
+
+    let encoded = encoding::encode(output, encoding)?;
+
+    // The compressed bytes differ from what a strong entity tag promises.
+    if let Some(etag) = parts.headers.get(http::header::ETAG) {
+        if etag.as_bytes().starts_with(b"\"") {
+            let weak = [b"W/", etag.as_bytes()].concat();
+
+            if let Ok(weak) = HeaderValue::from_bytes(&weak) {
+                parts.headers.insert(http::header::ETAG, weak);
             }
         }
     }
+
+    parts.headers.remove(http::header::CONTENT_LENGTH);
+    parts
+        .headers
+        .insert(http::header::CONTENT_ENCODING, encoding.header_value());
+
+    Ok(Response::from_parts(parts, encoded.boxed()))
 }
 
-impl Service<Request<Incoming>> for Router {
-    type Response = Response<Full<Bytes>>;
+fn content_range(range: &RangeInclusive<u64>, length: u64) -> String {
+    format!("bytes {}-{}/{length}", range.start(), range.end())
+}
+
+impl<B> Service<Request<B>> for Router
+where
+    B: Body<Data = Bytes> + Send + 'static,
+    B::Error: Into<Box<dyn Error + Send + Sync>>,
+{
+    type Response = Response<ResponseBody>;
     type Error = http::Error;
     type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
 
-    fn call(&self, request: Request<Incoming>) -> Self::Future {
+    fn call(&self, request: Request<B>) -> Self::Future {
+        Box::pin(self.clone().route(request))
+    }
+}
+
+impl<B> tower_service::Service<Request<B>> for Router
+where
+    B: Body<Data = Bytes> + Send + 'static,
+    B::Error: Into<Box<dyn Error + Send + Sync>>,
+{
+    type Response = Response<ResponseBody>;
+    type Error = http::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    /// Routers hold no per-request resources, so they are always ready.
+    fn poll_ready(&mut self, _context: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
         Box::pin(self.clone().route(request))
     }
 }
@@ -0,0 +1,117 @@
+use std::io;
+use std::sync::Arc;
+use tokio::sync::watch;
+
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+enum Phase {
+    Running,
+    Draining,
+    Terminating,
+}
+
+/// A handle to gracefully shut down a running server, e.g. from a signal handler.
+/// The server stops accepting connections and lets in-flight requests finish within its grace period.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    sender: Arc<watch::Sender<Phase>>,
+}
+
+impl ShutdownHandle {
+    pub(crate) fn new() -> Self {
+        let (sender, _) = watch::channel(Phase::Running);
+
+        Self {
+            sender: Arc::new(sender),
+        }
+    }
+
+    /// Stops accepting new connections and asks keep-alive connections to close after their current request.
+    pub fn shutdown(&self) {
+        self.advance(Phase::Draining);
+    }
+
+    /// Terminates any CGI scripts that are still running.
+    pub(crate) fn terminate(&self) {
+        self.advance(Phase::Terminating);
+    }
+
+    pub(crate) fn signal(&self) -> ShutdownSignal {
+        ShutdownSignal {
+            receiver: self.sender.subscribe(),
+        }
+    }
+
+    fn advance(&self, phase: Phase) {
+        self.sender.send_if_modified(|current| {
+            let advanced = *current < phase;
+
+            if advanced {
+                *current = phase;
+            }
+
+            advanced
+        });
+    }
+}
+
+/// Notifies connections and scripts of the progress of a server shutdown.
+/// Every phase is considered reached once the server is dropped.
+#[derive(Clone)]
+pub struct ShutdownSignal {
+    receiver: watch::Receiver<Phase>,
+}
+
+impl ShutdownSignal {
+    /// Waits until the server stops accepting new connections.
+    pub async fn draining(&self) {
+        self.reached(Phase::Draining).await
+    }
+
+    /// Waits until the grace period ends and remaining scripts must be terminated.
+    pub async fn terminating(&self) {
+        self.reached(Phase::Terminating).await
+    }
+
+    async fn reached(&self, phase: Phase) {
+        let mut receiver = self.receiver.clone();
+        let _ = receiver.wait_for(|current| *current >= phase).await;
+    }
+}
+
+/// The error for a script that was terminated because the server shut down.
+pub fn interrupted() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Interrupted,
+        "The server shut down before the script finished.",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn phases() {
+        let handle = ShutdownHandle::new();
+        let signal = handle.signal();
+
+        handle.shutdown();
+
+        tokio::time::timeout(Duration::from_secs(1), signal.draining())
+            .await
+            .unwrap();
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), signal.terminating())
+                .await
+                .is_err()
+        );
+
+        handle.terminate();
+        handle.shutdown();
+
+        tokio::time::timeout(Duration::from_secs(1), signal.terminating())
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,91 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::server::ServerConnection;
+use tokio_rustls::rustls::{crypto, ProtocolVersion, ServerConfig};
+use tokio_rustls::TlsAcceptor;
+
+/// Loads a PEM-encoded certificate chain and private key to terminate TLS connections with.
+/// Reloading replaces the configuration for new connections without affecting existing ones.
+#[derive(Clone)]
+pub struct CertificateLoader {
+    certificate: PathBuf,
+    key: PathBuf,
+    config: Arc<RwLock<Arc<ServerConfig>>>,
+}
+
+impl CertificateLoader {
+    pub fn new(certificate: PathBuf, key: PathBuf) -> io::Result<Self> {
+        let config = Self::load(&certificate, &key)?;
+
+        Ok(Self {
+            certificate,
+            key,
+            config: Arc::new(RwLock::new(config)),
+        })
+    }
+
+    pub fn reload(&self) -> io::Result<()> {
+        let config = Self::load(&self.certificate, &self.key)?;
+        let mut guard = self.config.write().unwrap_or_else(|e| e.into_inner());
+
+        *guard = config;
+
+        Ok(())
+    }
+
+    pub fn acceptor(&self) -> TlsAcceptor {
+        let config = self.config.read().unwrap_or_else(|e| e.into_inner());
+
+        TlsAcceptor::from(config.clone())
+    }
+
+    fn load(certificate: &Path, key: &Path) -> io::Result<Arc<ServerConfig>> {
+        let certificates = CertificateDer::pem_file_iter(certificate)
+            .and_then(|certificates| certificates.collect::<Result<Vec<_>, _>>())
+            .map_err(|e| invalid_pem(certificate, e))?;
+        let key = PrivateKeyDer::from_pem_file(key).map_err(|e| invalid_pem(key, e))?;
+
+        let mut config =
+            ServerConfig::builder_with_provider(Arc::new(crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .map_err(io::Error::other)?
+                .with_no_client_auth()
+                .with_single_cert(certificates, key)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+        Ok(Arc::new(config))
+    }
+}
+
+fn invalid_pem(path: &Path, error: impl std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{}: {error}", path.display()),
+    )
+}
+
+/// The name of the negotiated protocol version (e.g., `TLSv1.3`).
+pub fn protocol(connection: &ServerConnection) -> String {
+    match connection.protocol_version() {
+        Some(ProtocolVersion::TLSv1_2) => "TLSv1.2".to_string(),
+        Some(ProtocolVersion::TLSv1_3) => "TLSv1.3".to_string(),
+        Some(version) => format!("{version:?}"),
+        None => String::new(),
+    }
+}
+
+/// The name of the negotiated cipher suite (e.g., `TLS13_AES_256_GCM_SHA384`).
+pub fn cipher(connection: &ServerConnection) -> String {
+    match connection.negotiated_cipher_suite().map(|s| s.suite()) {
+        Some(suite) => suite
+            .as_str()
+            .map(String::from)
+            .unwrap_or_else(|| format!("{suite:?}")),
+        None => String::new(),
+    }
+}
@@ -1,55 +1,270 @@
-use std::collections::hash_map::Entry;
+use crate::deadline::Deadline;
+use crate::metrics::Metrics;
+use crate::server::{ErrorLog, Options};
+use disk::{Artifact, DiskCache};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use sha2::{Digest, Sha256};
 use std::collections::{HashMap, HashSet, VecDeque};
 use std::io;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::sync::RwLock;
-use wasi_common::WasiCtx;
-use wasmtime::{Config, Engine, InstancePre, Linker, Module, Store};
+use std::sync::{RwLock, RwLockWriteGuard};
+use std::time::{Duration, SystemTime};
+use wasmtime::component::{self, Component};
+use wasmtime::{
+    Config, Engine, InstanceAllocationStrategy, InstancePre, Linker, Module,
+    PoolingAllocationConfig, Store, Trap, UpdateDeadline,
+};
+
+mod disk;
+mod http;
+mod limits;
+mod module;
+mod preopen;
+
+pub use http::{incoming_request, HttpCtx};
+pub use limits::{LimitExceeded, Limiter};
+pub use module::ModuleCtx;
+pub use preopen::Preopens;
+
+/// The size of a WebAssembly linear memory page.
+const PAGE_BYTES: u64 = 1024 * 64;
+
+/// How often the epoch of the engine advances, which bounds the precision of script deadlines.
+const EPOCH_TICK: Duration = Duration::from_millis(10);
+
+/// The data of a store running a WebAssembly script.
+/// Core modules use a [`ModuleCtx`], while components use an [`HttpCtx`].
+pub struct State<T = ModuleCtx> {
+    pub wasi: T,
+    pub limiter: Limiter,
+}
+
+/// A WebAssembly script, linked and ready to be instantiated.
+#[derive(Clone)]
+pub enum Loaded {
+    /// A core module run as a CGI script.
+    Module(InstancePre<State>),
+    /// A component exporting the `wasi:http/incoming-handler` interface.
+    Component(component::InstancePre<State<HttpCtx>>),
+}
+
+type ModuleCache = Arc<RwLock<HashMap<PathBuf, Cached>>>;
+
+/// A compiled module along with the state of the file it was compiled from.
+#[derive(Clone)]
+struct Cached {
+    instance: Loaded,
+    fingerprint: Fingerprint,
+    digest: [u8; 32],
+}
+
+/// The modification time and size of a module, compared on every load so a stale module is never served.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct Fingerprint {
+    modified: Option<SystemTime>,
+    len: u64,
+}
+
+impl Fingerprint {
+    fn of(path: &Path) -> io::Result<Self> {
+        let metadata = std::fs::metadata(path)?;
+
+        Ok(Self {
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+        })
+    }
+}
 
 #[derive(Clone)]
 pub struct ModuleLoader {
-    cache: Option<Arc<RwLock<HashMap<PathBuf, InstancePre<WasiCtx>>>>>,
+    cache: Option<ModuleCache>,
+    disk_cache: Option<DiskCache>,
     engine: Engine,
     root: PathBuf,
+    /// The directory precompiled artifacts are loaded from, instead of next to their scripts.
+    artifacts: Option<PathBuf>,
+    metrics: Arc<Metrics>,
+    error_log: Arc<ErrorLog>,
+    /// Keeps the epoch ticker running for as long as any clone of the loader exists.
+    _ticker: Arc<()>,
 }
 
 impl ModuleLoader {
-    pub fn new(root: PathBuf, cache: bool) -> Result<Self, wasmtime::Error> {
+    pub fn new(
+        options: &Options,
+        metrics: Arc<Metrics>,
+        error_log: Arc<ErrorLog>,
+    ) -> Result<Self, wasmtime::Error> {
         let mut configuration = Config::new();
 
         configuration
             .async_support(true)
             .consume_fuel(true)
+            .epoch_interruption(true)
             .parallel_compilation(true);
 
+        if let Some(pooling) = &options.wasm_pooling {
+            let mut pool = PoolingAllocationConfig::default();
+            // A component holds several core instances, memories and tables,
+            // so their pools have room for as many components as scripts may run at once.
+            let per_component = |count: u32| pooling.instances.saturating_mul(count);
+
+            pool.total_component_instances(pooling.instances)
+                .total_core_instances(per_component(pooling.core_instances_per_component))
+                .max_core_instances_per_component(pooling.core_instances_per_component)
+                .total_memories(per_component(pooling.memories_per_component))
+                .max_memories_per_component(pooling.memories_per_component)
+                .total_tables(per_component(pooling.tables_per_component))
+                .max_tables_per_component(pooling.tables_per_component)
+                .total_stacks(pooling.instances)
+                .memory_pages(pooling.memory_bytes / PAGE_BYTES)
+                .table_elements(pooling.table_elements);
+
+            configuration
+                .allocation_strategy(InstanceAllocationStrategy::Pooling(pool))
+                .memory_init_cow(pooling.copy_on_write);
+        }
+
         let engine = Engine::new(&configuration)?;
-        let cache = if cache {
+        let cache = if options.wasm_cache {
             Some(Arc::new(RwLock::new(HashMap::new())))
         } else {
             None
         };
 
+        let disk_cache = match &options.wasm_disk_cache {
+            Some(directory) => Some(DiskCache::new(
+                directory.clone(),
+                &engine,
+                error_log.clone(),
+            )?),
+            None => None,
+        };
+        let root = options.cgi_bin.clone();
+
+        let ticker = start_ticker(engine.clone())?;
+
         Ok(Self {
             cache,
+            disk_cache,
             engine,
             root,
+            artifacts: options.wasm_artifacts.clone(),
+            metrics,
+            error_log,
+            _ticker: ticker,
         })
     }
 
-    pub fn new_store(&self, wasi_ctx: WasiCtx) -> Store<WasiCtx> {
-        Store::new(&self.engine, wasi_ctx)
+    /// Creates a store with the memory and table limits enforced, which traps once the deadline passes.
+    /// The deadline is checked again whenever it seems to pass, since it stands still while the script waits on the client.
+    pub fn new_store<T>(&self, state: State<T>, deadline: Arc<Deadline>) -> Store<State<T>> {
+        let mut store = Store::new(&self.engine, state);
+
+        store.limiter(|state| &mut state.limiter);
+        store.set_epoch_deadline(ticks(deadline.remaining()));
+        store.epoch_deadline_callback(move |_| match deadline.remaining() {
+            remaining if remaining.is_zero() => Err(Trap::Interrupt.into()),
+            remaining => Ok(UpdateDeadline::Continue(ticks(remaining))),
+        });
+
+        store
     }
 
     pub async fn scan(&self, preload: bool) -> io::Result<()> {
-        let seen = self.walk_filesystem(preload).await?;
+        let seen = self.walk_filesystem().await?;
+
+        if preload {
+            for path in seen.iter().filter(|path| is_script(path)) {
+                self.load(path).await?;
+            }
+        }
 
         self.purge(seen);
 
         Ok(())
     }
 
-    async fn walk_filesystem(&self, preload: bool) -> io::Result<HashSet<PathBuf>> {
+    /// Watches the CGI bin directory, evicting modules from the cache as soon as their files change.
+    /// With preloading, changed modules are compiled again right away instead of on their next request.
+    /// Returns `None` when the cache is disabled, since every load reads the module from disk.
+    pub fn watch(&self, preload: bool) -> notify::Result<Option<RecommendedWatcher>> {
+        if self.cache.is_none() {
+            return Ok(None);
+        }
+
+        let loader = self.clone();
+        let runtime = tokio::runtime::Handle::current();
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+            let event = match event {
+                Ok(event) => event,
+                Err(e) => {
+                    loader.error_log.write(
+                        None,
+                        None,
+                        format_args!("Encountered an error watching the CGI bin directory: {e}"),
+                    );
+                    return;
+                }
+            };
+
+            if let EventKind::Access(_) = event.kind {
+                return;
+            }
+
+            for path in event.paths {
+                loader.evict(&path);
+
+                if preload && is_script(&path) && path.is_file() {
+                    let loader = loader.clone();
+
+                    runtime.spawn(async move {
+                        if let Err(e) = loader.load(&path).await {
+                            loader.error_log.write(
+                                None,
+                                None,
+                                format_args!("Unable to reload {}: {e}", path.display()),
+                            );
+                        }
+                    });
+                }
+            }
+        })?;
+
+        watcher.watch(&self.root, RecursiveMode::Recursive)?;
+
+        Ok(Some(watcher))
+    }
+
+    /// Precompiles every module and component in the CGI bin directory into a `.cwasm` artifact where the loader looks for it.
+    /// Each script is validated against the imports it is linked with before it is written.
+    pub async fn compile(&self) -> io::Result<Vec<Compiled>> {
+        let mut modules: Vec<PathBuf> = self
+            .walk_filesystem()
+            .await?
+            .into_iter()
+            .filter(|path| is_script(path))
+            .collect();
+
+        modules.sort();
+
+        Ok(modules
+            .into_iter()
+            .map(|module| {
+                let artifact = self.artifact_path(&module);
+                let result = self
+                    .precompile(&module, &artifact)
+                    .map(|_| artifact)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{e:#}")));
+
+                Compiled { module, result }
+            })
+            .collect())
+    }
+
+    async fn walk_filesystem(&self) -> io::Result<HashSet<PathBuf>> {
         let mut queue = VecDeque::new();
         let mut seen = HashSet::new();
 
@@ -68,8 +283,6 @@ impl ModuleLoader {
 
                     queue.push_back(path)
                 }
-            } else if preload && next.extension() == Some("wcgi".as_ref()) {
-                self.load(&next).await?;
             }
 
             seen.insert(next.clone());
@@ -79,54 +292,537 @@ impl ModuleLoader {
     }
 
     fn purge(&self, seen: HashSet<PathBuf>) {
+        self.purge_where(|path| seen.contains(path));
+    }
+
+    /// Evicts the module at the path, or every module under it for a directory.
+    fn evict(&self, path: &Path) {
+        self.purge_where(|cached| !cached.starts_with(path));
+    }
+
+    fn purge_where(&self, retain: impl Fn(&Path) -> bool) {
         if let Some(lock) = self.cache.as_ref() {
-            let mut cache = lock.write().unwrap_or_else(|e| {
-                let mut guard = e.into_inner();
-                *guard = HashMap::new();
-                guard
-            });
+            let mut cache = write_cache(lock);
+            let before = cache.len();
 
-            cache.retain(|path, _| seen.contains(path));
+            cache.retain(|path, _| retain(path));
+            self.metrics.cache_evictions((before - cache.len()) as u64);
         }
     }
 
-    pub async fn load(&self, path: &PathBuf) -> io::Result<InstancePre<WasiCtx>> {
-        match self.cache.as_ref() {
-            None => self.load_from_file(path),
-            Some(lock) => match self.get_cache_entry(path) {
-                None => {
-                    let mut cache = lock.write().unwrap_or_else(|e| {
-                        let mut guard = e.into_inner();
-                        *guard = HashMap::new();
-                        guard
-                    });
+    pub async fn load(&self, path: &Path) -> io::Result<Loaded> {
+        let Some(lock) = self.cache.as_ref() else {
+            return self.load_from_file(path);
+        };
+        let fingerprint = Fingerprint::of(path)?;
 
-                    match cache.entry(path.clone()) {
-                        Entry::Occupied(entry) => Ok(entry.get().clone()),
-                        Entry::Vacant(entry) => {
-                            Ok(entry.insert(self.load_from_file(path)?).clone())
-                        }
-                    }
-                }
-                Some(module) => Ok(module),
-            },
+        if let Some(cached) = self.get_cache_entry(path) {
+            if cached.fingerprint == fingerprint {
+                self.metrics.cache_hit();
+                return Ok(cached.instance);
+            }
+        }
+
+        let bytes = std::fs::read(path)?;
+        let digest: [u8; 32] = Sha256::digest(&bytes).into();
+        let mut cache = write_cache(lock);
+
+        // The file may have been touched or rewritten without changing its contents.
+        if let Some(cached) = cache.get_mut(path) {
+            if cached.digest == digest {
+                cached.fingerprint = fingerprint;
+                self.metrics.cache_hit();
+                return Ok(cached.instance.clone());
+            }
+        }
+
+        if cache.remove(path).is_some() {
+            self.metrics.cache_evictions(1);
         }
+
+        self.metrics.cache_miss();
+
+        let instance = self.instantiate(path, &bytes)?;
+
+        cache.insert(
+            path.to_path_buf(),
+            Cached {
+                instance: instance.clone(),
+                fingerprint,
+                digest,
+            },
+        );
+
+        Ok(instance)
     }
 
-    fn get_cache_entry(&self, path: &PathBuf) -> Option<InstancePre<WasiCtx>> {
+    fn get_cache_entry(&self, path: &Path) -> Option<Cached> {
         self.cache.as_ref()?.read().ok()?.get(path).cloned()
     }
 
-    fn load_from_file(&self, path: &PathBuf) -> io::Result<InstancePre<WasiCtx>> {
-        let module = Module::from_file(&self.engine, path)
-            .map_err(|_| io::Error::from(io::ErrorKind::NotFound))?;
+    fn load_from_file(&self, path: &Path) -> io::Result<Loaded> {
+        let bytes = std::fs::read(path)?;
+
+        self.instantiate(path, &bytes)
+    }
+
+    fn instantiate(&self, path: &Path, bytes: &[u8]) -> io::Result<Loaded> {
+        if is_component(path) {
+            let component = self.load_or_compile(path, bytes)?;
+
+            return self
+                .link_component(&component)
+                .map(Loaded::Component)
+                .map_err(io::Error::other);
+        }
+
+        let module = self.load_or_compile(path, bytes)?;
+
+        self.link(&module)
+            .map(Loaded::Module)
+            .map_err(io::Error::other)
+    }
+
+    /// Loads the script's precompiled artifact, or compiles the script.
+    /// Scripts that fail to compile are reported in the error log, since the response only says the script is invalid.
+    fn load_or_compile<A: Artifact>(&self, path: &Path, bytes: &[u8]) -> io::Result<A> {
+        if let Some(artifact) = self.load_precompiled(path, bytes) {
+            return Ok(artifact);
+        }
+
+        self.compile_artifact(bytes).map_err(|e| {
+            self.error_log.write(
+                None,
+                None,
+                format_args!(
+                    "Unable to compile the WebAssembly script {}: {e:#}",
+                    path.display()
+                ),
+            );
+
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "The WebAssembly script could not be compiled.",
+            )
+        })
+    }
+
+    /// The path of a script's precompiled artifact, either under the artifact directory
+    /// at the script's path relative to the CGI bin directory, or next to the script.
+    fn artifact_path(&self, script: &Path) -> PathBuf {
+        match &self.artifacts {
+            Some(artifacts) => {
+                artifact_path(&artifacts.join(script.strip_prefix(&self.root).unwrap_or(script)))
+            }
+            None => artifact_path(script),
+        }
+    }
+
+    /// Loads the script's `.cwasm` artifact, unless it was compiled from different contents.
+    /// The artifact's digest file is checked first, since a stale artifact would be loaded as native code.
+    fn load_precompiled<A: Artifact>(&self, path: &Path, bytes: &[u8]) -> Option<A> {
+        let artifact = self.artifact_path(path);
+        let digest = std::fs::read_to_string(digest_path(&artifact)).ok()?;
+
+        if digest.trim() != hex(&Sha256::digest(bytes)) {
+            return None;
+        }
+
+        // SAFETY: artifacts in the CGI bin or artifact directory are as trusted as the scripts themselves.
+        match unsafe { A::deserialize_file(&self.engine, &artifact) } {
+            Ok(loaded) => Some(loaded),
+            Err(e) => {
+                self.error_log.write(
+                    None,
+                    None,
+                    format_args!(
+                        "Unable to load the precompiled WebAssembly module {}: {e}",
+                        artifact.display()
+                    ),
+                );
+                None
+            }
+        }
+    }
+
+    fn compile_artifact<A: Artifact>(&self, bytes: &[u8]) -> wasmtime::Result<A> {
+        match self.disk_cache.as_ref() {
+            Some(disk_cache) => disk_cache.load(&self.engine, bytes),
+            None => A::compile(&self.engine, bytes),
+        }
+    }
+
+    fn link(&self, module: &Module) -> wasmtime::Result<InstancePre<State>> {
         let mut linker = Linker::new(&self.engine);
 
-        wasmtime_wasi::add_to_linker(&mut linker, |s| s)
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        module::add_to_linker(&mut linker)?;
+
+        linker.instantiate_pre(module)
+    }
+
+    fn link_component(
+        &self,
+        component: &Component,
+    ) -> wasmtime::Result<component::InstancePre<State<HttpCtx>>> {
+        let mut linker = component::Linker::new(&self.engine);
+
+        http::add_to_linker(&mut linker)?;
+
+        linker.instantiate_pre(component)
+    }
+
+    fn precompile(&self, script: &Path, artifact: &Path) -> wasmtime::Result<()> {
+        let bytes = std::fs::read(script)?;
+        let serialized = if is_component(script) {
+            let compiled = Component::new(&self.engine, &bytes)?;
+
+            self.link_component(&compiled)?;
+            compiled.serialize()?
+        } else {
+            let compiled = Module::new(&self.engine, &bytes)?;
+
+            self.link(&compiled)?;
+            compiled.serialize()?
+        };
+
+        if let Some(parent) = artifact.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        std::fs::write(artifact, serialized)?;
+        std::fs::write(digest_path(artifact), hex(&Sha256::digest(&bytes)))?;
+
+        Ok(())
+    }
+}
+
+/// The path of a script's precompiled artifact, which keeps the script's extension (e.g., `echo.wcgi.cwasm`)
+/// so a module and a component with the same stem never share an artifact.
+fn artifact_path(script: &Path) -> PathBuf {
+    let mut path = script.as_os_str().to_owned();
+
+    path.push(".cwasm");
+    path.into()
+}
+
+/// The path of the file recording the SHA-256 digest of the script an artifact was compiled from (e.g., `echo.wcgi.cwasm.sha256`).
+fn digest_path(artifact: &Path) -> PathBuf {
+    let mut path = artifact.as_os_str().to_owned();
+
+    path.push(".sha256");
+    path.into()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// The outcome of precompiling a WebAssembly script.
+pub struct Compiled {
+    /// The path of the `.wcgi` module or `.whttp` component.
+    pub module: PathBuf,
+    /// The path of the `.cwasm` artifact, or the reason the module could not be compiled.
+    pub result: io::Result<PathBuf>,
+}
+
+/// Precompiles the WebAssembly scripts in the CGI bin directory with the engine configuration of a server with the given options.
+/// Artifacts are written under the output directory when one is given, then the `wasm_artifacts` directory,
+/// or else next to their modules. A server with the same `wasm_artifacts` loads them instead of compiling the modules itself.
+pub async fn compile(mut options: Options, output: Option<PathBuf>) -> io::Result<Vec<Compiled>> {
+    options.resolve_paths()?;
+    options.wasm_artifacts = output.or(options.wasm_artifacts);
+
+    let loader = ModuleLoader::new(&options, Default::default(), Default::default())
+        .map_err(io::Error::other)?;
+
+    loader.compile().await
+}
+
+/// Whether instantiating or calling a script failed because a pool of the pooling allocator was exhausted.
+/// Wasmtime only describes these failures in the error message.
+pub fn is_pool_exhausted(error: &wasmtime::Error) -> bool {
+    let message = error.root_cause().to_string();
+
+    message.starts_with("maximum concurrent ") && message.contains(" limit of ")
+}
+
+/// The number of epoch ticks before a duration has passed, rounded up.
+fn ticks(duration: Duration) -> u64 {
+    let ticks = duration.as_nanos().div_ceil(EPOCH_TICK.as_nanos());
+
+    u64::try_from(ticks).unwrap_or(u64::MAX)
+}
+
+/// Advances the epoch of the engine on a background thread until the returned guard is dropped.
+fn start_ticker(engine: Engine) -> io::Result<Arc<()>> {
+    let guard = Arc::new(());
+    let alive = Arc::downgrade(&guard);
+
+    std::thread::Builder::new()
+        .name("wasm-epoch".to_string())
+        .spawn(move || {
+            while alive.strong_count() > 0 {
+                std::thread::sleep(EPOCH_TICK);
+                engine.increment_epoch();
+            }
+        })?;
+
+    Ok(guard)
+}
+
+/// Clears the cache if a thread panicked while holding the lock, since its entries may be inconsistent.
+fn write_cache(
+    lock: &RwLock<HashMap<PathBuf, Cached>>,
+) -> RwLockWriteGuard<'_, HashMap<PathBuf, Cached>> {
+    lock.write().unwrap_or_else(|e| {
+        let mut guard = e.into_inner();
+        *guard = HashMap::new();
+        guard
+    })
+}
+
+fn is_script(path: &Path) -> bool {
+    path.extension() == Some("wcgi".as_ref()) || is_component(path)
+}
+
+fn is_component(path: &Path) -> bool {
+    path.extension() == Some("whttp".as_ref())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::WasmPooling;
+
+    #[tokio::test]
+    async fn precompiled() {
+        let directory = tempfile::tempdir().unwrap();
+        let root = directory.path().to_path_buf();
+
+        std::fs::create_dir(root.join("nested")).unwrap();
+        std::fs::copy("examples/echo.wcgi", root.join("nested/echo.wcgi")).unwrap();
+        std::fs::copy("examples/echo.wcgi", root.join("echo.wcgi")).unwrap();
+        std::fs::copy("examples/echo.whttp", root.join("echo.whttp")).unwrap();
+        std::fs::write(
+            root.join("invalid.wcgi"),
+            "(module (import \"env\" \"f\" (func)))",
+        )
+        .unwrap();
+
+        let loader = loader(&root, false);
+        let compiled = loader.compile().await.unwrap();
+
+        assert_eq!(compiled.len(), 4);
+        assert_eq!(compiled[0].module, root.join("echo.wcgi"));
+        assert_eq!(
+            compiled[0].result.as_ref().unwrap(),
+            &root.join("echo.wcgi.cwasm")
+        );
+        assert_eq!(compiled[1].module, root.join("echo.whttp"));
+        assert_eq!(
+            compiled[1].result.as_ref().unwrap(),
+            &root.join("echo.whttp.cwasm")
+        );
+        assert_eq!(compiled[2].module, root.join("invalid.wcgi"));
+        assert!(compiled[2].result.is_err());
+        assert_eq!(compiled[3].module, root.join("nested/echo.wcgi"));
+        assert_eq!(
+            compiled[3].result.as_ref().unwrap(),
+            &root.join("nested/echo.wcgi.cwasm")
+        );
+
+        let echo = std::fs::read(root.join("echo.wcgi")).unwrap();
+        let invalid = std::fs::read(root.join("invalid.wcgi")).unwrap();
+
+        assert!(loader
+            .load_precompiled::<Module>(&root.join("echo.wcgi"), &echo)
+            .is_some());
+        assert!(loader
+            .load_precompiled::<Module>(&root.join("nested/echo.wcgi"), &echo)
+            .is_some());
+        assert!(loader
+            .load_precompiled::<Module>(&root.join("invalid.wcgi"), &invalid)
+            .is_none());
+        // The artifact is ignored once the script changes, even if the artifact is newer.
+        assert!(loader
+            .load_precompiled::<Module>(&root.join("echo.wcgi"), &invalid)
+            .is_none());
+        assert!(matches!(
+            loader.load(&root.join("echo.whttp")).await.unwrap(),
+            Loaded::Component(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn artifact_directory() {
+        let directory = tempfile::tempdir().unwrap();
+        let artifacts = tempfile::tempdir().unwrap();
+        let root = directory.path().to_path_buf();
+
+        std::fs::create_dir(root.join("nested")).unwrap();
+        std::fs::copy("examples/echo.wcgi", root.join("nested/echo.wcgi")).unwrap();
+
+        let options = Options {
+            document_root: root.clone(),
+            wasm_artifacts: Some(artifacts.path().into()),
+            ..Default::default()
+        };
+        let compiled = compile(options.clone(), None).await.unwrap();
+        let root = root.canonicalize().unwrap();
+        let echo = std::fs::read(root.join("nested/echo.wcgi")).unwrap();
+
+        assert_eq!(
+            compiled[0].result.as_ref().unwrap(),
+            &artifacts.path().join("nested/echo.wcgi.cwasm")
+        );
+        assert!(!root.join("nested/echo.wcgi.cwasm").exists());
+
+        let loader = ModuleLoader::new(
+            &Options {
+                cgi_bin: root.clone(),
+                ..options
+            },
+            Default::default(),
+            Default::default(),
+        )
+        .unwrap();
+
+        assert!(loader
+            .load_precompiled::<Module>(&root.join("nested/echo.wcgi"), &echo)
+            .is_some());
+        assert!(self::loader(&root, false)
+            .load_precompiled::<Module>(&root.join("nested/echo.wcgi"), &echo)
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn modified() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("module.wcgi");
+        let loader = loader(directory.path(), true);
+
+        std::fs::write(&path, "(module (func (export \"before\")))").unwrap();
+
+        let before = module(loader.load(&path).await.unwrap());
+
+        std::fs::write(&path, "(module (func (export \"after\")))").unwrap();
+
+        let after = module(loader.load(&path).await.unwrap());
+
+        assert!(before.module().get_export("before").is_some());
+        assert!(after.module().get_export("after").is_some());
+        assert!(loader
+            .metrics
+            .render()
+            .contains("tortuga_wasm_cache_evictions_total 1\n"));
+    }
+
+    #[tokio::test]
+    async fn watch() {
+        let directory = tempfile::tempdir().unwrap();
+        let root = directory.path().canonicalize().unwrap();
+        let path = root.join("module.wcgi");
+        let loader = loader(&root, true);
+
+        std::fs::write(&path, "(module)").unwrap();
+        loader.load(&path).await.unwrap();
+
+        let _watcher = loader.watch(false).unwrap();
+
+        std::fs::remove_file(&path).unwrap();
+
+        for _ in 0..100 {
+            if loader.get_cache_entry(&path).is_none() {
+                return;
+            }
+
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+
+        panic!("The module was not evicted after its file was removed.");
+    }
+
+    #[tokio::test]
+    async fn pooling() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("module.wcgi");
+        let options = Options {
+            cgi_bin: directory.path().into(),
+            wasm_pooling: Some(WasmPooling {
+                instances: 2,
+                memory_bytes: PAGE_BYTES * 4,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let loader = ModuleLoader::new(&options, Default::default(), Default::default()).unwrap();
+
+        std::fs::write(&path, "(module (memory 1) (func (export \"_start\")))").unwrap();
+
+        let instance = module(loader.load(&path).await.unwrap());
+        let state = State {
+            wasi: ModuleCtx::new(wasmtime_wasi::preview2::WasiCtxBuilder::new().build()),
+            limiter: Limiter::default(),
+        };
+        let deadline = Deadline::new(Duration::from_secs(1));
+        let mut store = loader.new_store(state, Arc::new(deadline));
+
+        instance.instantiate_async(&mut store).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn component_pooling() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("echo.whttp");
+        let options = Options {
+            cgi_bin: directory.path().into(),
+            wasm_pooling: Some(WasmPooling {
+                instances: 2,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let loader = ModuleLoader::new(&options, Default::default(), Default::default()).unwrap();
+
+        std::fs::copy("examples/echo.whttp", &path).unwrap();
+
+        let Loaded::Component(component) = loader.load(&path).await.unwrap() else {
+            panic!("Expected a component.");
+        };
+        let mut stores = Vec::new();
+
+        // Each component uses several core instances, so the pools must fit all of them for every instance.
+        for _ in 0..3 {
+            let state = State {
+                wasi: HttpCtx::new(wasmtime_wasi::preview2::WasiCtxBuilder::new().build()),
+                limiter: Limiter::default(),
+            };
+            let deadline = Deadline::new(Duration::from_secs(1));
+            let mut store = loader.new_store(state, Arc::new(deadline));
+            let result = component.instantiate_async(&mut store).await;
+
+            stores.push((store, result));
+        }
+
+        assert!(stores[0].1.is_ok());
+        assert!(stores[1].1.is_ok());
+        assert!(matches!(&stores[2].1, Err(e) if is_pool_exhausted(e)));
+    }
+
+    fn module(loaded: Loaded) -> InstancePre<State> {
+        match loaded {
+            Loaded::Module(module) => module,
+            Loaded::Component(_) => panic!("Expected a core module."),
+        }
+    }
+
+    fn loader(root: &Path, cache: bool) -> ModuleLoader {
+        let options = Options {
+            cgi_bin: root.into(),
+            wasm_cache: cache,
+            ..Default::default()
+        };
 
-        linker
-            .instantiate_pre(&module)
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+        ModuleLoader::new(&options, Default::default(), Default::default()).unwrap()
     }
 }
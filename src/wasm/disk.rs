@@ -0,0 +1,191 @@
+use crate::server::ErrorLog;
+use sha2::{Digest, Sha256};
+use std::collections::hash_map::DefaultHasher;
+use std::fmt::Write as _;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use wasmtime::component::Component;
+use wasmtime::{Engine, Module};
+
+/// A compiled core module or component that can be serialized to disk.
+pub trait Artifact: Sized {
+    fn compile(engine: &Engine, bytes: &[u8]) -> wasmtime::Result<Self>;
+
+    /// # Safety
+    ///
+    /// The file must contain an artifact serialized by a compatible engine.
+    unsafe fn deserialize_file(engine: &Engine, path: &Path) -> wasmtime::Result<Self>;
+
+    fn serialize(&self) -> wasmtime::Result<Vec<u8>>;
+}
+
+impl Artifact for Module {
+    fn compile(engine: &Engine, bytes: &[u8]) -> wasmtime::Result<Self> {
+        Module::new(engine, bytes)
+    }
+
+    unsafe fn deserialize_file(engine: &Engine, path: &Path) -> wasmtime::Result<Self> {
+        Module::deserialize_file(engine, path)
+    }
+
+    fn serialize(&self) -> wasmtime::Result<Vec<u8>> {
+        Module::serialize(self)
+    }
+}
+
+impl Artifact for Component {
+    fn compile(engine: &Engine, bytes: &[u8]) -> wasmtime::Result<Self> {
+        Component::new(engine, bytes)
+    }
+
+    unsafe fn deserialize_file(engine: &Engine, path: &Path) -> wasmtime::Result<Self> {
+        Component::deserialize_file(engine, path)
+    }
+
+    fn serialize(&self) -> wasmtime::Result<Vec<u8>> {
+        Component::serialize(self)
+    }
+}
+
+/// A content-addressed directory of serialized modules, so modules are only compiled once across restarts.
+///
+/// Artifacts are named by the SHA-256 digest of the module's bytes and a hash of the engine's
+/// configuration, so an artifact is never loaded into an engine it was not compiled for.
+/// The directory must only be writable by trusted users, since artifacts are loaded as native code.
+#[derive(Clone)]
+pub struct DiskCache {
+    directory: PathBuf,
+    engine_hash: u64,
+    error_log: Arc<ErrorLog>,
+}
+
+impl DiskCache {
+    pub fn new(directory: PathBuf, engine: &Engine, error_log: Arc<ErrorLog>) -> io::Result<Self> {
+        std::fs::create_dir_all(&directory)?;
+
+        let mut hasher = DefaultHasher::new();
+
+        engine.precompile_compatibility_hash().hash(&mut hasher);
+
+        Ok(Self {
+            directory,
+            engine_hash: hasher.finish(),
+            error_log,
+        })
+    }
+
+    /// Loads the serialized module or component for the given bytes, compiling and storing it when missing or unusable.
+    pub fn load<A: Artifact>(&self, engine: &Engine, bytes: &[u8]) -> wasmtime::Result<A> {
+        let path = self.artifact(bytes);
+
+        if path.is_file() {
+            // SAFETY: the artifact was serialized by an engine with a compatible configuration,
+            // and the cache directory is trusted not to be written to by anyone else.
+            match unsafe { A::deserialize_file(engine, &path) } {
+                Ok(module) => return Ok(module),
+                Err(e) => self.error_log.write(
+                    None,
+                    None,
+                    format_args!(
+                        "Unable to load the cached WebAssembly module {}: {e}",
+                        path.display()
+                    ),
+                ),
+            }
+        }
+
+        let artifact = A::compile(engine, bytes)?;
+
+        if let Err(e) = self.store(&path, &artifact) {
+            self.error_log.write(
+                None,
+                None,
+                format_args!(
+                    "Unable to write the cached WebAssembly module {}: {e}",
+                    path.display()
+                ),
+            );
+        }
+
+        Ok(artifact)
+    }
+
+    fn artifact(&self, bytes: &[u8]) -> PathBuf {
+        let digest = Sha256::digest(bytes);
+        let mut name = String::with_capacity(digest.len() * 2 + 23);
+
+        for byte in digest {
+            let _ = write!(name, "{byte:02x}");
+        }
+
+        let _ = write!(name, "-{:016x}.cwasm", self.engine_hash);
+
+        self.directory.join(name)
+    }
+
+    /// Writes the artifact to a temporary file first, so concurrent readers never see a partial artifact.
+    fn store(&self, path: &Path, artifact: &impl Artifact) -> wasmtime::Result<()> {
+        let serialized = artifact.serialize()?;
+        let mut file = tempfile::NamedTempFile::new_in(&self.directory)?;
+
+        file.write_all(&serialized)?;
+        file.persist(path)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasmtime::Config;
+
+    #[test]
+    fn round_trip() {
+        let directory = tempfile::tempdir().unwrap();
+        let engine = Engine::new(&Config::new()).unwrap();
+        let cache =
+            DiskCache::new(directory.path().to_path_buf(), &engine, Default::default()).unwrap();
+        let bytes = wat_module();
+
+        cache.load::<Module>(&engine, &bytes).unwrap();
+
+        let artifacts: Vec<_> = std::fs::read_dir(directory.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+
+        assert_eq!(artifacts, vec![cache.artifact(&bytes)]);
+
+        let module: Module = cache.load(&engine, &bytes).unwrap();
+
+        assert!(module.get_export("answer").is_some());
+    }
+
+    #[test]
+    fn engine_configuration() {
+        let directory = tempfile::tempdir().unwrap();
+        let engine = Engine::new(&Config::new()).unwrap();
+        let fuel = Engine::new(Config::new().consume_fuel(true)).unwrap();
+        let cache =
+            DiskCache::new(directory.path().to_path_buf(), &engine, Default::default()).unwrap();
+        let fuel_cache =
+            DiskCache::new(directory.path().to_path_buf(), &fuel, Default::default()).unwrap();
+
+        assert_ne!(
+            cache.artifact(&wat_module()),
+            fuel_cache.artifact(&wat_module())
+        );
+    }
+
+    /// The binary encoding of `(module (func (export "answer") (result i32) i32.const 42))`.
+    fn wat_module() -> Vec<u8> {
+        vec![
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x05, 0x01, 0x60, 0x00, 0x01,
+            0x7f, 0x03, 0x02, 0x01, 0x00, 0x07, 0x0a, 0x01, 0x06, 0x61, 0x6e, 0x73, 0x77, 0x65,
+            0x72, 0x00, 0x00, 0x0a, 0x06, 0x01, 0x04, 0x00, 0x41, 0x2a, 0x0b,
+        ]
+    }
+}
@@ -0,0 +1,108 @@
+use crate::context::RequestContext;
+use crate::wasm::State;
+use bytes::Bytes;
+use http::uri::{Authority, Scheme};
+use http::{Request, Uri};
+use http_body_util::{BodyExt, Full};
+use std::io;
+use wasmtime::component::{Linker, Resource, ResourceTable};
+use wasmtime_wasi::preview2::{self, WasiCtx, WasiView};
+use wasmtime_wasi_http::bindings::http::types::ErrorCode;
+use wasmtime_wasi_http::body::HyperIncomingBody;
+use wasmtime_wasi_http::types::{HostFutureIncomingResponse, OutgoingRequest};
+use wasmtime_wasi_http::{WasiHttpCtx, WasiHttpView};
+
+/// The WASI context of a component handling an HTTP request.
+pub struct HttpCtx {
+    pub table: ResourceTable,
+    pub wasi: WasiCtx,
+    pub http: WasiHttpCtx,
+}
+
+impl HttpCtx {
+    pub fn new(wasi: WasiCtx) -> Self {
+        Self {
+            table: ResourceTable::new(),
+            wasi,
+            http: WasiHttpCtx,
+        }
+    }
+}
+
+impl WasiView for State<HttpCtx> {
+    fn table(&self) -> &ResourceTable {
+        &self.wasi.table
+    }
+
+    fn table_mut(&mut self) -> &mut ResourceTable {
+        &mut self.wasi.table
+    }
+
+    fn ctx(&self) -> &WasiCtx {
+        &self.wasi.wasi
+    }
+
+    fn ctx_mut(&mut self) -> &mut WasiCtx {
+        &mut self.wasi.wasi
+    }
+}
+
+impl WasiHttpView for State<HttpCtx> {
+    fn ctx(&mut self) -> &mut WasiHttpCtx {
+        &mut self.wasi.http
+    }
+
+    fn table(&mut self) -> &mut ResourceTable {
+        &mut self.wasi.table
+    }
+
+    /// Scripts may not make outgoing requests, just as CGI scripts run as modules have no network access.
+    fn send_request(
+        &mut self,
+        _request: OutgoingRequest,
+    ) -> wasmtime::Result<Resource<HostFutureIncomingResponse>> {
+        let denied = preview2::spawn(async { Ok(Err(ErrorCode::HttpRequestDenied)) });
+
+        Ok(WasiHttpView::table(self).push(HostFutureIncomingResponse::new(denied))?)
+    }
+}
+
+/// Links the `wasi:http/proxy` world, along with the rest of WASI that components built from
+/// the standard library import regardless of whether they use it.
+pub fn add_to_linker(linker: &mut Linker<State<HttpCtx>>) -> wasmtime::Result<()> {
+    preview2::command::add_to_linker(linker)?;
+    wasmtime_wasi_http::proxy::add_only_http_to_linker(linker)
+}
+
+/// Translates the request into the form a component receives as its `incoming-request`.
+/// The URI is made absolute, since components read the scheme and authority from it.
+pub fn incoming_request(
+    context: &RequestContext,
+    body: Bytes,
+) -> io::Result<Request<HyperIncomingBody>> {
+    let head = context.request();
+    let server = context.server();
+    let authority = match head.uri().authority() {
+        Some(authority) => authority.clone(),
+        None => match head.headers().get(http::header::HOST) {
+            Some(host) => Authority::try_from(host.as_bytes()),
+            None => Authority::try_from(format!("{}:{}", server.server_name(), server.port())),
+        }
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?,
+    };
+    let uri = Uri::builder()
+        .scheme(Scheme::try_from(server.scheme()).map_err(io::Error::other)?)
+        .authority(authority)
+        .path_and_query(head.uri().path_and_query().map_or("/", |p| p.as_str()))
+        .build()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    let mut request = Request::new(Full::new(body).map_err(|never| match never {}).boxed());
+
+    *request.method_mut() = head.method().clone();
+    *request.uri_mut() = uri;
+    *request.version_mut() = head.version();
+    *request.headers_mut() = head.headers().clone();
+
+    Ok(request)
+}
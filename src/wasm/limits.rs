@@ -0,0 +1,91 @@
+use std::fmt;
+use wasmtime::ResourceLimiter;
+
+/// Enforces the linear memory and table limits of a script.
+/// Growing past a limit is an error rather than a failed `memory.grow`, so the script is stopped.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Limiter {
+    max_memory_bytes: Option<u64>,
+    max_table_elements: Option<u64>,
+}
+
+impl Limiter {
+    pub fn new(max_memory_bytes: Option<u64>, max_table_elements: Option<u64>) -> Self {
+        Self {
+            max_memory_bytes,
+            max_table_elements,
+        }
+    }
+}
+
+impl ResourceLimiter for Limiter {
+    fn memory_growing(
+        &mut self,
+        _current: usize,
+        desired: usize,
+        _maximum: Option<usize>,
+    ) -> wasmtime::Result<bool> {
+        match self.max_memory_bytes {
+            Some(limit) if desired as u64 > limit => Err(LimitExceeded::Memory(limit).into()),
+            _ => Ok(true),
+        }
+    }
+
+    fn table_growing(
+        &mut self,
+        _current: u32,
+        desired: u32,
+        _maximum: Option<u32>,
+    ) -> wasmtime::Result<bool> {
+        match self.max_table_elements {
+            Some(limit) if u64::from(desired) > limit => Err(LimitExceeded::Table(limit).into()),
+            _ => Ok(true),
+        }
+    }
+}
+
+/// The limit a script tried to grow past.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LimitExceeded {
+    Memory(u64),
+    Table(u64),
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitExceeded::Memory(limit) => {
+                write!(f, "The script exceeded its memory limit of {limit} bytes.")
+            }
+            LimitExceeded::Table(limit) => {
+                write!(
+                    f,
+                    "The script exceeded its table limit of {limit} elements."
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits() {
+        let mut limiter = Limiter::new(Some(65536), None);
+
+        assert!(limiter.memory_growing(0, 65536, None).unwrap());
+        assert_eq!(
+            limiter
+                .memory_growing(65536, 131072, None)
+                .unwrap_err()
+                .downcast::<LimitExceeded>()
+                .unwrap(),
+            LimitExceeded::Memory(65536)
+        );
+        assert!(limiter.table_growing(0, u32::MAX, None).unwrap());
+    }
+}
@@ -0,0 +1,56 @@
+use crate::wasm::State;
+use wasmtime::component::ResourceTable;
+use wasmtime::Linker;
+use wasmtime_wasi::preview2::preview1::{self, WasiPreview1Adapter, WasiPreview1View};
+use wasmtime_wasi::preview2::{WasiCtx, WasiView};
+
+/// The WASI context of a core module run as a CGI script.
+/// Its preview1 calls are served by the preview2 implementation, whose streams may wait on the client.
+pub struct ModuleCtx {
+    pub table: ResourceTable,
+    pub wasi: WasiCtx,
+    pub adapter: WasiPreview1Adapter,
+}
+
+impl ModuleCtx {
+    pub fn new(wasi: WasiCtx) -> Self {
+        Self {
+            table: ResourceTable::new(),
+            wasi,
+            adapter: WasiPreview1Adapter::new(),
+        }
+    }
+}
+
+impl WasiView for State<ModuleCtx> {
+    fn table(&self) -> &ResourceTable {
+        &self.wasi.table
+    }
+
+    fn table_mut(&mut self) -> &mut ResourceTable {
+        &mut self.wasi.table
+    }
+
+    fn ctx(&self) -> &WasiCtx {
+        &self.wasi.wasi
+    }
+
+    fn ctx_mut(&mut self) -> &mut WasiCtx {
+        &mut self.wasi.wasi
+    }
+}
+
+impl WasiPreview1View for State<ModuleCtx> {
+    fn adapter(&self) -> &WasiPreview1Adapter {
+        &self.wasi.adapter
+    }
+
+    fn adapter_mut(&mut self) -> &mut WasiPreview1Adapter {
+        &mut self.wasi.adapter
+    }
+}
+
+/// Adds the `wasi_snapshot_preview1` functions a core module may import.
+pub fn add_to_linker(linker: &mut Linker<State<ModuleCtx>>) -> wasmtime::Result<()> {
+    preview1::add_to_linker_async(linker)
+}
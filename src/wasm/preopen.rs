@@ -0,0 +1,100 @@
+use crate::server::{DirectoryAccess, Options};
+use std::io;
+use std::path::Path;
+use tempfile::TempDir;
+use wasmtime_wasi::preview2::{DirPerms, FilePerms, WasiCtxBuilder};
+use wasmtime_wasi::{ambient_authority, Dir};
+
+/// A directory on the host made available to WebAssembly scripts at a path in the guest.
+struct Preopen {
+    guest: String,
+    directory: Dir,
+    access: DirectoryAccess,
+}
+
+/// The directories WebAssembly scripts may access through WASI.
+pub struct Preopens {
+    directories: Vec<Preopen>,
+    scratch: bool,
+}
+
+impl Preopens {
+    /// Opens the document root and any mapped directories, so missing directories are reported at startup.
+    pub fn new(options: &Options) -> io::Result<Self> {
+        let mut directories = Vec::new();
+        let document_root = (guest_path(&options.document_root)?, &options.document_root);
+        let mapped = options
+            .wasm_directories
+            .iter()
+            .map(|(guest, mapped)| (guest.clone(), &mapped.path, mapped.access));
+
+        for (guest, host, access) in
+            std::iter::once((document_root.0, document_root.1, options.wasm_document_root))
+                .chain(mapped)
+        {
+            if access == DirectoryAccess::None {
+                continue;
+            }
+
+            let directory = Dir::open_ambient_dir(host, ambient_authority())
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", host.display())))?;
+
+            directories.push(Preopen {
+                guest,
+                directory,
+                access,
+            });
+        }
+
+        Ok(Self {
+            directories,
+            scratch: options.wasm_scratch_directory,
+        })
+    }
+
+    /// Makes the directories available to the script, along with a new scratch directory at `/tmp` if enabled.
+    /// Directories without read-write access only allow reading files and listing directories.
+    /// The scratch directory is removed once the returned value is dropped.
+    pub fn apply(&self, builder: &mut WasiCtxBuilder) -> io::Result<Option<TempDir>> {
+        self.open(|directory, guest, access| {
+            let (directories, files) = match access {
+                DirectoryAccess::ReadWrite => (DirPerms::all(), FilePerms::all()),
+                _ => (DirPerms::READ, FilePerms::READ),
+            };
+
+            builder.preopened_dir(directory, directories, files, guest);
+        })
+    }
+
+    fn open(
+        &self,
+        mut push: impl FnMut(Dir, &str, DirectoryAccess),
+    ) -> io::Result<Option<TempDir>> {
+        for preopen in &self.directories {
+            let directory = preopen.directory.try_clone()?;
+
+            push(directory, &preopen.guest, preopen.access);
+        }
+
+        if !self.scratch {
+            return Ok(None);
+        }
+
+        let scratch = tempfile::tempdir()?;
+        let directory = Dir::open_ambient_dir(scratch.path(), ambient_authority())?;
+
+        push(directory, "/tmp", DirectoryAccess::ReadWrite);
+
+        Ok(Some(scratch))
+    }
+}
+
+/// The path of a host directory as seen by the guest, which must be valid UTF-8 for WASI.
+fn guest_path(path: &Path) -> io::Result<String> {
+    path.to_str().map(String::from).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{}: path is not valid UTF-8", path.display()),
+        )
+    })
+}